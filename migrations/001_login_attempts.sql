@@ -0,0 +1,23 @@
+-- Brute-force protection for /login and /signup.
+-- Every login attempt (successful or not) is recorded so we can apply
+-- per-username and per-IP backoff. Rows older than a few days are useless
+-- and may be pruned at any time.
+CREATE TABLE IF NOT EXISTS login_attempts (
+    id BIGSERIAL PRIMARY KEY,
+    username TEXT NOT NULL,
+    ip_address INET NOT NULL,
+    success BOOLEAN NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS login_attempts_username_idx
+    ON login_attempts (username, attempted_at DESC);
+CREATE INDEX IF NOT EXISTS login_attempts_ip_idx
+    ON login_attempts (ip_address, attempted_at DESC);
+
+-- Needed to rate limit signups per signup_ip. Existing accounts keep NULL, their signup
+-- time is unknown and they must not count as fresh signups of their IP.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS created_at TIMESTAMPTZ;
+ALTER TABLE users ALTER COLUMN created_at SET DEFAULT NOW();
+CREATE INDEX IF NOT EXISTS users_signup_ip_created_idx
+    ON users (signup_ip, created_at DESC);
@@ -0,0 +1,9 @@
+-- Granular roles for deck collaborators. Existing maintainers keep their
+-- current rights (review + approve + view stats).
+ALTER TABLE maintainers
+    ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'maintainer';
+
+ALTER TABLE maintainers DROP CONSTRAINT IF EXISTS maintainers_role_check;
+ALTER TABLE maintainers
+    ADD CONSTRAINT maintainers_role_check
+    CHECK (role IN ('reviewer', 'maintainer', 'editor', 'manager'));
@@ -0,0 +1,12 @@
+-- Username changes. Commits, note_events and notifications reference users by id and
+-- keep their attribution; the hashed username in note_stats/subscriptions is rewritten
+-- by the rename itself.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS username_changed_at TIMESTAMPTZ;
+
+-- Old usernames stay blocked for a while so nobody can pick them up and impersonate
+-- the previous owner. The previous owner may reclaim it.
+CREATE TABLE IF NOT EXISTS reserved_usernames (
+    username TEXT PRIMARY KEY,
+    user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    reserved_until TIMESTAMPTZ NOT NULL
+);
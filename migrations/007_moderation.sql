@@ -0,0 +1,25 @@
+-- Abuse reports for public decks and notes, worked through in the admin moderation queue.
+CREATE TABLE IF NOT EXISTS content_reports (
+    id BIGSERIAL PRIMARY KEY,
+    reporter_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    deck_id BIGINT NOT NULL REFERENCES decks(id) ON DELETE CASCADE,
+    note_id BIGINT REFERENCES notes(id) ON DELETE CASCADE,
+    category TEXT NOT NULL CHECK (category IN ('copyright', 'harmful', 'spam', 'other')),
+    comment TEXT NOT NULL DEFAULT '',
+    status TEXT NOT NULL DEFAULT 'open' CHECK (status IN ('open', 'hidden', 'suspended', 'dismissed')),
+    resolved_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    resolved_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS content_reports_status_idx ON content_reports (status, created_at DESC);
+CREATE INDEX IF NOT EXISTS content_reports_deck_idx ON content_reports (deck_id);
+
+-- Hidden decks are forced private; the owner cannot make them public again.
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS moderation_hidden BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Suspended users cannot log in.
+ALTER TABLE users ADD COLUMN IF NOT EXISTS suspended_at TIMESTAMPTZ;
+
+-- Notifications are no longer tied to commits only (e.g. report outcomes).
+ALTER TABLE notifications ALTER COLUMN commit_id DROP NOT NULL;
@@ -0,0 +1,16 @@
+-- Owner-initiated deck handovers. The recipient has to accept before ownership changes.
+CREATE TABLE IF NOT EXISTS deck_transfers (
+    id BIGSERIAL PRIMARY KEY,
+    deck_id BIGINT NOT NULL REFERENCES decks(id) ON DELETE CASCADE,
+    from_user INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    to_user INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    status TEXT NOT NULL DEFAULT 'pending'
+        CHECK (status IN ('pending', 'accepted', 'declined', 'cancelled')),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    resolved_at TIMESTAMPTZ
+);
+
+-- Only one open offer per deck
+CREATE UNIQUE INDEX IF NOT EXISTS deck_transfers_pending_idx
+    ON deck_transfers (deck_id) WHERE status = 'pending';
+CREATE INDEX IF NOT EXISTS deck_transfers_to_user_idx ON deck_transfers (to_user, status);
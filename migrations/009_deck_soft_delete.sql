@@ -0,0 +1,7 @@
+-- Deleting a deck only marks it (and its subdecks). A background job purges it for good
+-- once the restore window has passed.
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+-- Set once the owner was told about the upcoming purge so they are only notified once
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS purge_warned BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE INDEX IF NOT EXISTS decks_deleted_at_idx ON decks (deleted_at) WHERE deleted_at IS NOT NULL;
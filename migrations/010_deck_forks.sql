@@ -0,0 +1,3 @@
+-- A fork is an independent copy of another deck. Keep a link to the source for provenance.
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS forked_from BIGINT REFERENCES decks(id) ON DELETE SET NULL;
+CREATE INDEX IF NOT EXISTS decks_forked_from_idx ON decks (forked_from) WHERE forked_from IS NOT NULL;
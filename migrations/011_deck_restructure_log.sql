@@ -0,0 +1,17 @@
+-- Renames, moves and merges of subdecks done on the website. The add-on polls this to
+-- rebuild the deck tree of subscribers.
+CREATE TABLE IF NOT EXISTS deck_restructure_log (
+    id BIGSERIAL PRIMARY KEY,
+    root_deck BIGINT NOT NULL REFERENCES decks(id) ON DELETE CASCADE,
+    -- The merged deck is gone afterwards, so this can be NULL for merges
+    deck_id BIGINT REFERENCES decks(id) ON DELETE SET NULL,
+    deck_hash TEXT NOT NULL,
+    action TEXT NOT NULL CHECK (action IN ('rename', 'move', 'merge')),
+    old_path TEXT NOT NULL,
+    new_path TEXT NOT NULL,
+    merged_into BIGINT REFERENCES decks(id) ON DELETE SET NULL,
+    actor_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS deck_restructure_log_root_idx ON deck_restructure_log (root_deck, created_at);
@@ -0,0 +1,34 @@
+-- Decks can now be public, unlisted (reachable by hash but not listed) or private (owner,
+-- maintainers and invited users only). The "private" checkbox used to be labelled as
+-- unlisted and private decks were reachable by hash, so existing ones become unlisted.
+-- Decks hidden by moderation stay private.
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS unlisted BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE decks SET unlisted = TRUE, private = FALSE
+WHERE private AND NOT moderation_hidden AND NOT unlisted;
+
+-- Shareable links for private decks. Only a hash of the token is stored.
+CREATE TABLE IF NOT EXISTS deck_invites (
+    id BIGSERIAL PRIMARY KEY,
+    deck_id BIGINT NOT NULL REFERENCES decks(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    access TEXT NOT NULL CHECK (access IN ('view', 'subscribe')),
+    label TEXT NOT NULL DEFAULT '',
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    expires_at TIMESTAMPTZ,
+    max_uses INTEGER CHECK (max_uses > 0),
+    use_count INTEGER NOT NULL DEFAULT 0,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS deck_invites_deck_idx ON deck_invites (deck_id);
+
+-- Access handed out through an invite. Revoking the invite removes these again.
+CREATE TABLE IF NOT EXISTS deck_access_grants (
+    deck_id BIGINT NOT NULL REFERENCES decks(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    access TEXT NOT NULL CHECK (access IN ('view', 'subscribe')),
+    invite_id BIGINT REFERENCES deck_invites(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (deck_id, user_id)
+);
@@ -0,0 +1,18 @@
+-- Owners describe their decks with a language and categories so /decks can filter by them.
+-- Valid values are defined in discovery_manager.rs.
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS language TEXT;
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS categories TEXT[] NOT NULL DEFAULT '{}';
+
+-- Reviewed notes of a top-level deck and its subdecks, kept up to date by the stats job so
+-- /decks can sort by it
+ALTER TABLE decks ADD COLUMN IF NOT EXISTS note_count BIGINT;
+
+CREATE INDEX IF NOT EXISTS decks_language_idx ON decks (language) WHERE parent IS NULL;
+CREATE INDEX IF NOT EXISTS decks_categories_idx ON decks USING GIN (categories);
+
+-- Decks picked by admins for the top of /decks
+CREATE TABLE IF NOT EXISTS featured_decks (
+    deck_id BIGINT PRIMARY KEY REFERENCES decks(id) ON DELETE CASCADE,
+    featured_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,26 @@
+-- Changelogs are written in Markdown. `message` keeps the sanitized HTML that gets displayed,
+-- `source` the Markdown it was rendered from. Older entries have no source and store plain
+-- text (or HTML written by the server) in `message`.
+ALTER TABLE changelogs ADD COLUMN IF NOT EXISTS source TEXT;
+ALTER TABLE changelogs ADD COLUMN IF NOT EXISTS edited_at TIMESTAMPTZ;
+
+CREATE INDEX IF NOT EXISTS changelogs_deck_timestamp_idx ON changelogs (deck, timestamp DESC);
+
+-- Previous versions of an entry, written before every edit
+CREATE TABLE IF NOT EXISTS changelog_revisions (
+    id BIGSERIAL PRIMARY KEY,
+    changelog_id BIGINT NOT NULL REFERENCES changelogs(id) ON DELETE CASCADE,
+    message TEXT NOT NULL,
+    source TEXT,
+    edited_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS changelog_revisions_changelog_idx ON changelog_revisions (changelog_id, created_at DESC);
+
+-- Commits summarized by a changelog entry
+CREATE TABLE IF NOT EXISTS changelog_commits (
+    changelog_id BIGINT NOT NULL REFERENCES changelogs(id) ON DELETE CASCADE,
+    commit_id INTEGER NOT NULL REFERENCES commits(commit_id) ON DELETE CASCADE,
+    PRIMARY KEY (changelog_id, commit_id)
+);
@@ -0,0 +1,8 @@
+-- Secret feed links so feed readers can follow private decks and a user's maintained decks
+-- without a session. Only the hash of the token is stored, one token per user.
+CREATE TABLE IF NOT EXISTS feed_tokens (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    token_hash TEXT NOT NULL UNIQUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at TIMESTAMPTZ
+);
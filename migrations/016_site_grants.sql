@@ -0,0 +1,9 @@
+-- Capabilities that are not tied to a deck, like working through content reports.
+-- Admins hold all of them implicitly.
+CREATE TABLE IF NOT EXISTS site_grants (
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    capability TEXT NOT NULL,
+    granted_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, capability)
+);
@@ -0,0 +1,300 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::database;
+use crate::error::Error::{DeckNotFound, UserNotFound};
+use crate::user::User;
+use crate::{deck_manager, DeckHash, Return};
+
+const SEARCH_LIMIT: i64 = 50;
+
+#[derive(Serialize)]
+pub struct AdminUserRow {
+    pub id: i32,
+    pub username: String,
+    pub is_admin: bool,
+    pub deleted_at: Option<String>,
+    /// Unknown for accounts from before it was recorded
+    pub created_at: Option<String>,
+    pub owned_decks: i64,
+    pub suspended_at: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct AdminDeckRow {
+    pub id: i64,
+    pub name: String,
+    pub hash: String,
+    pub owner: String,
+    pub private: bool,
+    pub last_update: String,
+    pub moderation_hidden: bool,
+    pub featured: bool,
+}
+
+#[derive(Serialize)]
+pub struct LoginLogRow {
+    pub ip_address: String,
+    pub timestamp: String,
+}
+
+#[derive(Serialize)]
+pub struct AuditLogRow {
+    pub admin: Option<String>,
+    pub action: String,
+    pub target_type: String,
+    pub target_id: String,
+    pub details: Option<String>,
+    pub timestamp: String,
+}
+
+fn like_pattern(query: &str) -> String {
+    let escaped = query
+        .trim()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{escaped}%")
+}
+
+pub async fn record_audit(
+    db_state: &Arc<database::AppState>,
+    admin: &User,
+    action: &str,
+    target_type: &str,
+    target_id: &str,
+    details: serde_json::Value,
+) -> Return<()> {
+    let client = database::client(db_state).await?;
+    client
+        .execute(
+            "INSERT INTO admin_audit_log (admin_id, action, target_type, target_id, details)
+             VALUES ($1, $2, $3, $4, $5)",
+            &[&admin.id(), &action, &target_type, &target_id, &details],
+        )
+        .await?;
+    tracing::info!(admin = admin.id(), action, target_type, target_id, "Admin action");
+    Ok(())
+}
+
+pub async fn get_audit_log(
+    db_state: &Arc<database::AppState>,
+    limit: i64,
+) -> Return<Vec<AuditLogRow>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            "SELECT u.username, a.action, a.target_type, a.target_id, a.details::TEXT,
+                    TO_CHAR(a.created_at, 'MM/DD/YYYY HH24:MI:SS')
+             FROM admin_audit_log a
+             LEFT JOIN users u ON u.id = a.admin_id
+             ORDER BY a.created_at DESC
+             LIMIT $1",
+            &[&limit],
+        )
+        .await?
+        .into_iter()
+        .map(|row| AuditLogRow {
+            admin: row.get(0),
+            action: row.get(1),
+            target_type: row.get(2),
+            target_id: row.get(3),
+            details: row.get(4),
+            timestamp: row.get(5),
+        })
+        .collect();
+    Ok(rows)
+}
+
+const USER_COLUMNS: &str = "
+    u.id, u.username, u.is_admin,
+    TO_CHAR(u.deleted_at, 'MM/DD/YYYY HH24:MI:SS'),
+    TO_CHAR(u.created_at, 'MM/DD/YYYY'),
+    (SELECT COUNT(*) FROM decks d WHERE d.owner = u.id AND d.parent IS NULL),
+    TO_CHAR(u.suspended_at, 'MM/DD/YYYY HH24:MI:SS')";
+
+fn user_row(row: &tokio_postgres::Row) -> AdminUserRow {
+    AdminUserRow {
+        id: row.get(0),
+        username: row.get(1),
+        is_admin: row.get(2),
+        deleted_at: row.get(3),
+        created_at: row.get(4),
+        owned_decks: row.get(5),
+        suspended_at: row.get(6),
+    }
+}
+
+pub async fn search_users(
+    db_state: &Arc<database::AppState>,
+    query: &str,
+) -> Return<Vec<AdminUserRow>> {
+    let client = database::client(db_state).await?;
+    let sql = format!(
+        "SELECT {USER_COLUMNS} FROM users u
+         WHERE u.username ILIKE $1 OR u.id::TEXT = $2
+         ORDER BY u.username
+         LIMIT $3"
+    );
+    let rows = client
+        .query(&sql, &[&like_pattern(query), &query.trim(), &SEARCH_LIMIT])
+        .await?;
+    Ok(rows.iter().map(user_row).collect())
+}
+
+pub async fn get_user(db_state: &Arc<database::AppState>, user_id: i32) -> Return<AdminUserRow> {
+    let client = database::client(db_state).await?;
+    let sql = format!("SELECT {USER_COLUMNS} FROM users u WHERE u.id = $1");
+    let row = client.query_opt(&sql, &[&user_id]).await?.ok_or(UserNotFound)?;
+    Ok(user_row(&row))
+}
+
+pub async fn get_login_logs(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+    limit: i64,
+) -> Return<Vec<LoginLogRow>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            "SELECT HOST(ip_address), TO_CHAR(created_at, 'MM/DD/YYYY HH24:MI:SS')
+             FROM login_logs
+             WHERE user_id = $1
+             ORDER BY created_at DESC
+             LIMIT $2",
+            &[&user_id, &limit],
+        )
+        .await?
+        .into_iter()
+        .map(|row| LoginLogRow {
+            ip_address: row.get(0),
+            timestamp: row.get(1),
+        })
+        .collect();
+    Ok(rows)
+}
+
+fn deck_row(row: &tokio_postgres::Row) -> AdminDeckRow {
+    AdminDeckRow {
+        id: row.get(0),
+        name: row.get(1),
+        hash: row.get(2),
+        owner: row.get(3),
+        private: row.get(4),
+        last_update: row.get(5),
+        moderation_hidden: row.get(6),
+        featured: row.get(7),
+    }
+}
+
+const DECK_COLUMNS: &str = "
+    d.id, d.name, d.human_hash, COALESCE(u.username, '<deleted>'), d.private,
+    TO_CHAR(d.last_update, 'MM/DD/YYYY'), d.moderation_hidden,
+    EXISTS (SELECT 1 FROM featured_decks f WHERE f.deck_id = d.id)";
+
+pub async fn search_decks(
+    db_state: &Arc<database::AppState>,
+    query: &str,
+) -> Return<Vec<AdminDeckRow>> {
+    let client = database::client(db_state).await?;
+    let sql = format!(
+        "SELECT {DECK_COLUMNS} FROM decks d
+         LEFT JOIN users u ON u.id = d.owner
+         WHERE d.parent IS NULL AND (d.name ILIKE $1 OR d.human_hash = $2 OR u.username = $3)
+         ORDER BY d.last_update DESC
+         LIMIT $4"
+    );
+    let rows = client
+        .query(
+            &sql,
+            &[
+                &like_pattern(query),
+                &query.trim(),
+                &query.trim().to_lowercase(),
+                &SEARCH_LIMIT,
+            ],
+        )
+        .await?;
+    Ok(rows.iter().map(deck_row).collect())
+}
+
+pub async fn get_owned_decks(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+) -> Return<Vec<AdminDeckRow>> {
+    let client = database::client(db_state).await?;
+    let sql = format!(
+        "SELECT {DECK_COLUMNS} FROM decks d
+         LEFT JOIN users u ON u.id = d.owner
+         WHERE d.parent IS NULL AND d.owner = $1
+         ORDER BY d.name"
+    );
+    let rows = client.query(&sql, &[&user_id]).await?;
+    Ok(rows.iter().map(deck_row).collect())
+}
+
+/// Moves a top-level deck (and all subdecks) to another user. Returns (`deck_id`, `old_owner`, `new_owner`).
+pub async fn transfer_deck(
+    db_state: &Arc<database::AppState>,
+    deck_hash: &DeckHash,
+    new_owner_name: &str,
+) -> Return<(i64, i32, i32)> {
+    let mut client = database::client(db_state).await?;
+
+    let deck = client
+        .query_opt(
+            "SELECT id, owner FROM decks WHERE human_hash = $1 AND parent IS NULL",
+            &[deck_hash],
+        )
+        .await?
+        .ok_or(DeckNotFound)?;
+    let deck_id: i64 = deck.get(0);
+    let old_owner: i32 = deck.get(1);
+
+    let new_owner: i32 = client
+        .query_opt(
+            "SELECT id FROM users WHERE username = $1 AND deleted_at IS NULL",
+            &[&new_owner_name.trim().to_lowercase()],
+        )
+        .await?
+        .ok_or(UserNotFound)?
+        .get(0);
+
+    let tx = client.transaction().await?;
+    if let Err(e) = deck_manager::transfer_ownership(&tx, deck_id, new_owner).await {
+        let _ = tx.rollback().await;
+        return Err(e);
+    }
+    tx.commit().await?;
+
+    Ok((deck_id, old_owner, new_owner))
+}
+
+#[derive(Serialize)]
+pub struct SiteCounts {
+    pub users: i64,
+    pub deleted_users: i64,
+    pub decks: i64,
+    pub open_reports: i64,
+}
+
+pub async fn get_site_counts(db_state: &Arc<database::AppState>) -> Return<SiteCounts> {
+    let client = database::client(db_state).await?;
+    let row = client
+        .query_one(
+            "SELECT
+                (SELECT COUNT(*) FROM users WHERE deleted_at IS NULL),
+                (SELECT COUNT(*) FROM users WHERE deleted_at IS NOT NULL),
+                (SELECT COUNT(*) FROM decks WHERE parent IS NULL),
+                (SELECT COUNT(*) FROM content_reports WHERE status = 'open')",
+            &[],
+        )
+        .await?;
+    Ok(SiteCounts {
+        users: row.get(0),
+        deleted_users: row.get(1),
+        decks: row.get(2),
+        open_reports: row.get(3),
+    })
+}
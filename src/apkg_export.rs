@@ -0,0 +1,768 @@
+//! Download of a deck as an Anki package (`.apkg`) for people who don't use the add-on.
+//!
+//! The package uses the legacy schema 11 collection that every Anki version imports: a SQLite
+//! `collection.anki2`, a `media` manifest that maps the numbered zip entries to file names and
+//! the media files themselves. The archive is written into the response body while it is being
+//! built: the zip header of the collection goes out before the notes are even loaded, so large
+//! decks start downloading right away instead of running into timeouts. Media files are read
+//! from the media bucket.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use async_zip::tokio::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use axum::body::Bytes;
+use futures_util::io::AsyncWriteExt as _;
+use futures_util::{Stream, StreamExt};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use rusqlite::params;
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use tokio::io::{AsyncReadExt as _, DuplexStream};
+use tokio::sync::oneshot;
+use tokio_util::io::ReaderStream;
+
+use crate::database;
+use crate::deck_manager::DECK_TREE;
+use crate::media_reference_manager::{media_bucket, media_object_key};
+use crate::user::random_token;
+use crate::DeckId;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Deck and notetype ids are moved out of the range of Anki's own ids (the default deck is 1)
+const ANKI_ID_BASE: i64 = 1_000_000_000_000;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+struct ExportDeck {
+    id: DeckId,
+    name: String,
+}
+
+struct ExportTemplate {
+    name: String,
+    qfmt: String,
+    afmt: String,
+}
+
+struct ExportNotetype {
+    id: i64,
+    name: String,
+    css: String,
+    fields: Vec<String>,
+    templates: Vec<ExportTemplate>,
+}
+
+impl ExportNotetype {
+    fn is_cloze(&self) -> bool {
+        self.templates.iter().any(|t| t.qfmt.contains("{{cloze:"))
+    }
+}
+
+struct ExportNote {
+    id: i64,
+    guid: String,
+    deck: DeckId,
+    notetype: i64,
+    modified: i64,
+    fields: Vec<String>,
+    tags: Vec<String>,
+}
+
+/// A media file the notes reference
+struct ExportMedia {
+    file_name: String,
+    hash: String,
+    /// Top-level deck the file is stored below
+    deck_hash: String,
+}
+
+struct ExportData {
+    decks: Vec<ExportDeck>,
+    notetypes: Vec<ExportNotetype>,
+    notes: Vec<ExportNote>,
+    media: Vec<ExportMedia>,
+}
+
+/// Reviewed content of the deck and its subdecks, with subscribed fields and tags of inherited
+/// notes resolved the same way the note page shows them
+async fn load(db_state: &Arc<database::AppState>, root: DeckId) -> Result<ExportData, BoxError> {
+    let client = database::client(db_state).await?;
+
+    let decks = client
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT d.id, d.full_path FROM decks d JOIN tree ON tree.id = d.id ORDER BY d.full_path"
+            ),
+            &[&root],
+        )
+        .await?
+        .iter()
+        .map(|row| ExportDeck {
+            id: row.get(0),
+            name: row.get(1),
+        })
+        .collect();
+
+    let mut notes = vec![];
+    let mut note_index = HashMap::new();
+    let note_rows = client
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT n.id, n.guid, n.deck, n.notetype, EXTRACT(EPOCH FROM n.last_update)::BIGINT
+                FROM notes n JOIN tree ON tree.id = n.deck
+                WHERE n.reviewed AND NOT n.deleted
+                ORDER BY n.id"
+            ),
+            &[&root],
+        )
+        .await?;
+    for row in note_rows {
+        let id: i64 = row.get(0);
+        note_index.insert(id, notes.len());
+        notes.push(ExportNote {
+            id,
+            guid: row.get(1),
+            deck: row.get(2),
+            notetype: row.get(3),
+            modified: row.get::<_, Option<i64>>(4).unwrap_or_default(),
+            fields: vec![],
+            tags: vec![],
+        });
+    }
+    let note_ids: Vec<i64> = notes.iter().map(|note| note.id).collect();
+
+    let notetype_ids: Vec<i64> = notes
+        .iter()
+        .map(|note| note.notetype)
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut notetypes: Vec<ExportNotetype> = client
+        .query(
+            "SELECT id, name, css FROM notetype WHERE id = ANY($1) ORDER BY id",
+            &[&notetype_ids],
+        )
+        .await?
+        .iter()
+        .map(|row| ExportNotetype {
+            id: row.get(0),
+            name: row.get(1),
+            css: row.get::<_, Option<String>>(2).unwrap_or_default(),
+            fields: vec![],
+            templates: vec![],
+        })
+        .collect();
+    let notetype_index: HashMap<i64, usize> = notetypes
+        .iter()
+        .enumerate()
+        .map(|(i, notetype)| (notetype.id, i))
+        .collect();
+    for row in client
+        .query(
+            "SELECT notetype, name FROM notetype_field WHERE notetype = ANY($1) ORDER BY notetype, position",
+            &[&notetype_ids],
+        )
+        .await?
+    {
+        let notetype: i64 = row.get(0);
+        notetypes[notetype_index[&notetype]].fields.push(row.get(1));
+    }
+    for row in client
+        .query(
+            "SELECT notetype, name, qfmt, afmt FROM notetype_template
+             WHERE notetype = ANY($1) ORDER BY notetype, id",
+            &[&notetype_ids],
+        )
+        .await?
+    {
+        let notetype: i64 = row.get(0);
+        notetypes[notetype_index[&notetype]]
+            .templates
+            .push(ExportTemplate {
+                name: row.get(1),
+                qfmt: row.get(2),
+                afmt: row.get(3),
+            });
+    }
+
+    for note in &mut notes {
+        let field_count = notetype_index
+            .get(&note.notetype)
+            .map_or(0, |&i| notetypes[i].fields.len());
+        note.fields = vec![String::new(); field_count];
+    }
+
+    let fields_query = "SELECT note, position::int, content FROM fields
+                        WHERE note = ANY($1) AND reviewed = true";
+    let tags_query = "SELECT note, content FROM tags
+                      WHERE note = ANY($1) AND reviewed = true AND content IS NOT NULL";
+
+    for row in client.query(fields_query, &[&note_ids]).await? {
+        let note = &mut notes[note_index[&row.get::<_, i64>(0)]];
+        let position: i32 = row.get(1);
+        if let Some(field) = usize::try_from(position)
+            .ok()
+            .and_then(|i| note.fields.get_mut(i))
+        {
+            *field = row.get::<_, Option<String>>(2).unwrap_or_default();
+        }
+    }
+    for row in client.query(tags_query, &[&note_ids]).await? {
+        notes[note_index[&row.get::<_, i64>(0)]]
+            .tags
+            .push(row.get(1));
+    }
+
+    // Subscribed notes: base fields overwrite the subscribed positions (all when NULL), tags are
+    // the base tags minus the removed ones plus the note's own
+    let inheritance = client
+        .query(
+            "SELECT subscriber_note_id, base_note_id, subscribed_fields,
+                    COALESCE(removed_base_tags, '{}')
+             FROM note_inheritance WHERE subscriber_note_id = ANY($1)",
+            &[&note_ids],
+        )
+        .await?;
+    if !inheritance.is_empty() {
+        let base_ids: Vec<i64> = inheritance.iter().map(|row| row.get(1)).collect();
+        let mut base_fields: HashMap<i64, Vec<(i32, String)>> = HashMap::new();
+        for row in client.query(fields_query, &[&base_ids]).await? {
+            base_fields.entry(row.get(0)).or_default().push((
+                row.get(1),
+                row.get::<_, Option<String>>(2).unwrap_or_default(),
+            ));
+        }
+        let mut base_tags: HashMap<i64, Vec<String>> = HashMap::new();
+        for row in client.query(tags_query, &[&base_ids]).await? {
+            base_tags.entry(row.get(0)).or_default().push(row.get(1));
+        }
+
+        for row in inheritance {
+            let note = &mut notes[note_index[&row.get::<_, i64>(0)]];
+            let base_id: i64 = row.get(1);
+            let subscribed: Option<Vec<i32>> = row.get(2);
+            let removed: Vec<String> = row.get(3);
+
+            for (position, content) in base_fields.get(&base_id).into_iter().flatten() {
+                let is_subscribed = subscribed
+                    .as_ref()
+                    .is_none_or(|positions| positions.contains(position));
+                if !is_subscribed {
+                    continue;
+                }
+                if let Some(field) = usize::try_from(*position)
+                    .ok()
+                    .and_then(|i| note.fields.get_mut(i))
+                {
+                    field.clone_from(content);
+                }
+            }
+
+            let mut tags: Vec<String> = base_tags
+                .get(&base_id)
+                .into_iter()
+                .flatten()
+                .filter(|tag| !removed.contains(tag) && !note.tags.contains(tag))
+                .cloned()
+                .collect();
+            tags.append(&mut note.tags);
+            note.tags = tags;
+        }
+    }
+
+    let media = client
+        .query(
+            "WITH RECURSIVE refs AS (
+                 SELECT mr.file_name, mf.hash, n.deck
+                 FROM media_references mr
+                 JOIN media_files mf ON mf.id = mr.media_id
+                 JOIN notes n ON n.id = mr.note_id
+                 WHERE mr.note_id = ANY($1)
+                    OR mr.note_id IN (SELECT base_note_id FROM note_inheritance
+                                      WHERE subscriber_note_id = ANY($1))
+             ), up AS (
+                 SELECT id AS start, parent, human_hash FROM decks
+                 WHERE id IN (SELECT deck FROM refs)
+                 UNION ALL
+                 SELECT up.start, d.parent, d.human_hash
+                 FROM decks d JOIN up ON d.id = up.parent
+             )
+             SELECT DISTINCT ON (refs.file_name) refs.file_name, refs.hash, up.human_hash
+             FROM refs
+             JOIN up ON up.start = refs.deck AND up.parent IS NULL
+             ORDER BY refs.file_name",
+            &[&note_ids],
+        )
+        .await?
+        .iter()
+        .map(|row| ExportMedia {
+            file_name: row.get(0),
+            hash: row.get(1),
+            deck_hash: row.get(2),
+        })
+        .collect();
+
+    Ok(ExportData {
+        decks,
+        notetypes,
+        notes,
+        media,
+    })
+}
+
+const SCHEMA: &str = "
+    CREATE TABLE col (
+        id integer primary key, crt integer not null, mod integer not null,
+        scm integer not null, ver integer not null, dty integer not null,
+        usn integer not null, ls integer not null, conf text not null,
+        models text not null, decks text not null, dconf text not null, tags text not null
+    );
+    CREATE TABLE notes (
+        id integer primary key, guid text not null, mid integer not null,
+        mod integer not null, usn integer not null, tags text not null,
+        flds text not null, sfld integer not null, csum integer not null,
+        flags integer not null, data text not null
+    );
+    CREATE TABLE cards (
+        id integer primary key, nid integer not null, did integer not null,
+        ord integer not null, mod integer not null, usn integer not null,
+        type integer not null, queue integer not null, due integer not null,
+        ivl integer not null, factor integer not null, reps integer not null,
+        lapses integer not null, left integer not null, odue integer not null,
+        odid integer not null, flags integer not null, data text not null
+    );
+    CREATE TABLE revlog (
+        id integer primary key, cid integer not null, usn integer not null,
+        ease integer not null, ivl integer not null, lastIvl integer not null,
+        factor integer not null, time integer not null, type integer not null
+    );
+    CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+    CREATE INDEX ix_notes_usn ON notes (usn);
+    CREATE INDEX ix_cards_usn ON cards (usn);
+    CREATE INDEX ix_revlog_usn ON revlog (usn);
+    CREATE INDEX ix_cards_nid ON cards (nid);
+    CREATE INDEX ix_cards_sched ON cards (did, queue, due);
+    CREATE INDEX ix_revlog_cid ON revlog (cid);
+    CREATE INDEX ix_notes_csum ON notes (csum);";
+
+const LATEX_PRE: &str = "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage[utf8]{inputenc}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n";
+
+fn deck_json(id: i64, name: &str, now: i64) -> Value {
+    json!({
+        "id": id,
+        "name": name,
+        "mod": now,
+        "usn": -1,
+        "desc": "",
+        "dyn": 0,
+        "conf": 1,
+        "collapsed": false,
+        "browserCollapsed": false,
+        "extendNew": 0,
+        "extendRev": 0,
+        "newToday": [0, 0],
+        "revToday": [0, 0],
+        "lrnToday": [0, 0],
+        "timeToday": [0, 0],
+    })
+}
+
+fn notetype_json(notetype: &ExportNotetype, deck_id: i64, now: i64) -> Value {
+    let fields: Vec<Value> = notetype
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(ord, name)| {
+            json!({
+                "name": name,
+                "ord": ord,
+                "sticky": false,
+                "rtl": false,
+                "font": "Arial",
+                "size": 20,
+                "media": [],
+            })
+        })
+        .collect();
+    let templates: Vec<Value> = notetype
+        .templates
+        .iter()
+        .enumerate()
+        .map(|(ord, template)| {
+            json!({
+                "name": template.name,
+                "ord": ord,
+                "qfmt": template.qfmt,
+                "afmt": template.afmt,
+                "bqfmt": "",
+                "bafmt": "",
+                "did": null,
+            })
+        })
+        .collect();
+    json!({
+        "id": ANKI_ID_BASE + notetype.id,
+        "name": notetype.name,
+        "type": i32::from(notetype.is_cloze()),
+        "mod": now,
+        "usn": -1,
+        "sortf": 0,
+        "did": deck_id,
+        "flds": fields,
+        "tmpls": templates,
+        "css": notetype.css,
+        "latexPre": LATEX_PRE,
+        "latexPost": "\\end{document}",
+        "latexsvg": false,
+        "req": [],
+        "tags": [],
+        "vers": [],
+    })
+}
+
+static RE_TAG: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?s)<[^>]*>").expect("Invalid tag regex"));
+static RE_REFERENCE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{\{([^{}]+)\}\}").expect("Invalid field reference regex"));
+static RE_CLOZE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\{\{c(\d+)::").expect("Invalid cloze regex"));
+
+fn strip_html(content: &str) -> String {
+    RE_TAG.replace_all(content, "")
+        .replace("&nbsp;", " ")
+        .trim()
+        .to_string()
+}
+
+/// First 8 hex digits of the SHA-1 of the stripped sort field, like Anki's duplicate check
+fn field_checksum(sort_field: &str) -> i64 {
+    let digest = Sha1::digest(sort_field.as_bytes());
+    i64::from(u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]))
+}
+
+/// Field names a template references, without filters and section markers
+fn template_fields(qfmt: &str) -> Vec<&str> {
+    RE_REFERENCE
+        .captures_iter(qfmt)
+        .filter_map(|capture| {
+            let reference = capture.get(1)?.as_str().trim();
+            let reference = reference.trim_start_matches(['#', '^', '/']);
+            let name = reference.rsplit(':').next()?.trim();
+            (!name.is_empty() && name != "FrontSide").then_some(name)
+        })
+        .collect()
+}
+
+/// Template ordinals that produce a card: for cloze notetypes one per cloze number, otherwise
+/// every template that shows at least one non-empty field
+fn card_ordinals(notetype: &ExportNotetype, fields: &[String]) -> Vec<u32> {
+    if notetype.is_cloze() {
+        let mut ordinals: Vec<u32> = fields
+            .iter()
+            .flat_map(|field| RE_CLOZE.captures_iter(field))
+            .filter_map(|capture| capture[1].parse::<u32>().ok())
+            .filter(|&number| number > 0)
+            .map(|number| number - 1)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        ordinals.sort_unstable();
+        if ordinals.is_empty() {
+            ordinals.push(0);
+        }
+        return ordinals;
+    }
+
+    let ordinals: Vec<u32> = (0u32..)
+        .zip(&notetype.templates)
+        .filter(|(_, template)| {
+            let referenced = template_fields(&template.qfmt);
+            referenced.is_empty()
+                || referenced.iter().any(|name| {
+                    notetype
+                        .fields
+                        .iter()
+                        .position(|field| field == name)
+                        .is_some_and(|i| !strip_html(&fields[i]).is_empty())
+                })
+        })
+        .map(|(ord, _)| ord)
+        .collect();
+    // Anki keeps at least the first card of a note
+    if ordinals.is_empty() {
+        vec![0]
+    } else {
+        ordinals
+    }
+}
+
+fn build_collection(path: &Path, data: &ExportData) -> rusqlite::Result<()> {
+    let now = chrono::Utc::now();
+    let now_secs = now.timestamp();
+    let now_ms = now.timestamp_millis();
+
+    let mut conn = rusqlite::Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+
+    let mut decks = serde_json::Map::new();
+    decks.insert("1".to_string(), deck_json(1, "Default", now_secs));
+    for deck in &data.decks {
+        let id = ANKI_ID_BASE + deck.id;
+        decks.insert(id.to_string(), deck_json(id, &deck.name, now_secs));
+    }
+    let first_deck = data.decks.first().map_or(1, |deck| ANKI_ID_BASE + deck.id);
+
+    let models: serde_json::Map<String, Value> = data
+        .notetypes
+        .iter()
+        .map(|notetype| {
+            (
+                (ANKI_ID_BASE + notetype.id).to_string(),
+                notetype_json(notetype, first_deck, now_secs),
+            )
+        })
+        .collect();
+
+    let conf = json!({
+        "activeDecks": [1],
+        "curDeck": 1,
+        "newSpread": 0,
+        "collapseTime": 1200,
+        "timeLim": 0,
+        "estTimes": true,
+        "dueCounts": true,
+        "curModel": null,
+        "nextPos": data.notes.len() + 1,
+        "sortType": "noteFld",
+        "sortBackwards": false,
+        "addToCur": true,
+    });
+    let dconf = json!({
+        "1": {
+            "id": 1,
+            "name": "Default",
+            "mod": 0,
+            "usn": 0,
+            "maxTaken": 60,
+            "autoplay": true,
+            "timer": 0,
+            "replayq": true,
+            "dyn": false,
+            "new": {
+                "bury": false,
+                "delays": [1.0, 10.0],
+                "initialFactor": 2500,
+                "ints": [1, 4, 0],
+                "order": 1,
+                "perDay": 20,
+            },
+            "rev": {
+                "bury": false,
+                "ease4": 1.3,
+                "ivlFct": 1.0,
+                "maxIvl": 36500,
+                "perDay": 200,
+                "hardFactor": 1.2,
+            },
+            "lapse": {
+                "delays": [10.0],
+                "leechAction": 1,
+                "leechFails": 8,
+                "minInt": 1,
+                "mult": 0.0,
+            },
+        },
+    });
+
+    tx.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?2, 11, 0, 0, 0, ?3, ?4, ?5, ?6, '{}')",
+        params![
+            now_secs - now_secs % 86_400,
+            now_ms,
+            conf.to_string(),
+            Value::Object(models).to_string(),
+            Value::Object(decks).to_string(),
+            dconf.to_string(),
+        ],
+    )?;
+
+    let notetypes: HashMap<i64, &ExportNotetype> = data
+        .notetypes
+        .iter()
+        .map(|notetype| (notetype.id, notetype))
+        .collect();
+    {
+        let mut insert_note = tx.prepare(
+            "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+        )?;
+        let mut insert_card = tx.prepare(
+            "INSERT INTO cards VALUES (?1, ?2, ?3, ?4, ?5, -1, 0, 0, ?6, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+        )?;
+        for (position, note) in (1i64..).zip(&data.notes) {
+            let Some(notetype) = notetypes.get(&note.notetype) else {
+                continue;
+            };
+            // Anki tags can't contain spaces
+            let tags: Vec<String> = note.tags.iter().map(|tag| tag.replace(' ', "_")).collect();
+            let tags = if tags.is_empty() {
+                String::new()
+            } else {
+                format!(" {} ", tags.join(" "))
+            };
+            let sort_field = note.fields.first().map(|f| strip_html(f)).unwrap_or_default();
+            insert_note.execute(params![
+                note.id,
+                note.guid,
+                ANKI_ID_BASE + notetype.id,
+                note.modified,
+                tags,
+                note.fields.join("\x1f"),
+                sort_field,
+                field_checksum(&sort_field),
+            ])?;
+            for ord in card_ordinals(notetype, &note.fields) {
+                insert_card.execute(params![
+                    note.id * 1000 + i64::from(ord),
+                    note.id,
+                    ANKI_ID_BASE + note.deck,
+                    ord,
+                    note.modified,
+                    position,
+                ])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Removes a temporary file however the export (or import) ends
+pub(crate) struct TempFile(pub(crate) PathBuf);
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+async fn write_package(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+    writer: DuplexStream,
+) -> Result<(), BoxError> {
+    // The local header is written right away, the client sees the download start while the
+    // notes are loaded and the collection is built
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    let mut entry = zip
+        .write_entry_stream(ZipEntryBuilder::new(
+            "collection.anki2".to_string().into(),
+            Compression::Deflate,
+        ))
+        .await?;
+
+    let mut data = load(db_state, deck_id).await?;
+    let media = std::mem::take(&mut data.media);
+
+    let collection = TempFile(std::env::temp_dir().join(format!("apkg-{}.anki2", random_token())));
+    let path = collection.0.clone();
+    tokio::task::spawn_blocking(move || build_collection(&path, &data)).await??;
+
+    let mut file = tokio::fs::File::open(&collection.0).await?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        entry.write_all(&buffer[..read]).await?;
+    }
+    entry.close().await?;
+    drop(collection);
+
+    // Media is stored uncompressed, images and audio are compressed already
+    let mut manifest = BTreeMap::new();
+    let (bucket, media) = match media_bucket() {
+        Some(bucket) => (bucket, media),
+        None => {
+            if !media.is_empty() {
+                tracing::warn!(
+                    deck_id,
+                    "S3_MEDIA_BUCKET is not set, apkg export has no media"
+                );
+            }
+            (String::new(), vec![])
+        }
+    };
+    for media in media {
+        let mut object = match db_state
+            .s3_client
+            .get_object()
+            .bucket(&bucket)
+            .key(media_object_key(&media.deck_hash, &media.hash))
+            .send()
+            .await
+        {
+            Ok(object) => object,
+            Err(err) => {
+                tracing::warn!(
+                    deck_id,
+                    file_name = %media.file_name,
+                    error = %err,
+                    "Media file missing from apkg export"
+                );
+                continue;
+            }
+        };
+        let number = manifest.len().to_string();
+        let mut entry = zip
+            .write_entry_stream(ZipEntryBuilder::new(number.clone().into(), Compression::Stored))
+            .await?;
+        while let Some(chunk) = object.body.try_next().await? {
+            entry.write_all(&chunk).await?;
+        }
+        entry.close().await?;
+        manifest.insert(number, media.file_name);
+    }
+
+    zip.write_entry_whole(
+        ZipEntryBuilder::new("media".to_string().into(), Compression::Deflate),
+        serde_json::to_string(&manifest)?.as_bytes(),
+    )
+    .await?;
+    zip.close().await?;
+    Ok(())
+}
+
+/// Builds the package in the background and returns the archive as a body stream. A failure
+/// half way through ends the stream with an error, so the client sees an aborted download
+/// instead of a truncated file.
+pub fn stream_package(
+    db_state: Arc<database::AppState>,
+    deck_id: DeckId,
+) -> impl Stream<Item = io::Result<Bytes>> + Send + 'static {
+    let (writer, reader) = tokio::io::duplex(CHUNK_SIZE);
+    let (result_tx, result_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let result = write_package(&db_state, deck_id, writer).await;
+        if let Err(err) = &result {
+            tracing::error!(deck_id, error = %err, "apkg export failed");
+        }
+        let _ = result_tx.send(result.map_err(|err| err.to_string()));
+    });
+
+    let outcome = futures_util::stream::once(result_rx).filter_map(|result| async move {
+        match result {
+            Ok(Ok(())) => None,
+            Ok(Err(message)) => Some(Err(io::Error::other(message))),
+            Err(_) => Some(Err(io::Error::other("apkg export stopped"))),
+        }
+    });
+    ReaderStream::new(reader).chain(outcome)
+}
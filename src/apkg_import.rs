@@ -0,0 +1,618 @@
+//! Creating a deck from an uploaded Anki package (`.apkg`) instead of the desktop add-on.
+//!
+//! Only packages with a schema 11 collection (`collection.anki21` or `collection.anki2`) are
+//! read, newer Anki versions write those when "Support older Anki versions" is checked. The
+//! decks, notetypes, notes, fields and tags are created reviewed as one "Deck Creation" commit.
+//! Notes whose GUID is already on AnkiCollab are skipped and reported, otherwise the add-on
+//! couldn't tell them apart. The media files the imported notes reference are uploaded below the
+//! new deck and registered for those notes, referenced files the package lacks are reported.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use async_zip::tokio::read::seek::ZipFileReader;
+use aws_sdk_s3::primitives::ByteStream;
+use futures_util::io::AsyncReadExt as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncWriteExt as _, BufReader};
+
+use crate::apkg_export::TempFile;
+use crate::commit_manager::RATIONALE_DECK_CREATION;
+use crate::error::Error::BadRequest;
+use crate::media_reference_manager::{extract_media_references, media_bucket, media_object_key};
+use crate::user::{random_token, User};
+use crate::{cleanser, database, DeckHash, DeckId, Return};
+
+pub const MAX_NOTES: usize = 50_000;
+/// Uncompressed size of the collection, a small upload must not fill the disk
+const MAX_COLLECTION_BYTES: u64 = 1024 * 1024 * 1024;
+/// Decompressed size of a single media file, larger files are reported as missing
+const MAX_MEDIA_FILE_BYTES: u64 = 100 * 1024 * 1024;
+/// Decompressed size of all media files together
+const MAX_MEDIA_BYTES: u64 = 2 * 1024 * 1024 * 1024;
+const MAX_MANIFEST_BYTES: u64 = 16 * 1024 * 1024;
+/// GUIDs and file names listed in the report, the counts cover all of them
+const REPORTED_NAMES: usize = 100;
+const CHUNK_SIZE: usize = 64 * 1024;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+struct PackageNotetype {
+    name: String,
+    css: String,
+    fields: Vec<String>,
+    /// (name, qfmt, afmt)
+    templates: Vec<(String, String, String)>,
+}
+
+struct PackageNote {
+    guid: String,
+    notetype: i64,
+    /// Deck path relative to the new top-level deck, empty for the top-level deck itself
+    deck: Vec<String>,
+    fields: Vec<String>,
+    tags: Vec<String>,
+}
+
+struct Package {
+    notetypes: HashMap<i64, PackageNotetype>,
+    notes: Vec<PackageNote>,
+}
+
+#[derive(Serialize)]
+pub struct ImportReport {
+    pub deck_hash: DeckHash,
+    pub decks: usize,
+    pub notetypes: usize,
+    pub notes: usize,
+    /// Notes without content in their first field, Anki requires it
+    pub empty: usize,
+    /// Notes whose notetype is missing from the package
+    pub unknown_notetype: usize,
+    /// GUIDs already on AnkiCollab, and GUIDs repeated in the package (the first note is kept)
+    pub duplicate_count: usize,
+    pub duplicate_guids: Vec<String>,
+    /// Media files uploaded for the notes
+    pub media_uploaded: usize,
+    /// Referenced media files that are not in the package or too large
+    pub missing_media_count: usize,
+    pub missing_media: Vec<String>,
+}
+
+fn invalid_package() -> crate::error::Error {
+    BadRequest(
+        "This is not an Anki package this site can read. Export it from Anki as .apkg with \
+         \"Support older Anki versions\" checked."
+            .to_string(),
+    )
+}
+
+#[derive(Deserialize)]
+struct LegacyField {
+    name: String,
+    ord: u32,
+}
+
+#[derive(Deserialize)]
+struct LegacyTemplate {
+    name: String,
+    ord: u32,
+    qfmt: String,
+    afmt: String,
+}
+
+#[derive(Deserialize)]
+struct LegacyNotetype {
+    name: String,
+    #[serde(default)]
+    css: String,
+    flds: Vec<LegacyField>,
+    tmpls: Vec<LegacyTemplate>,
+}
+
+#[derive(Deserialize)]
+struct LegacyDeck {
+    name: String,
+}
+
+/// Reads the collection, note decks are still the full Anki path
+fn read_collection(path: &Path) -> Result<Package, BoxError> {
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let (models, decks): (String, String) =
+        conn.query_row("SELECT models, decks FROM col", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+    // Schema 18 collections keep notetypes in their own tables and leave this empty
+    let models: HashMap<String, LegacyNotetype> = serde_json::from_str(&models)?;
+    let decks: HashMap<String, LegacyDeck> = serde_json::from_str(&decks)?;
+    let deck_names: HashMap<i64, String> = decks
+        .into_iter()
+        .filter_map(|(id, deck)| Some((id.parse().ok()?, deck.name)))
+        .collect();
+
+    let notetypes = models
+        .into_iter()
+        .filter_map(|(id, mut model)| {
+            model.flds.sort_by_key(|f| f.ord);
+            model.tmpls.sort_by_key(|t| t.ord);
+            Some((
+                id.parse().ok()?,
+                PackageNotetype {
+                    name: model.name,
+                    css: model.css,
+                    fields: model.flds.into_iter().map(|f| f.name).collect(),
+                    templates: model
+                        .tmpls
+                        .into_iter()
+                        .map(|t| (t.name, t.qfmt, t.afmt))
+                        .collect(),
+                },
+            ))
+        })
+        .collect();
+
+    // A note lives in the deck of its first card, cards in filtered decks count for their home deck
+    let mut note_decks: HashMap<i64, i64> = HashMap::new();
+    let mut statement = conn.prepare(
+        "SELECT nid, CASE WHEN odid != 0 THEN odid ELSE did END FROM cards ORDER BY nid, ord",
+    )?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        note_decks.entry(row.get(0)?).or_insert(row.get(1)?);
+    }
+
+    let mut notes = vec![];
+    let mut statement = conn.prepare("SELECT id, guid, mid, tags, flds FROM notes ORDER BY id")?;
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let tags: String = row.get(3)?;
+        let fields: String = row.get(4)?;
+        let deck = note_decks
+            .get(&id)
+            .and_then(|deck| deck_names.get(deck))
+            .map_or("Default", String::as_str);
+        notes.push(PackageNote {
+            guid: row.get(1)?,
+            notetype: row.get(2)?,
+            deck: deck.split("::").map(str::to_string).collect(),
+            fields: fields.split('\x1f').map(str::to_string).collect(),
+            tags: tags.split_whitespace().map(str::to_string).collect(),
+        });
+    }
+
+    Ok(Package { notetypes, notes })
+}
+
+/// Moves the note decks below the new top-level deck. A package with a single top-level deck
+/// becomes that deck, several top-level decks become its subdecks.
+fn relative_paths(package: &mut Package) {
+    let top_levels: HashSet<&String> = package
+        .notes
+        .iter()
+        .filter_map(|note| note.deck.first())
+        .collect();
+    let strip = usize::from(top_levels.len() == 1);
+    for note in &mut package.notes {
+        note.deck.drain(..strip.min(note.deck.len()));
+    }
+}
+
+/// Entries of the zip by name: (index, uncompressed size)
+type Entries = HashMap<String, (usize, u64)>;
+
+type PackageReader = ZipFileReader<BufReader<tokio::fs::File>>;
+
+async fn open_package(path: &Path) -> Result<(PackageReader, Entries), BoxError> {
+    let file = tokio::fs::File::open(path).await?;
+    let zip = ZipFileReader::with_tokio(BufReader::new(file)).await?;
+    let mut entries = HashMap::new();
+    for (index, entry) in zip.file().entries().iter().enumerate() {
+        if let Ok(name) = entry.filename().as_str() {
+            entries.insert(name.to_string(), (index, entry.uncompressed_size()));
+        }
+    }
+    Ok((zip, entries))
+}
+
+/// Decompresses an entry, `None` if it is larger than `limit`. The size in the zip directory is
+/// only a claim, so what is actually read counts.
+async fn read_entry(
+    zip: &mut PackageReader,
+    index: usize,
+    limit: u64,
+) -> Result<Option<Vec<u8>>, BoxError> {
+    let reader = zip.reader_with_entry(index).await?;
+    let mut bytes = vec![];
+    reader.take(limit + 1).read_to_end(&mut bytes).await?;
+    Ok((bytes.len() as u64 <= limit).then_some(bytes))
+}
+
+/// Uploads the media files the imported notes reference and registers them for those notes.
+/// Returns the number of stored files and the referenced files that could not be stored.
+async fn store_media(
+    db_state: &Arc<database::AppState>,
+    zip: &mut PackageReader,
+    entries: &Entries,
+    deck_hash: &str,
+    note_media: &[(i64, HashSet<String>)],
+) -> Return<(usize, Vec<String>)> {
+    let referenced: BTreeSet<&String> = note_media.iter().flat_map(|(_, names)| names).collect();
+    if referenced.is_empty() {
+        return Ok((0, vec![]));
+    }
+    let Some(bucket) = media_bucket() else {
+        tracing::warn!("S3_MEDIA_BUCKET is not set, media of imported packages is dropped");
+        return Ok((0, referenced.into_iter().cloned().collect()));
+    };
+
+    // Maps the numbered entries to file names. References come from cleaned fields, so the
+    // names are cleaned the same way to match them.
+    let manifest: HashMap<String, String> = match entries.get("media") {
+        Some(&(index, _)) => read_entry(zip, index, MAX_MANIFEST_BYTES)
+            .await
+            .ok()
+            .flatten()
+            .and_then(|bytes| serde_json::from_slice::<HashMap<String, String>>(&bytes).ok())
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(number, name)| (cleanser::clean(&name), number))
+            .collect(),
+        None => HashMap::new(),
+    };
+
+    let client = database::client(db_state).await?;
+    let mut media_ids: HashMap<&str, i64> = HashMap::new();
+    let mut missing = vec![];
+    let mut total: u64 = 0;
+    for name in referenced {
+        let bytes = match manifest.get(name).and_then(|number| entries.get(number)) {
+            Some(&(index, _)) => read_entry(zip, index, MAX_MEDIA_FILE_BYTES)
+                .await
+                .ok()
+                .flatten()
+                .filter(|bytes| total + bytes.len() as u64 <= MAX_MEDIA_BYTES),
+            None => None,
+        };
+        let Some(bytes) = bytes else {
+            missing.push(name.clone());
+            continue;
+        };
+        total += bytes.len() as u64;
+
+        let hash = format!("{:x}", Sha256::digest(&bytes));
+        let size = i64::try_from(bytes.len()).unwrap_or(i64::MAX);
+        if let Err(e) = db_state
+            .s3_client
+            .put_object()
+            .bucket(&bucket)
+            .key(media_object_key(deck_hash, &hash))
+            .body(ByteStream::from(bytes))
+            .send()
+            .await
+        {
+            tracing::warn!(error = %e, deck_hash = %deck_hash, file_name = %name, "Failed to upload imported media");
+            missing.push(name.clone());
+            continue;
+        }
+
+        let existing = client
+            .query_opt("SELECT id FROM media_files WHERE hash = $1", &[&hash])
+            .await?;
+        let media_id: i64 = match existing {
+            Some(row) => row.get(0),
+            None => client
+                .query_one(
+                    "INSERT INTO media_files (hash, file_size) VALUES ($1, $2) RETURNING id",
+                    &[&hash, &size],
+                )
+                .await?
+                .get(0),
+        };
+        media_ids.insert(name, media_id);
+    }
+
+    let insert_reference = client
+        .prepare(
+            "INSERT INTO media_references (media_id, note_id, file_name) VALUES ($1, $2, $3)
+             ON CONFLICT DO NOTHING",
+        )
+        .await?;
+    for (note_id, names) in note_media {
+        for name in names {
+            if let Some(media_id) = media_ids.get(name.as_str()) {
+                client
+                    .execute(&insert_reference, &[media_id, note_id, name])
+                    .await?;
+            }
+        }
+    }
+    Ok((media_ids.len(), missing))
+}
+
+/// Parses the package and creates the deck tree owned by `user`
+pub async fn import_package(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    path: &Path,
+    deck_name: &str,
+    private: bool,
+) -> Return<ImportReport> {
+    let deck_name = cleanser::clean(deck_name.trim()).replace("::", " ");
+    if deck_name.is_empty() {
+        return Err(BadRequest("The deck needs a name".to_string()));
+    }
+
+    let (mut zip, entries) = open_package(path).await.map_err(|_| invalid_package())?;
+    if entries.contains_key("collection.anki21b") && !entries.contains_key("collection.anki21") {
+        return Err(invalid_package());
+    }
+    let &(collection_index, collection_size) = entries
+        .get("collection.anki21")
+        .or_else(|| entries.get("collection.anki2"))
+        .ok_or_else(invalid_package)?;
+    if collection_size > MAX_COLLECTION_BYTES {
+        return Err(invalid_package());
+    }
+
+    // SQLite needs a file, the collection can be larger than what we want to keep in memory
+    let collection =
+        TempFile(std::env::temp_dir().join(format!("import-{}.anki2", random_token())));
+    {
+        let mut reader = zip
+            .reader_with_entry(collection_index)
+            .await
+            .map_err(|_| invalid_package())?;
+        let mut file = tokio::fs::File::create(&collection.0)
+            .await
+            .map_err(|_| invalid_package())?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut written: u64 = 0;
+        loop {
+            let read = reader
+                .read(&mut buffer)
+                .await
+                .map_err(|_| invalid_package())?;
+            if read == 0 {
+                break;
+            }
+            // The size in the zip directory is only a claim, count what is actually written
+            written += read as u64;
+            if written > MAX_COLLECTION_BYTES {
+                return Err(invalid_package());
+            }
+            file.write_all(&buffer[..read])
+                .await
+                .map_err(|_| invalid_package())?;
+        }
+        file.flush().await.map_err(|_| invalid_package())?;
+    }
+    let collection_path = collection.0.clone();
+    let mut package = tokio::task::spawn_blocking(move || read_collection(&collection_path))
+        .await
+        .map_err(|_| invalid_package())?
+        .map_err(|_| invalid_package())?;
+    drop(collection);
+
+    if package.notes.is_empty() {
+        return Err(BadRequest("The package contains no notes".to_string()));
+    }
+    if package.notes.len() > MAX_NOTES {
+        return Err(BadRequest(format!(
+            "Packages with more than {MAX_NOTES} notes have to be uploaded with the add-on"
+        )));
+    }
+    if package.notetypes.is_empty() {
+        return Err(invalid_package());
+    }
+    relative_paths(&mut package);
+
+    // GUIDs other decks already use, repeats inside the package are found while importing
+    let guids: Vec<&str> = package
+        .notes
+        .iter()
+        .map(|note| note.guid.as_str())
+        .collect();
+    let existing: HashSet<String> = database::client(db_state)
+        .await?
+        .query("SELECT guid FROM notes WHERE guid = ANY($1)", &[&guids])
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    let root = tx
+        .query_one(
+            "INSERT INTO decks (name, description, human_hash, owner, parent, full_path, private, last_update)
+             VALUES ($1, '', replace(gen_random_uuid()::text, '-', ''), $2, NULL, $1, $3, NOW())
+             RETURNING id, human_hash",
+            &[&deck_name, &user.id(), &private],
+        )
+        .await?;
+    let root_id: DeckId = root.get(0);
+    let deck_hash: DeckHash = root.get(1);
+
+    // Parents are created before their children because shorter paths sort first
+    let mut deck_ids: BTreeMap<Vec<String>, DeckId> = BTreeMap::new();
+    deck_ids.insert(vec![], root_id);
+    let mut wanted: Vec<Vec<String>> = package
+        .notes
+        .iter()
+        .flat_map(|note| (1..=note.deck.len()).map(|len| note.deck[..len].to_vec()))
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    wanted.sort_by_key(Vec::len);
+    for path in wanted {
+        let name = cleanser::clean(path.last().map_or("", String::as_str));
+        let parent = deck_ids[&path[..path.len() - 1]];
+        let full_path = std::iter::once(deck_name.clone())
+            .chain(path.iter().map(|part| cleanser::clean(part)))
+            .collect::<Vec<_>>()
+            .join("::");
+        let id: DeckId = tx
+            .query_one(
+                "INSERT INTO decks (name, description, human_hash, owner, parent, full_path, private, last_update)
+                 VALUES ($1, '', replace(gen_random_uuid()::text, '-', ''), $2, $3, $4, $5, NOW())
+                 RETURNING id",
+                &[&name, &user.id(), &parent, &full_path, &private],
+            )
+            .await?
+            .get(0);
+        deck_ids.insert(path, id);
+    }
+
+    let commit_id: i32 = tx
+        .query_one(
+            "INSERT INTO commits (rationale, info, timestamp, deck, user_id)
+             VALUES ($1, $2, NOW(), $3, $4) RETURNING commit_id",
+            &[
+                &RATIONALE_DECK_CREATION,
+                &"Imported from an Anki package",
+                &root_id,
+                &user.id(),
+            ],
+        )
+        .await?
+        .get(0);
+
+    let used: HashSet<i64> = package.notes.iter().map(|note| note.notetype).collect();
+    let mut notetype_ids: HashMap<i64, i64> = HashMap::new();
+    for (anki_id, notetype) in package.notetypes.iter().filter(|(id, _)| used.contains(id)) {
+        let id: i64 = tx
+            .query_one(
+                "INSERT INTO notetype (guid, name, css, owner)
+                 VALUES (replace(gen_random_uuid()::text, '-', ''), $1, $2, $3) RETURNING id",
+                &[&cleanser::clean(&notetype.name), &notetype.css, &user.id()],
+            )
+            .await?
+            .get(0);
+        for (position, name) in (0u32..).zip(&notetype.fields) {
+            tx.execute(
+                "INSERT INTO notetype_field (notetype, name, protected, position)
+                 VALUES ($1, $2, false, $3)",
+                &[&id, &cleanser::clean(name), &position],
+            )
+            .await?;
+        }
+        for (name, qfmt, afmt) in &notetype.templates {
+            tx.execute(
+                "INSERT INTO notetype_template (notetype, name, qfmt, afmt) VALUES ($1, $2, $3, $4)",
+                &[&id, &cleanser::clean(name), qfmt, afmt],
+            )
+            .await?;
+        }
+        notetype_ids.insert(*anki_id, id);
+    }
+
+    let insert_note = tx
+        .prepare(
+            "INSERT INTO notes (guid, notetype, deck, last_update, reviewed)
+             VALUES ($1, $2, $3, NOW(), true) RETURNING id",
+        )
+        .await?;
+    let insert_field = tx
+        .prepare(
+            "INSERT INTO fields (note, position, content, commit, reviewed)
+             VALUES ($1, $2, $3, $4, true)",
+        )
+        .await?;
+    let insert_tag = tx
+        .prepare(
+            "INSERT INTO tags (note, content, reviewed, action, commit) VALUES ($1, $2, true, true, $3)",
+        )
+        .await?;
+
+    let mut imported = 0;
+    let mut empty = 0;
+    let mut unknown_notetype = 0;
+    let mut duplicates: HashSet<&str> = HashSet::new();
+    let mut seen: HashSet<&str> = HashSet::new();
+    let mut note_media: Vec<(i64, HashSet<String>)> = vec![];
+    for note in &package.notes {
+        if existing.contains(&note.guid) || !seen.insert(note.guid.as_str()) {
+            duplicates.insert(note.guid.as_str());
+            continue;
+        }
+        let Some(&notetype) = notetype_ids.get(&note.notetype) else {
+            unknown_notetype += 1;
+            continue;
+        };
+        let fields: Vec<String> = note
+            .fields
+            .iter()
+            .map(|field| cleanser::clean(&field.replace('\u{200B}', "")))
+            .collect();
+        if fields.first().is_none_or(|field| field.trim().is_empty()) {
+            empty += 1;
+            continue;
+        }
+
+        let note_id: i64 = tx
+            .query_one(
+                &insert_note,
+                &[&note.guid, &notetype, &deck_ids[&note.deck]],
+            )
+            .await?
+            .get(0);
+        let mut references = HashSet::new();
+        for (position, content) in (0u32..).zip(&fields) {
+            if content.is_empty() {
+                continue;
+            }
+            references.extend(extract_media_references(content));
+            tx.execute(&insert_field, &[&note_id, &position, content, &commit_id])
+                .await?;
+        }
+        let mut tags = HashSet::new();
+        for tag in &note.tags {
+            let tag = cleanser::clean(tag);
+            if !tag.is_empty() && tags.insert(tag.clone()) {
+                tx.execute(&insert_tag, &[&note_id, &tag, &commit_id])
+                    .await?;
+            }
+        }
+        if !references.is_empty() {
+            note_media.push((note_id, references));
+        }
+        imported += 1;
+    }
+
+    if imported == 0 {
+        return Err(BadRequest(
+            "None of the notes could be imported, they are all empty, use an unknown notetype or are already on AnkiCollab"
+                .to_string(),
+        ));
+    }
+    tx.commit().await?;
+
+    // The notes exist now, a failed upload only leaves a file missing
+    let (media_uploaded, mut missing_media) =
+        store_media(db_state, &mut zip, &entries, &deck_hash, &note_media).await?;
+    let missing_media_count = missing_media.len();
+    missing_media.truncate(REPORTED_NAMES);
+
+    let mut duplicate_guids: Vec<String> = duplicates.into_iter().map(str::to_string).collect();
+    duplicate_guids.sort();
+    let duplicate_count = duplicate_guids.len();
+    duplicate_guids.truncate(REPORTED_NAMES);
+    Ok(ImportReport {
+        deck_hash,
+        decks: deck_ids.len(),
+        notetypes: notetype_ids.len(),
+        notes: imported,
+        empty,
+        unknown_notetype,
+        duplicate_count,
+        duplicate_guids,
+        media_uploaded,
+        missing_media_count,
+        missing_media,
+    })
+}
@@ -0,0 +1,124 @@
+//! Small in-memory registry of background jobs so admins can see what is running
+//! and whether the last runs succeeded. Only the most recent `MAX_RECORDS` are kept,
+//! the registry is reset on restart.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use serde::Serialize;
+
+const MAX_RECORDS: usize = 100;
+const TIMESTAMP_FORMAT: &str = "%m/%d/%Y %H:%M:%S";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaskRecord {
+    pub id: u64,
+    pub name: String,
+    pub status: TaskStatus,
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    records: VecDeque<TaskRecord>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TaskRegistry {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl TaskRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn start(&self, name: &str) -> u64 {
+        let mut inner = self.inner.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        inner.next_id += 1;
+        let id = inner.next_id;
+        if inner.records.len() >= MAX_RECORDS {
+            inner.records.pop_front();
+        }
+        inner.records.push_back(TaskRecord {
+            id,
+            name: name.to_owned(),
+            status: TaskStatus::Running,
+            started_at: Utc::now().format(TIMESTAMP_FORMAT).to_string(),
+            finished_at: None,
+            error: None,
+        });
+        id
+    }
+
+    fn finish(&self, id: u64, result: Result<(), String>) {
+        let mut inner = self.inner.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        if let Some(record) = inner.records.iter_mut().find(|r| r.id == id) {
+            record.finished_at = Some(Utc::now().format(TIMESTAMP_FORMAT).to_string());
+            match result {
+                Ok(()) => record.status = TaskStatus::Succeeded,
+                Err(e) => {
+                    record.status = TaskStatus::Failed;
+                    record.error = Some(e);
+                }
+            }
+        }
+    }
+
+    /// Spawns `fut` on the runtime and records its outcome under `name`.
+    pub fn spawn<F>(&self, name: &str, fut: F)
+    where
+        F: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let id = self.start(name);
+        let registry = self.clone();
+        let name = name.to_owned();
+        tokio::spawn(async move {
+            let result = fut.await;
+            if let Err(e) = &result {
+                tracing::warn!(task = %name, error = %e, "Background task failed");
+            }
+            registry.finish(id, result);
+        });
+    }
+
+    /// Runs the job `job` creates every `period`, starting right away. Every run is recorded like
+    /// a spawned task.
+    pub fn spawn_periodic<F, Fut>(&self, name: &str, period: Duration, job: F)
+    where
+        F: Fn() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let registry = self.clone();
+        let name = name.to_owned();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                registry.spawn(&name, job());
+            }
+        });
+    }
+
+    /// Newest first
+    #[must_use]
+    pub fn snapshot(&self) -> Vec<TaskRecord> {
+        let inner = self.inner.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        inner.records.iter().rev().cloned().collect()
+    }
+}
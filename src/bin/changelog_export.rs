@@ -0,0 +1,184 @@
+//! Exports the complete changelog history of a deck, e.g. to publish release notes.
+//!
+//! ```text
+//! changelog_export <DECK_HASH> --format markdown --since 2024-01-01 --until 2024-06-30 -o notes.md
+//! ```
+//!
+//! Reads `DATABASE_URL` from the environment or the `.env` file, like the website.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+use clap::{Parser, ValueEnum};
+use serde::Serialize;
+use tokio_postgres::NoTls;
+use website::csv;
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Markdown,
+    Json,
+    Csv,
+}
+
+#[derive(Parser)]
+#[command(about = "Export the changelog history of a deck")]
+struct Args {
+    /// Deck key (human hash) of the deck to export
+    deck: String,
+
+    #[arg(short, long, value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+
+    /// Only include entries on or after this date (YYYY-MM-DD)
+    #[arg(long)]
+    since: Option<NaiveDate>,
+
+    /// Only include entries on or before this date (YYYY-MM-DD)
+    #[arg(long)]
+    until: Option<NaiveDate>,
+
+    /// Only export the changelog of the deck itself, not of its subdecks
+    #[arg(long)]
+    exclude_subdecks: bool,
+
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    id: i64,
+    deck: String,
+    deck_hash: String,
+    date: String,
+    timestamp: String,
+    /// The Markdown source, entries from before Markdown only have their displayed text
+    message: String,
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+async fn fetch_entries(
+    client: &tokio_postgres::Client,
+    args: &Args,
+) -> Result<(String, Vec<Entry>), BoxError> {
+    let root = client
+        .query_opt(
+            "SELECT full_path FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[&args.deck],
+        )
+        .await?
+        .ok_or_else(|| format!("Deck {} not found", args.deck))?;
+    let deck_name: String = root.get(0);
+
+    let rows = client
+        .query(
+            "
+            WITH RECURSIVE tree AS (
+                SELECT id FROM decks WHERE human_hash = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN tree ON d.parent = tree.id
+                WHERE $4 AND d.deleted_at IS NULL
+            )
+            SELECT c.id, d.full_path, d.human_hash,
+                   TO_CHAR(c.timestamp, 'YYYY-MM-DD'),
+                   TO_CHAR(c.timestamp, 'YYYY-MM-DD HH24:MI:SS'),
+                   COALESCE(c.source, c.message)
+            FROM changelogs c
+            JOIN tree ON tree.id = c.deck
+            JOIN decks d ON d.id = c.deck
+            WHERE ($2::DATE IS NULL OR c.timestamp::DATE >= $2)
+              AND ($3::DATE IS NULL OR c.timestamp::DATE <= $3)
+            ORDER BY c.timestamp DESC, c.id DESC",
+            &[&args.deck, &args.since, &args.until, &!args.exclude_subdecks],
+        )
+        .await?;
+
+    let entries = rows
+        .into_iter()
+        .map(|row| Entry {
+            id: row.get(0),
+            deck: row.get(1),
+            deck_hash: row.get(2),
+            date: row.get(3),
+            timestamp: row.get(4),
+            message: row.get(5),
+        })
+        .collect();
+    Ok((deck_name, entries))
+}
+
+fn render_markdown(deck_name: &str, entries: &[Entry]) -> String {
+    let mut out = format!("# Changelog: {deck_name}\n");
+    let mut current_date = "";
+    for entry in entries {
+        if entry.date != current_date {
+            current_date = &entry.date;
+            let _ = write!(out, "\n## {current_date}\n\n");
+        }
+        // Keep multi-line messages inside their list item
+        let message = entry.message.trim().replace('\n', "\n  ");
+        if entry.deck == deck_name {
+            let _ = writeln!(out, "- {message}");
+        } else {
+            let _ = writeln!(out, "- **{}**: {message}", entry.deck);
+        }
+    }
+    out
+}
+
+fn render_csv(entries: &[Entry]) -> String {
+    let mut out = String::from("id,deck,deck_hash,timestamp,message\n");
+    for entry in entries {
+        let _ = writeln!(
+            out,
+            "{},{},{},{},{}",
+            entry.id,
+            csv::field(&entry.deck),
+            csv::field(&entry.deck_hash),
+            entry.timestamp,
+            csv::field(&entry.message)
+        );
+    }
+    out
+}
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    let args = Args::parse();
+    if let (Some(since), Some(until)) = (args.since, args.until) {
+        if since > until {
+            return Err("--since must not be after --until".into());
+        }
+    }
+
+    dotenvy::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set")?;
+    let (client, connection) = tokio_postgres::connect(&database_url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Database connection error: {e}");
+        }
+    });
+
+    let (deck_name, entries) = fetch_entries(&client, &args).await?;
+    let rendered = match args.format {
+        Format::Markdown => render_markdown(&deck_name, &entries),
+        Format::Json => serde_json::to_string_pretty(&entries)? + "\n",
+        Format::Csv => render_csv(&entries),
+    };
+
+    match &args.output {
+        Some(path) => {
+            fs::write(path, rendered)?;
+            eprintln!("Exported {} changelog entries to {}", entries.len(), path.display());
+        }
+        None => io::stdout().write_all(rendered.as_bytes())?,
+    }
+    Ok(())
+}
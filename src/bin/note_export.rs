@@ -0,0 +1,71 @@
+//! Exports the notes of a deck as CSV or JSON lines, e.g. for backups or analysis.
+//!
+//! ```text
+//! note_export <DECK_HASH> --format jsonl --include-pending --resolve-inherited -o notes.jsonl
+//! ```
+//!
+//! Reads `DATABASE_URL` from the environment or the `.env` file, like the website.
+
+use std::fs;
+use std::io::{self, Write as _};
+use std::path::PathBuf;
+
+use clap::Parser;
+use tokio_postgres::NoTls;
+use website::note_export;
+
+#[derive(Parser)]
+#[command(about = "Export the notes of a deck")]
+struct Args {
+    /// Deck key (human hash) of the deck to export, subdecks are included
+    deck: String,
+
+    #[arg(short, long, value_enum, default_value_t = note_export::Format::Csv)]
+    format: note_export::Format,
+
+    /// Also export unreviewed notes and open suggestions
+    #[arg(long)]
+    include_pending: bool,
+
+    /// Export subscribed notes with the content of their base note
+    #[arg(long)]
+    resolve_inherited: bool,
+
+    /// Write to this file instead of stdout
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[tokio::main]
+async fn main() -> Result<(), BoxError> {
+    let args = Args::parse();
+
+    dotenvy::dotenv().ok();
+    let database_url = std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL must be set")?;
+    let (client, connection) = tokio_postgres::connect(&database_url, NoTls).await?;
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("Database connection error: {e}");
+        }
+    });
+
+    let options = note_export::ExportOptions {
+        include_pending: args.include_pending,
+        resolve_inherited: args.resolve_inherited,
+    };
+    let rows = note_export::export_notes(&client, &args.deck, &options)
+        .await?
+        .ok_or_else(|| format!("Deck {} not found", args.deck))?;
+    let rendered = note_export::render(&rows, args.format, &options)?;
+
+    match &args.output {
+        Some(path) => {
+            fs::write(path, rendered)?;
+            eprintln!("Exported {} notes to {}", rows.len(), path.display());
+        }
+        None => io::stdout().write_all(rendered.as_bytes())?,
+    }
+    Ok(())
+}
@@ -1,22 +1,158 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt::Write as _;
 use std::sync::Arc;
 
+use pulldown_cmark::{html, Options, Parser};
+use serde::Serialize;
+
 use crate::cleanser;
+use crate::commit_manager::get_string_from_rationale;
 use crate::database;
+use crate::error::Error::{BadRequest, ChangelogNotFound, DeckNotFound};
+use crate::note_history;
+use crate::permissions::{self, Capability};
 use crate::structs::ChangelogInfo;
-use crate::Return;
+use crate::user::User;
+use crate::{DeckId, Return};
+
+pub const PAGE_SIZE: i64 = 20;
+const MAX_LINKED_COMMITS: usize = 50;
+/// Drafts only summarize the most recent commits, like the commit links
+const MAX_DRAFT_COMMITS: i64 = MAX_LINKED_COMMITS as i64;
+
+// Entries without a Markdown source predate it and are plain text, so keep their line breaks
+const CHANGELOG_COLUMNS: &str = "
+    c.id,
+    CASE WHEN c.source IS NULL THEN replace(c.message, E'\\n', '<br>') ELSE c.message END,
+    TO_CHAR(c.timestamp, 'MM/DD/YYYY HH24:MI:SS'),
+    c.edited_at IS NOT NULL,
+    ARRAY(SELECT cc.commit_id FROM changelog_commits cc WHERE cc.changelog_id = c.id ORDER BY cc.commit_id)";
+
+fn changelog_row(row: &tokio_postgres::Row) -> ChangelogInfo {
+    ChangelogInfo {
+        id: row.get(0),
+        message: row.get(1),
+        timestamp: row.get(2),
+        edited: row.get(3),
+        commits: row.get(4),
+    }
+}
+
+#[derive(Serialize)]
+pub struct EditableChangelog {
+    pub id: i64,
+    pub deck_id: DeckId,
+    pub deck_hash: String,
+    pub deck_name: String,
+    pub timestamp: String,
+    /// Markdown, or the stored message for entries written before Markdown support
+    pub source: String,
+    pub commits: Vec<i32>,
+}
+
+#[derive(Serialize)]
+pub struct ChangelogRevision {
+    pub message: String,
+    pub editor: Option<String>,
+    pub timestamp: String,
+}
+
+/// Renders Markdown to HTML that is safe to embed in pages
+pub fn render_markdown(source: &str) -> String {
+    let mut options = Options::empty();
+    options.insert(Options::ENABLE_STRIKETHROUGH);
+    options.insert(Options::ENABLE_TABLES);
+    let mut rendered = String::new();
+    html::push_html(&mut rendered, Parser::new_ext(source, options));
+    cleanser::clean(&rendered)
+}
+
+/// Parses "12, #15 20" into commit ids
+pub fn parse_commit_ids(input: &str) -> Return<Vec<i32>> {
+    input
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .map(|part| part.trim().trim_start_matches('#'))
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            part.parse::<i32>()
+                .map_err(|_| BadRequest(format!("{part} is not a commit id")))
+        })
+        .collect()
+}
+
+/// Replaces the commits linked to an entry. Only commits to the same top-level deck can be linked.
+async fn link_commits(
+    tx: &tokio_postgres::Transaction<'_>,
+    changelog_id: i64,
+    deck_id: DeckId,
+    commits: &[i32],
+) -> Return<()> {
+    let commits: Vec<i32> = commits.iter().copied().collect::<BTreeSet<_>>().into_iter().collect();
+    if commits.len() > MAX_LINKED_COMMITS {
+        return Err(BadRequest(format!(
+            "A changelog entry can link at most {MAX_LINKED_COMMITS} commits"
+        )));
+    }
+
+    tx.execute(
+        "DELETE FROM changelog_commits WHERE changelog_id = $1",
+        &[&changelog_id],
+    )
+    .await?;
+    if commits.is_empty() {
+        return Ok(());
+    }
+
+    let linked = tx
+        .execute(
+            "
+            WITH RECURSIVE up AS (
+                SELECT id, parent FROM decks WHERE id = $2
+                UNION ALL
+                SELECT d.id, d.parent FROM decks d JOIN up ON d.id = up.parent
+            ),
+            tree AS (
+                SELECT id FROM up WHERE parent IS NULL
+                UNION ALL
+                SELECT d.id FROM decks d JOIN tree ON d.parent = tree.id
+            )
+            INSERT INTO changelog_commits (changelog_id, commit_id)
+            SELECT $1, c.commit_id FROM commits c
+            WHERE c.commit_id = ANY($3) AND c.deck IN (SELECT id FROM tree)",
+            &[&changelog_id, &deck_id, &commits],
+        )
+        .await?;
+    if linked as usize != commits.len() {
+        return Err(BadRequest("Only commits to this deck can be linked".to_string()));
+    }
+    Ok(())
+}
 
 pub async fn insert_new_changelog(
     db_state: &Arc<database::AppState>,
     deck_hash: &String,
-    message: &str,
+    source: &str,
+    commits: &[i32],
 ) -> Return<()> {
-    let query = r"
-        INSERT INTO changelogs (deck, message, timestamp)
-        VALUES ((SELECT id FROM decks WHERE human_hash = $1), $2, NOW())
-    ";
-    let client = database::client(db_state).await?;
-    let cleaned = cleanser::clean(message);
-    client.execute(query, &[&deck_hash, &cleaned]).await?;
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    let deck_id: DeckId = tx
+        .query_opt("SELECT id FROM decks WHERE human_hash = $1", &[&deck_hash])
+        .await?
+        .ok_or(DeckNotFound)?
+        .get(0);
+    let changelog_id: i64 = tx
+        .query_one(
+            "INSERT INTO changelogs (deck, message, source, timestamp)
+             VALUES ($1, $2, $3, NOW()) RETURNING id",
+            &[&deck_id, &render_markdown(source), &source],
+        )
+        .await?
+        .get(0);
+    link_commits(&tx, changelog_id, deck_id, commits).await?;
+
+    tx.commit().await?;
     Ok(())
 }
 
@@ -26,40 +162,346 @@ pub async fn get_changelogs(
 ) -> Return<Vec<ChangelogInfo>> {
     let client = database::client(db_state).await?;
 
-    let query = "SELECT id, message, TO_CHAR(timestamp, 'MM/DD/YYYY HH24:MI:SS') AS timestamp FROM changelogs WHERE deck = (SELECT id FROM decks WHERE human_hash = $1) ORDER BY timestamp DESC LIMIT 5";
+    let query = format!(
+        "SELECT {CHANGELOG_COLUMNS} FROM changelogs c
+         WHERE c.deck = (SELECT id FROM decks WHERE human_hash = $1)
+         ORDER BY c.timestamp DESC LIMIT 5"
+    );
 
     let rows = client
-        .query(query, &[&deck_hash])
+        .query(&query, &[&deck_hash])
         .await?
-        .into_iter()
-        .map(|row| ChangelogInfo {
-            id: row.get(0),
-            message: row.get(1),
-            timestamp: row.get(2),
-        })
+        .iter()
+        .map(changelog_row)
         .collect::<Vec<_>>();
 
     Ok(rows)
 }
 
+/// One page (1-based) of the full history and the total number of entries
+pub async fn get_changelog_page(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+    page: i64,
+) -> Return<(Vec<ChangelogInfo>, i64)> {
+    let client = database::client(db_state).await?;
+
+    let total: i64 = client
+        .query_one("SELECT COUNT(*) FROM changelogs WHERE deck = $1", &[&deck_id])
+        .await?
+        .get(0);
+    let query = format!(
+        "SELECT {CHANGELOG_COLUMNS} FROM changelogs c
+         WHERE c.deck = $1
+         ORDER BY c.timestamp DESC, c.id DESC
+         LIMIT $2 OFFSET $3"
+    );
+    let offset = (page.max(1) - 1) * PAGE_SIZE;
+    let rows = client
+        .query(&query, &[&deck_id, &PAGE_SIZE, &offset])
+        .await?
+        .iter()
+        .map(changelog_row)
+        .collect();
+
+    Ok((rows, total))
+}
+
+pub async fn get_editable_changelog(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    id: i64,
+) -> Return<EditableChangelog> {
+    let client = database::client(db_state).await?;
+    let row = client
+        .query_opt(
+            "SELECT c.id, c.deck, d.human_hash, d.full_path,
+                    TO_CHAR(c.timestamp, 'MM/DD/YYYY HH24:MI:SS'),
+                    COALESCE(c.source, c.message),
+                    ARRAY(SELECT cc.commit_id FROM changelog_commits cc WHERE cc.changelog_id = c.id ORDER BY cc.commit_id)
+             FROM changelogs c JOIN decks d ON d.id = c.deck
+             WHERE c.id = $1",
+            &[&id],
+        )
+        .await?
+        .ok_or(ChangelogNotFound)?;
+    let entry = EditableChangelog {
+        id: row.get(0),
+        deck_id: row.get(1),
+        deck_hash: row.get(2),
+        deck_name: row.get(3),
+        timestamp: row.get(4),
+        source: row.get(5),
+        commits: row.get(6),
+    };
+    permissions::require(db_state, user, entry.deck_id, Capability::EditDeckSettings).await?;
+    Ok(entry)
+}
+
+/// Saves a new version of an entry and keeps the previous one as a revision. Returns the deck hash.
+pub async fn update_changelog(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    id: i64,
+    source: &str,
+    commits: &[i32],
+) -> Return<String> {
+    if source.trim().is_empty() {
+        return Err(BadRequest("The changelog message cannot be empty".to_string()));
+    }
+
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    let row = tx
+        .query_opt(
+            "SELECT c.deck, d.human_hash, c.message, c.source
+             FROM changelogs c JOIN decks d ON d.id = c.deck
+             WHERE c.id = $1 FOR UPDATE OF c",
+            &[&id],
+        )
+        .await?
+        .ok_or(ChangelogNotFound)?;
+    let deck_id: DeckId = row.get(0);
+    let deck_hash: String = row.get(1);
+    permissions::require(db_state, user, deck_id, Capability::EditDeckSettings).await?;
+
+    let old_message: String = row.get(2);
+    let old_source: Option<String> = row.get(3);
+    tx.execute(
+        "INSERT INTO changelog_revisions (changelog_id, message, source, edited_by)
+         VALUES ($1, $2, $3, $4)",
+        &[&id, &old_message, &old_source, &user.id()],
+    )
+    .await?;
+    tx.execute(
+        "UPDATE changelogs SET message = $2, source = $3, edited_at = NOW() WHERE id = $1",
+        &[&id, &render_markdown(source), &source],
+    )
+    .await?;
+    link_commits(&tx, id, deck_id, commits).await?;
+
+    tx.commit().await?;
+    Ok(deck_hash)
+}
+
+pub async fn get_revisions(
+    db_state: &Arc<database::AppState>,
+    id: i64,
+) -> Return<Vec<ChangelogRevision>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            "SELECT CASE WHEN r.source IS NULL THEN replace(r.message, E'\\n', '<br>') ELSE r.message END,
+                    u.username,
+                    TO_CHAR(r.created_at, 'MM/DD/YYYY HH24:MI:SS')
+             FROM changelog_revisions r
+             LEFT JOIN users u ON u.id = r.edited_by
+             WHERE r.changelog_id = $1
+             ORDER BY r.created_at DESC, r.id DESC",
+            &[&id],
+        )
+        .await?
+        .iter()
+        .map(|row| ChangelogRevision {
+            message: row.get(0),
+            editor: row.get(1),
+            timestamp: row.get(2),
+        })
+        .collect();
+    Ok(rows)
+}
+
 pub async fn delete_changelog(
     db_state: &Arc<database::AppState>,
     id: i64,
-    user_id: i32,
+    user: &User,
 ) -> Result<String, Box<dyn std::error::Error>> {
-    let query = r"
-        DELETE FROM changelogs
-        WHERE id = $1 AND deck IN (SELECT id FROM decks WHERE owner = $2)
-        RETURNING deck
-    ";
     let client = database::client(db_state).await?;
-    let row = match client.query_opt(query, &[&id, &user_id]).await? {
+    let row = match client
+        .query_opt("SELECT deck FROM changelogs WHERE id = $1", &[&id])
+        .await?
+    {
         Some(row) => row,
         None => return Err("Deck not found".into()),
     };
     let deck_id: i64 = row.get(0);
+    permissions::require(db_state, user, deck_id, Capability::EditDeckSettings).await?;
+
+    client
+        .execute("DELETE FROM changelogs WHERE id = $1", &[&id])
+        .await?;
     let deck_hash_query = "SELECT human_hash FROM decks WHERE id = $1";
     let deck_hash_row = client.query_one(deck_hash_query, &[&deck_id]).await?;
     let deck_hash: String = deck_hash_row.get(0);
     Ok(deck_hash)
 }
+
+#[derive(Serialize)]
+pub struct ChangelogDraft {
+    /// Markdown, empty if nothing was approved since the last entry
+    pub message: String,
+    pub commits: Vec<i32>,
+}
+
+#[derive(Default)]
+struct DraftCounts {
+    notes: BTreeSet<i64>,
+    new_notes: usize,
+    field_changes: usize,
+    tags_added: usize,
+    tags_removed: usize,
+    moved: usize,
+    deleted: usize,
+}
+
+impl DraftCounts {
+    fn summary(&self) -> String {
+        let plural = |count: usize, word: &str| {
+            format!("{count} {word}{}", if count == 1 { "" } else { "s" })
+        };
+        let mut parts = vec![];
+        if self.new_notes > 0 {
+            parts.push(plural(self.new_notes, "new note"));
+        }
+        if self.field_changes > 0 {
+            parts.push(plural(self.field_changes, "field change"));
+        }
+        if self.tags_added > 0 {
+            parts.push(format!("{} added", plural(self.tags_added, "tag")));
+        }
+        if self.tags_removed > 0 {
+            parts.push(format!("{} removed", plural(self.tags_removed, "tag")));
+        }
+        if self.moved > 0 {
+            parts.push(format!("{} moved", plural(self.moved, "note")));
+        }
+        if self.deleted > 0 {
+            parts.push(format!("{} deleted", plural(self.deleted, "note")));
+        }
+        if parts.is_empty() {
+            parts.push(format!("{} changed", plural(self.notes.len(), "note")));
+        }
+        parts.join(", ")
+    }
+}
+
+/// Summarizes the commits approved in the deck (and its subdecks) since its last changelog
+/// entry, grouped by rationale and subdeck.
+pub async fn draft_since_last_entry(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+) -> Return<ChangelogDraft> {
+    let client = database::client(db_state).await?;
+
+    let commit_rows = client
+        .query(
+            "
+            WITH RECURSIVE up AS (
+                SELECT id, parent FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id, d.parent FROM decks d JOIN up ON d.id = up.parent
+            ),
+            tree AS (
+                SELECT id FROM up WHERE parent IS NULL
+                UNION ALL
+                SELECT d.id FROM decks d JOIN tree ON d.parent = tree.id
+            )
+            SELECT e.commit_id, c.rationale
+            FROM note_events e
+            JOIN commits c ON c.commit_id = e.commit_id
+            WHERE e.approved IS TRUE
+              AND c.deck IN (SELECT id FROM tree)
+              AND e.created_at::TIMESTAMPTZ > COALESCE(
+                  (SELECT MAX(timestamp)::TIMESTAMPTZ FROM changelogs WHERE deck = $1),
+                  '-infinity'::TIMESTAMPTZ
+              )
+            GROUP BY e.commit_id, c.rationale
+            ORDER BY MAX(e.created_at) DESC
+            LIMIT $2",
+            // Same tree `link_commits` accepts, one extra row tells whether the draft is cut off
+            &[&deck_id, &(MAX_DRAFT_COMMITS + 1)],
+        )
+        .await?;
+    let truncated = commit_rows.len() > MAX_LINKED_COMMITS;
+    let commit_rows = &commit_rows[..commit_rows.len().min(MAX_LINKED_COMMITS)];
+    if commit_rows.is_empty() {
+        return Ok(ChangelogDraft {
+            message: String::new(),
+            commits: vec![],
+        });
+    }
+
+    // Subdeck of every note, relative to the deck the changelog belongs to
+    let deck_rows = client
+        .query(
+            "
+            WITH RECURSIVE tree AS (
+                SELECT id, name::TEXT AS path FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id, tree.path || '::' || d.name FROM decks d JOIN tree ON d.parent = tree.id
+            )
+            SELECT n.id, tree.path
+            FROM notes n JOIN tree ON tree.id = n.deck
+            WHERE n.id IN (SELECT note_id FROM note_events WHERE commit_id = ANY($2))",
+            &[
+                &deck_id,
+                &commit_rows.iter().map(|row| row.get(0)).collect::<Vec<i32>>(),
+            ],
+        )
+        .await?;
+    let note_decks: HashMap<i64, String> = deck_rows
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+
+    let mut groups: BTreeMap<&str, BTreeMap<String, DraftCounts>> = BTreeMap::new();
+    let mut commits = vec![];
+    for row in commit_rows {
+        let commit_id: i32 = row.get(0);
+        let rationale = get_string_from_rationale(row.get(1));
+        commits.push(commit_id);
+
+        for note in note_history::fetch_commit_history(&client, commit_id).await? {
+            // Notes moved out of this deck since then are not part of its changelog
+            let Some(subdeck) = note_decks.get(&note.note_id) else {
+                continue;
+            };
+            let counts = groups
+                .entry(rationale)
+                .or_default()
+                .entry(subdeck.clone())
+                .or_default();
+            counts.notes.insert(note.note_id);
+            if note.event_types.iter().any(|e| e == "note_created") {
+                counts.new_notes += 1;
+            }
+            counts.field_changes += note.field_added + note.field_updated + note.field_removed;
+            counts.tags_added += note.tag_added;
+            counts.tags_removed += note.tag_removed;
+            counts.moved += usize::from(note.moved);
+            counts.deleted += usize::from(note.deleted);
+        }
+    }
+
+    let mut message = String::new();
+    for (rationale, subdecks) in &groups {
+        let _ = writeln!(message, "**{rationale}**\n");
+        for (subdeck, counts) in subdecks {
+            let _ = writeln!(message, "- {subdeck}: {}", counts.summary());
+        }
+        message.push('\n');
+    }
+    if truncated {
+        let _ = writeln!(
+            message,
+            "_Only the latest {MAX_DRAFT_COMMITS} commits are summarized, older ones were left out._"
+        );
+    }
+
+    commits.sort_unstable();
+    Ok(ChangelogDraft {
+        message: message.trim_end().to_string(),
+        commits,
+    })
+}
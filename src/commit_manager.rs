@@ -10,7 +10,19 @@ use std::sync::Arc;
 
 extern crate htmldiff;
 
-const fn get_string_from_rationale(input: i32) -> &'static str {
+/// Rationale ids the site writes itself, see `get_string_from_rationale`
+pub const RATIONALE_DECK_CREATION: i32 = 1;
+pub const RATIONALE_UPDATED_TAGS: i32 = 7;
+pub const RATIONALE_NEW_TAGS: i32 = 8;
+pub const RATIONALE_BULK_SUGGESTION: i32 = 9;
+
+/// Bulk tools (spreadsheet import, find and replace, tag tools) stop here to keep a single
+/// commit reviewable
+pub const MAX_BULK_NOTES: usize = 2000;
+/// Notes the bulk tools show before anything is written
+pub const BULK_PREVIEW_NOTES: usize = 50;
+
+pub(crate) const fn get_string_from_rationale(input: i32) -> &'static str {
     match input {
         0 => "None",
         1 => "Deck Creation",
@@ -0,0 +1,218 @@
+//! CSRF protection for cookie-authenticated requests.
+//!
+//! Every browser session gets a random `__Host-ankicollabcsrf` cookie. The token we embed
+//! in pages is an HMAC of that cookie, so it cannot be forged without the server secret.
+//! All non-GET requests must echo the token back, either in the `X-CSRF-Token` header
+//! (set by `static/js/csrf.js` for every `fetch`) or in a `csrf_token` form field.
+//! Requests carrying a Bearer token are not cookie authenticated and are exempt.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::{
+    body::{Body, Bytes},
+    extract::{Request, State},
+    http::{header, HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use axum_extra::extract::cookie::CookieJar;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use cookie::{Cookie as CookieBuilder, SameSite};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::database::AppState;
+use crate::error::Error;
+
+type HmacSha256 = Hmac<Sha256>;
+
+const CSRF_COOKIE_NAME: &str = "__Host-ankicollabcsrf";
+const CSRF_HEADER_NAME: &str = "x-csrf-token";
+const CSRF_FORM_FIELD: &str = "csrf_token";
+/// How much of an urlencoded body is searched for the form field
+const MAX_FORM_BODY: usize = 64 * 1024;
+
+tokio::task_local! {
+    static CSRF_TOKEN: String;
+}
+
+#[derive(Clone)]
+pub struct CsrfService {
+    secret: Arc<Vec<u8>>,
+    cookie_secure: bool,
+}
+
+impl std::fmt::Debug for CsrfService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CsrfService")
+            .field("secret", &"<redacted>")
+            .field("cookie_secure", &self.cookie_secure)
+            .finish()
+    }
+}
+
+impl CsrfService {
+    pub fn new(secret: Vec<u8>, cookie_secure: bool) -> Result<Self, &'static str> {
+        if secret.len() < 32 {
+            return Err("CSRF secret must be at least 32 bytes");
+        }
+        Ok(Self {
+            secret: Arc::new(secret),
+            cookie_secure,
+        })
+    }
+
+    fn mac(&self, session_id: &str) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(session_id.as_bytes());
+        mac
+    }
+
+    fn token_for(&self, session_id: &str) -> String {
+        URL_SAFE_NO_PAD.encode(self.mac(session_id).finalize().into_bytes())
+    }
+
+    fn verify(&self, session_id: &str, token: &str) -> bool {
+        URL_SAFE_NO_PAD
+            .decode(token.trim())
+            .is_ok_and(|sig| self.mac(session_id).verify_slice(&sig).is_ok())
+    }
+
+    fn new_session_id() -> String {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        URL_SAFE_NO_PAD.encode(bytes)
+    }
+
+    fn session_cookie(&self, session_id: String) -> String {
+        // Session cookie on purpose: a fresh token for every browser session
+        CookieBuilder::build((CSRF_COOKIE_NAME, session_id))
+            .path("/")
+            .secure(self.cookie_secure)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .to_string()
+    }
+}
+
+/// Tera function so templates can embed the token: `{{ csrf_token() }}`.
+/// Outside of a request (e.g. the error page rendered by the outer middleware) it yields an empty string.
+pub fn csrf_token_function(_args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let token = CSRF_TOKEN.try_with(Clone::clone).unwrap_or_default();
+    Ok(tera::Value::String(token))
+}
+
+const fn is_safe_method(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+fn has_bearer_token(request: &Request) -> bool {
+    request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.len() > 7 && v[..7].eq_ignore_ascii_case("bearer "))
+}
+
+fn is_urlencoded_form(request: &Request) -> bool {
+    request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"))
+}
+
+/// Our tokens are base64url, so they never need percent-decoding. With `complete` false the
+/// last pair may still be cut off and is not looked at.
+fn token_from_form(body: &[u8], complete: bool) -> Option<String> {
+    let mut pairs: Vec<&[u8]> = body.split(|&b| b == b'&').collect();
+    if !complete {
+        pairs.pop();
+    }
+    pairs.into_iter().find_map(|pair| {
+        let value = pair.strip_prefix(CSRF_FORM_FIELD.as_bytes())?.strip_prefix(b"=")?;
+        std::str::from_utf8(value).ok().map(str::to_string)
+    })
+}
+
+/// Reads the form until the token field is complete and hands on a body that starts with the
+/// bytes already read. Our forms put the token first, so large forms (e.g. pasted spreadsheets)
+/// are not buffered completely, only the first `MAX_FORM_BODY` bytes are searched.
+async fn token_from_form_body(body: Body) -> Result<(Option<String>, Body), Error> {
+    let mut stream = body.into_data_stream();
+    let mut buffered = Vec::new();
+    let token = loop {
+        let Some(chunk) = stream.next().await else {
+            break token_from_form(&buffered, true);
+        };
+        let chunk = chunk.map_err(|_| Error::BadRequest("Invalid form body".to_string()))?;
+        buffered.extend_from_slice(&chunk);
+        let token = token_from_form(&buffered, false);
+        if token.is_some() || buffered.len() > MAX_FORM_BODY {
+            break token;
+        }
+    };
+    let prefix = futures_util::stream::once(async move { Ok(Bytes::from(buffered)) });
+    Ok((token, Body::from_stream(prefix.chain(stream))))
+}
+
+pub async fn csrf_middleware(
+    State(appstate): State<Arc<AppState>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Error> {
+    let csrf = &appstate.csrf;
+    let jar = CookieJar::from_headers(request.headers());
+    let existing_session = jar.get(CSRF_COOKIE_NAME).map(|c| c.value().to_string());
+
+    let mut request = request;
+    if !is_safe_method(request.method()) && !has_bearer_token(&request) {
+        let Some(session_id) = existing_session.as_deref() else {
+            return Err(Error::InvalidCsrfToken);
+        };
+
+        let header_token = request
+            .headers()
+            .get(CSRF_HEADER_NAME)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        let token = if let Some(token) = header_token {
+            Some(token)
+        } else if is_urlencoded_form(&request) {
+            // Look for the hidden field, then hand the form on untouched
+            let (parts, body) = request.into_parts();
+            let (token, body) = token_from_form_body(body).await?;
+            request = Request::from_parts(parts, body);
+            token
+        } else {
+            None
+        };
+
+        if !token.is_some_and(|t| csrf.verify(session_id, &t)) {
+            return Err(Error::InvalidCsrfToken);
+        }
+    }
+
+    let (session_id, new_session) = match existing_session {
+        Some(id) => (id, false),
+        None => (CsrfService::new_session_id(), true),
+    };
+    let token = csrf.token_for(&session_id);
+
+    let mut response = CSRF_TOKEN.scope(token, next.run(request)).await;
+
+    if new_session {
+        if let Ok(value) = HeaderValue::from_str(&csrf.session_cookie(session_id)) {
+            // append, handlers may set their own cookies (e.g. on login)
+            response.headers_mut().append(header::SET_COOKIE, value);
+        }
+    }
+
+    Ok(response)
+}
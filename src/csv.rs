@@ -0,0 +1,11 @@
+//! Minimal CSV writing for the exports, quoting as RFC 4180 describes.
+
+/// Quotes the value if it contains a separator, a quote or a line break
+#[must_use]
+pub fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
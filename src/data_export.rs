@@ -0,0 +1,322 @@
+//! "Download my data" (GDPR Art. 20).
+//!
+//! Requesting an export inserts a pending row and builds the JSON bundle in the
+//! background. The random token is the download link, it is only shown once and stored
+//! hashed. It stays valid for `EXPORT_TTL_HOURS` and only works for the account that
+//! requested it, expired bundles are purged by a periodic job.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+use tokio_postgres::Client;
+
+use crate::database;
+use crate::error::Error::{BadRequest, ExportNotFound};
+use crate::user::{random_token, token_hash, user_hash, User};
+use crate::Return;
+
+const EXPORT_TTL_HOURS: i32 = 48;
+/// One new export per hour is plenty and keeps people from hammering the job
+const EXPORT_COOLDOWN_MINUTES: i32 = 60;
+
+#[derive(Serialize)]
+pub struct ExportInfo {
+    pub status: String,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+/// Most recent export that has not expired yet, shown on the profile page
+pub async fn latest_export(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+) -> Return<Option<ExportInfo>> {
+    let client = database::client(db_state).await?;
+    let row = client
+        .query_opt(
+            "SELECT status,
+                    TO_CHAR(created_at, 'MM/DD/YYYY HH24:MI:SS'),
+                    TO_CHAR(expires_at, 'MM/DD/YYYY HH24:MI:SS')
+             FROM data_exports
+             WHERE user_id = $1 AND expires_at > NOW()
+             ORDER BY created_at DESC
+             LIMIT 1",
+            &[&user_id],
+        )
+        .await?;
+    Ok(row.map(|row| ExportInfo {
+        status: row.get(0),
+        created_at: row.get(1),
+        expires_at: row.get(2),
+    }))
+}
+
+/// Queues a new export for `user` and returns the id of the pending row and its download token
+pub async fn request_export(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+) -> Return<(i64, String)> {
+    let client = database::client(db_state).await?;
+
+    let recent: i64 = client
+        .query_one(
+            "SELECT COUNT(*) FROM data_exports
+             WHERE user_id = $1 AND created_at > NOW() - make_interval(mins => $2)",
+            &[&user.id(), &EXPORT_COOLDOWN_MINUTES],
+        )
+        .await?
+        .get(0);
+    if recent > 0 {
+        return Err(BadRequest(
+            "You already requested an export recently. Please wait a bit before requesting another one.".to_string(),
+        ));
+    }
+
+    let token = random_token();
+    let export_id: i64 = client
+        .query_one(
+            "INSERT INTO data_exports (user_id, token_hash, expires_at)
+             VALUES ($1, $2, NOW() + make_interval(hours => $3))
+             RETURNING id",
+            &[&user.id(), &token_hash(&token), &EXPORT_TTL_HOURS],
+        )
+        .await?
+        .get(0);
+
+    Ok((export_id, token))
+}
+
+/// Periodic job: expired bundles are personal data nobody can download anymore
+pub async fn purge_expired_exports(db_state: &Arc<database::AppState>) -> Return<u64> {
+    Ok(database::client(db_state)
+        .await?
+        .execute("DELETE FROM data_exports WHERE expires_at <= NOW()", &[])
+        .await?)
+}
+
+/// Runs in the background. Marks the export as failed if anything goes wrong so the
+/// user is not left waiting for a bundle that will never arrive.
+pub async fn build_export(
+    db_state: &Arc<database::AppState>,
+    export_id: i64,
+    user: &User,
+) -> Return<()> {
+    let client = database::client(db_state).await?;
+
+    match collect_user_data(&client, user).await {
+        Ok(payload) => {
+            client
+                .execute(
+                    "UPDATE data_exports SET status = 'ready', payload = $2 WHERE id = $1",
+                    &[&export_id, &payload],
+                )
+                .await?;
+            Ok(())
+        }
+        Err(e) => {
+            client
+                .execute(
+                    "UPDATE data_exports SET status = 'failed' WHERE id = $1",
+                    &[&export_id],
+                )
+                .await?;
+            Err(e.into())
+        }
+    }
+}
+
+/// The finished bundle, if `token` belongs to `user_id` and is still valid
+pub async fn get_export(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+    token: &str,
+) -> Return<serde_json::Value> {
+    let client = database::client(db_state).await?;
+    let row = client
+        .query_opt(
+            "SELECT payload FROM data_exports
+             WHERE token_hash = $1 AND user_id = $2 AND status = 'ready' AND expires_at > NOW()",
+            &[&token_hash(token), &user_id],
+        )
+        .await?
+        .ok_or(ExportNotFound)?;
+    row.get::<_, Option<serde_json::Value>>(0)
+        .ok_or(ExportNotFound)
+}
+
+async fn json_list(
+    client: &Client,
+    query: &str,
+    params: &[&(dyn tokio_postgres::types::ToSql + Sync)],
+) -> Result<serde_json::Value, tokio_postgres::Error> {
+    Ok(client.query_one(query, params).await?.get(0))
+}
+
+async fn collect_user_data(
+    client: &Client,
+    user: &User,
+) -> Result<serde_json::Value, tokio_postgres::Error> {
+    let user_id = user.id();
+
+    let profile = json_list(
+        client,
+        "SELECT jsonb_build_object(
+                    'id', id,
+                    'username', username,
+                    'is_admin', is_admin,
+                    'created_at', created_at,
+                    'signup_ip', HOST(signup_ip))
+         FROM users WHERE id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let login_history = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'ip_address', HOST(ip_address),
+                    'timestamp', created_at) ORDER BY created_at DESC), '[]'::jsonb)
+         FROM login_logs WHERE user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let commits = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'commit_id', c.commit_id,
+                    'deck', d.name,
+                    'rationale', c.rationale,
+                    'info', c.info,
+                    'timestamp', c.timestamp) ORDER BY c.commit_id), '[]'::jsonb)
+         FROM commits c
+         LEFT JOIN decks d ON d.id = c.deck
+         WHERE c.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let field_suggestions = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'commit_id', f.commit,
+                    'note_id', f.note,
+                    'position', f.position,
+                    'content', f.content,
+                    'reviewed', f.reviewed) ORDER BY f.id), '[]'::jsonb)
+         FROM fields f
+         JOIN commits c ON c.commit_id = f.commit
+         WHERE c.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let tag_suggestions = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'commit_id', t.commit,
+                    'note_id', t.note,
+                    'tag', t.content,
+                    'action', CASE WHEN t.action THEN 'add' ELSE 'remove' END,
+                    'reviewed', t.reviewed) ORDER BY t.id), '[]'::jsonb)
+         FROM tags t
+         JOIN commits c ON c.commit_id = t.commit
+         WHERE c.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let deletion_suggestions = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'commit_id', s.commit,
+                    'note_id', s.note)), '[]'::jsonb)
+         FROM card_deletion_suggestions s
+         JOIN commits c ON c.commit_id = s.commit
+         WHERE c.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let move_suggestions = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'commit_id', s.commit,
+                    'note_id', s.note)), '[]'::jsonb)
+         FROM note_move_suggestions s
+         JOIN commits c ON c.commit_id = s.commit
+         WHERE c.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let notifications = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'deck', d.name,
+                    'commit_id', n.commit_id,
+                    'status', n.status,
+                    'reason', n.reason,
+                    'is_read', n.is_read,
+                    'created_at', n.created_at) ORDER BY n.created_at DESC), '[]'::jsonb)
+         FROM notifications n
+         LEFT JOIN decks d ON d.id = n.deck_id
+         WHERE n.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    let owned_decks = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'name', name,
+                    'hash', human_hash,
+                    'private', private) ORDER BY name), '[]'::jsonb)
+         FROM decks WHERE owner = $1 AND parent IS NULL",
+        &[&user_id],
+    )
+    .await?;
+
+    let maintained_decks = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'name', d.name,
+                    'hash', d.human_hash,
+                    'role', m.role) ORDER BY d.name), '[]'::jsonb)
+         FROM maintainers m
+         JOIN decks d ON d.id = m.deck
+         WHERE m.user_id = $1",
+        &[&user_id],
+    )
+    .await?;
+
+    // Subscriptions are keyed by the hashed username, not the user id
+    let subscriptions = json_list(
+        client,
+        "SELECT COALESCE(jsonb_agg(jsonb_build_object(
+                    'name', d.name,
+                    'hash', d.human_hash) ORDER BY d.name), '[]'::jsonb)
+         FROM subscriptions s
+         JOIN decks d ON d.id = s.deck_id
+         WHERE s.user_hash = $1",
+        &[&user_hash(&user.username)],
+    )
+    .await?;
+
+    Ok(serde_json::json!({
+        "generated_at": chrono::Utc::now().to_rfc3339(),
+        "profile": profile,
+        "login_history": login_history,
+        "commits": commits,
+        "suggestions": {
+            "fields": field_suggestions,
+            "tags": tag_suggestions,
+            "note_removals": deletion_suggestions,
+            "note_moves": move_suggestions,
+        },
+        "notifications": notifications,
+        "owned_decks": owned_decks,
+        "maintained_decks": maintained_decks,
+        "subscriptions": subscriptions,
+    }))
+}
@@ -4,12 +4,14 @@ use std::sync::Arc;
 use bb8_postgres::bb8::{Pool, PooledConnection};
 use bb8_postgres::{tokio_postgres::NoTls, PostgresConnectionManager};
 
-use crate::error::Error::{DatabaseConnection, Unauthorized};
-use crate::{DeckHash, DeckId, Return, UserId};
+use crate::error::Error::DatabaseConnection;
+use crate::Return;
 
 use aws_sdk_s3::Client as S3Client;
 use tera::Tera;
 
+use crate::background_tasks::TaskRegistry;
+use crate::csrf::CsrfService;
 use crate::media_tokens::MediaTokenService;
 
 #[derive(Debug)]
@@ -18,6 +20,8 @@ pub struct AppState {
     pub tera: Arc<Tera>,
     pub s3_client: S3Client,
     pub media_token_service: MediaTokenService,
+    pub csrf: CsrfService,
+    pub tasks: TaskRegistry,
 }
 
 pub async fn establish_pool_connection() -> Result<
@@ -45,22 +49,3 @@ pub async fn client(
         }
     }
 }
-
-pub async fn owned_deck_id(
-    db_state: &Arc<AppState>,
-    deck_hash: &DeckHash,
-    user_id: UserId,
-) -> Return<DeckId> {
-    let owned_info = client(db_state)
-        .await?
-        .query(
-            "Select id from decks where human_hash = $1 and owner = $2",
-            &[&deck_hash, &user_id],
-        )
-        .await?;
-
-    match owned_info.is_empty() {
-        true => Err(Unauthorized),
-        false => Ok(owned_info[0].get(0)),
-    }
-}
@@ -0,0 +1,849 @@
+use std::env;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::cleanser;
+use crate::database;
+use crate::error::Error::{BadRequest, Unauthorized, UserNotFound};
+use crate::notification_manager;
+use crate::permissions;
+use crate::user::User;
+use crate::{DeckHash, DeckId, Return};
+
+/// Hands the deck and its whole subtree to `new_owner`. Notetypes of the previous owner that
+/// are only used inside this subtree move along, and the handover is noted in the changelog.
+/// Returns the number of decks that changed owner.
+pub async fn transfer_ownership(
+    tx: &tokio_postgres::Transaction<'_>,
+    deck_id: DeckId,
+    new_owner: i32,
+) -> Return<u64> {
+    let names = tx
+        .query_one(
+            "SELECT COALESCE(o.username, '<deleted>'), n.username
+             FROM decks d
+             LEFT JOIN users o ON o.id = d.owner
+             JOIN users n ON n.id = $2
+             WHERE d.id = $1",
+            &[&deck_id, &new_owner],
+        )
+        .await?;
+    let old_name: String = names.get(0);
+    let new_name: String = names.get(1);
+
+    // Must run before the decks change hands, it keys on the previous owner
+    tx.execute(
+        r"
+        WITH RECURSIVE subtree AS (
+            SELECT id, owner FROM decks WHERE id = $1
+            UNION ALL
+            SELECT d.id, d.owner FROM decks d JOIN subtree s ON d.parent = s.id
+        )
+        UPDATE notetype nt SET owner = $2
+        WHERE nt.owner = (SELECT owner FROM decks WHERE id = $1)
+          AND EXISTS (SELECT 1 FROM notes n WHERE n.notetype = nt.id AND n.deck IN (SELECT id FROM subtree))
+          AND NOT EXISTS (SELECT 1 FROM notes n WHERE n.notetype = nt.id AND n.deck NOT IN (SELECT id FROM subtree))",
+        &[&deck_id, &new_owner],
+    )
+    .await?;
+
+    let updated = tx
+        .execute(
+            r"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id
+            )
+            UPDATE decks SET owner = $2 WHERE id IN (SELECT id FROM subtree)",
+            &[&deck_id, &new_owner],
+        )
+        .await?;
+
+    // The owner implicitly holds every role, a maintainer row would only be confusing
+    tx.execute(
+        r"
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM decks WHERE id = $1
+            UNION ALL
+            SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id
+        )
+        DELETE FROM maintainers WHERE user_id = $2 AND deck IN (SELECT id FROM subtree)",
+        &[&deck_id, &new_owner],
+    )
+    .await?;
+
+    // An admin transfer overrides whatever the previous owner had offered
+    tx.execute(
+        "UPDATE deck_transfers SET status = 'cancelled', resolved_at = NOW()
+         WHERE deck_id = $1 AND status = 'pending'",
+        &[&deck_id],
+    )
+    .await?;
+
+    tx.execute(
+        "INSERT INTO changelogs (deck, message, timestamp) VALUES ($1, $2, NOW())",
+        &[
+            &deck_id,
+            &format!("Ownership transferred from {old_name} to {new_name}."),
+        ],
+    )
+    .await?;
+
+    Ok(updated)
+}
+
+#[derive(Serialize)]
+pub struct DeckTransferInfo {
+    pub id: i64,
+    pub deck_name: String,
+    pub deck_hash: String,
+    pub from_user: String,
+    pub to_user: String,
+    pub created_at: String,
+}
+
+const TRANSFER_COLUMNS: &str = "
+    t.id, d.name, d.human_hash, f.username, r.username,
+    TO_CHAR(t.created_at, 'MM/DD/YYYY HH24:MI:SS')
+    FROM deck_transfers t
+    JOIN decks d ON d.id = t.deck_id
+    JOIN users f ON f.id = t.from_user
+    JOIN users r ON r.id = t.to_user";
+
+fn transfer_row(row: &tokio_postgres::Row) -> DeckTransferInfo {
+    DeckTransferInfo {
+        id: row.get(0),
+        deck_name: row.get(1),
+        deck_hash: row.get(2),
+        from_user: row.get(3),
+        to_user: row.get(4),
+        created_at: row.get(5),
+    }
+}
+
+/// Pending offers made to and by `user_id`, shown on `/ManageDecks`
+pub async fn pending_transfers(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+) -> Return<(Vec<DeckTransferInfo>, Vec<DeckTransferInfo>)> {
+    let client = database::client(db_state).await?;
+    let incoming = client
+        .query(
+            &format!("SELECT {TRANSFER_COLUMNS} WHERE t.to_user = $1 AND t.status = 'pending' ORDER BY t.created_at"),
+            &[&user_id],
+        )
+        .await?;
+    let outgoing = client
+        .query(
+            &format!("SELECT {TRANSFER_COLUMNS} WHERE t.from_user = $1 AND t.status = 'pending' ORDER BY t.created_at"),
+            &[&user_id],
+        )
+        .await?;
+    Ok((
+        incoming.iter().map(transfer_row).collect(),
+        outgoing.iter().map(transfer_row).collect(),
+    ))
+}
+
+/// Offers the top-level deck `deck_hash` to `recipient`. Only the owner may do this.
+pub async fn offer_transfer(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    deck_hash: &DeckHash,
+    recipient: &str,
+) -> Return<()> {
+    let deck_id = permissions::owned_deck_id(db_state, deck_hash, user).await?;
+    let client = database::client(db_state).await?;
+
+    let is_root: bool = client
+        .query_one("SELECT parent IS NULL FROM decks WHERE id = $1", &[&deck_id])
+        .await?
+        .get(0);
+    if !is_root {
+        return Err(BadRequest(
+            "Only top-level decks can be transferred, subdecks move with their parent".to_string(),
+        ));
+    }
+
+    let to_user: i32 = client
+        .query_opt(
+            "SELECT id FROM users
+             WHERE username = $1 AND deleted_at IS NULL AND suspended_at IS NULL",
+            &[&recipient.trim().to_lowercase()],
+        )
+        .await?
+        .ok_or(UserNotFound)?
+        .get(0);
+    if to_user == user.id() {
+        return Err(BadRequest("You already own this deck".to_string()));
+    }
+
+    let inserted = client
+        .execute(
+            "INSERT INTO deck_transfers (deck_id, from_user, to_user)
+             VALUES ($1, $2, $3)
+             ON CONFLICT (deck_id) WHERE status = 'pending' DO NOTHING",
+            &[&deck_id, &user.id(), &to_user],
+        )
+        .await?;
+    if inserted == 0 {
+        return Err(BadRequest(
+            "There already is a pending transfer for this deck. Cancel it first.".to_string(),
+        ));
+    }
+    drop(client);
+
+    notification_manager::create_notification(
+        db_state,
+        to_user,
+        None,
+        deck_id,
+        "transfer_offered",
+        Some(&format!(
+            "{} wants to hand this deck over to you. Accept or decline it under Manage Decks.",
+            user.username
+        )),
+    )
+    .await
+}
+
+/// Accepts a pending offer made to `user`. Returns the hash of the transferred deck.
+pub async fn accept_transfer(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    transfer_id: i64,
+) -> Return<DeckHash> {
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    // The offer is void if the deck changed hands in the meantime
+    let row = tx
+        .query_opt(
+            "UPDATE deck_transfers t SET status = 'accepted', resolved_at = NOW()
+             FROM decks d
+             WHERE t.id = $1 AND t.to_user = $2 AND t.status = 'pending'
+               AND d.id = t.deck_id AND d.owner = t.from_user
+             RETURNING t.deck_id, t.from_user, d.human_hash",
+            &[&transfer_id, &user.id()],
+        )
+        .await?
+        .ok_or_else(|| BadRequest("This transfer is no longer available".to_string()))?;
+    let deck_id: DeckId = row.get(0);
+    let from_user: i32 = row.get(1);
+    let deck_hash: DeckHash = row.get(2);
+
+    if let Err(e) = transfer_ownership(&tx, deck_id, user.id()).await {
+        let _ = tx.rollback().await;
+        return Err(e);
+    }
+    tx.commit().await?;
+    drop(client);
+
+    notification_manager::create_notification(
+        db_state,
+        from_user,
+        None,
+        deck_id,
+        "transfer_accepted",
+        Some(&format!("{} is now the owner of this deck.", user.username)),
+    )
+    .await?;
+
+    Ok(deck_hash)
+}
+
+/// The recipient declines, or the owner withdraws the offer
+pub async fn close_transfer(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    transfer_id: i64,
+    as_recipient: bool,
+) -> Return<()> {
+    let client = database::client(db_state).await?;
+    let (status, party) = if as_recipient {
+        ("declined", "to_user")
+    } else {
+        ("cancelled", "from_user")
+    };
+    let row = client
+        .query_opt(
+            &format!(
+                "UPDATE deck_transfers SET status = $1, resolved_at = NOW()
+                 WHERE id = $2 AND {party} = $3 AND status = 'pending'
+                 RETURNING deck_id, from_user"
+            ),
+            &[&status, &transfer_id, &user.id()],
+        )
+        .await?
+        .ok_or(Unauthorized)?;
+    drop(client);
+
+    if as_recipient {
+        notification_manager::create_notification(
+            db_state,
+            row.get(1),
+            None,
+            row.get(0),
+            "transfer_declined",
+            Some(&format!("{} declined to take over this deck.", user.username)),
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Deleted decks can be restored from `/ManageDecks` for this long
+pub const RESTORE_WINDOW_DAYS: i32 = 30;
+/// The owner is notified this many days before the deck is purged
+const PURGE_WARNING_DAYS: i32 = 3;
+
+/// A deleted deck whose parent is still live (`d` aliases `decks`). Restoring and purging work
+/// on these, the subdecks below go along with them.
+const DELETION_ROOT: &str = "d.deleted_at IS NOT NULL AND NOT EXISTS (
+    SELECT 1 FROM decks p WHERE p.id = d.parent AND p.deleted_at IS NOT NULL)";
+
+/// Marks the deck and all subdecks as deleted. Nothing is removed until the restore window is over.
+pub async fn soft_delete(db_state: &Arc<database::AppState>, deck_id: DeckId) -> Return<()> {
+    let client = database::client(db_state).await?;
+    client
+        .execute(
+            r"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id
+            )
+            UPDATE decks SET deleted_at = NOW(), purge_warned = FALSE
+            WHERE id IN (SELECT id FROM subtree) AND deleted_at IS NULL",
+            &[&deck_id],
+        )
+        .await?;
+
+    // Nobody should be offered a deck that is about to disappear
+    client
+        .execute(
+            "UPDATE deck_transfers SET status = 'cancelled', resolved_at = NOW()
+             WHERE deck_id = $1 AND status = 'pending'",
+            &[&deck_id],
+        )
+        .await?;
+    Ok(())
+}
+
+pub async fn restore(db_state: &Arc<database::AppState>, deck_id: DeckId) -> Return<()> {
+    let client = database::client(db_state).await?;
+    let parent_deleted: bool = client
+        .query_one(
+            "SELECT COALESCE(p.deleted_at IS NOT NULL, FALSE)
+             FROM decks d LEFT JOIN decks p ON p.id = d.parent WHERE d.id = $1",
+            &[&deck_id],
+        )
+        .await?
+        .get(0);
+    if parent_deleted {
+        return Err(BadRequest(
+            "The parent deck is deleted as well, restore it first".to_string(),
+        ));
+    }
+
+    // Subdecks deleted on their own before stay deleted, they carry an earlier timestamp
+    let updated = client
+        .execute(
+            r"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id
+            )
+            UPDATE decks SET deleted_at = NULL, purge_warned = FALSE
+            WHERE id IN (SELECT id FROM subtree)
+              AND deleted_at = (SELECT deleted_at FROM decks WHERE id = $1)",
+            &[&deck_id],
+        )
+        .await?;
+    if updated == 0 {
+        return Err(BadRequest("This deck is not deleted".to_string()));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+pub struct DeletedDeckInfo {
+    pub name: String,
+    pub hash: String,
+    pub deleted_at: String,
+    pub purge_at: String,
+}
+
+/// Deleted decks of `owner` that are waiting to be purged, subdecks of those are not listed
+pub async fn deleted_decks(
+    db_state: &Arc<database::AppState>,
+    owner: i32,
+) -> Return<Vec<DeletedDeckInfo>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            &format!(
+                "SELECT d.full_path, d.human_hash,
+                        TO_CHAR(d.deleted_at, 'MM/DD/YYYY'),
+                        TO_CHAR(d.deleted_at + make_interval(days => $2), 'MM/DD/YYYY')
+                 FROM decks d
+                 WHERE d.owner = $1 AND {DELETION_ROOT}
+                 ORDER BY d.deleted_at DESC"
+            ),
+            &[&owner, &RESTORE_WINDOW_DAYS],
+        )
+        .await?;
+    Ok(rows
+        .into_iter()
+        .map(|row| DeletedDeckInfo {
+            name: row.get(0),
+            hash: row.get(1),
+            deleted_at: row.get(2),
+            purge_at: row.get(3),
+        })
+        .collect())
+}
+
+/// Periodic job: warns owners about upcoming purges and permanently removes decks whose
+/// restore window has passed. Returns the number of purged decks.
+pub async fn purge_expired_decks(db_state: &Arc<database::AppState>) -> Return<usize> {
+    let client = database::client(db_state).await?;
+
+    let warn = client
+        .query(
+            &format!(
+                "UPDATE decks d SET purge_warned = TRUE
+                 WHERE {DELETION_ROOT} AND NOT d.purge_warned
+                   AND d.owner IS NOT NULL
+                   AND d.deleted_at < NOW() - make_interval(days => $1)
+                 RETURNING d.id, d.owner, d.full_path"
+            ),
+            &[&(RESTORE_WINDOW_DAYS - PURGE_WARNING_DAYS)],
+        )
+        .await?;
+
+    let expired: Vec<String> = client
+        .query(
+            &format!(
+                "SELECT d.human_hash FROM decks d
+                 WHERE {DELETION_ROOT}
+                   AND d.deleted_at < NOW() - make_interval(days => $1)"
+            ),
+            &[&RESTORE_WINDOW_DAYS],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    drop(client);
+
+    for row in &warn {
+        let name: String = row.get(2);
+        notification_manager::create_notification(
+            db_state,
+            row.get(1),
+            None,
+            row.get(0),
+            "deck_purge_pending",
+            Some(&format!(
+                "{name} will be permanently deleted in {PURGE_WARNING_DAYS} days. Restore it under Manage Decks if this was a mistake."
+            )),
+        )
+        .await?;
+    }
+
+    for deck_hash in &expired {
+        let client = database::client(db_state).await?;
+        client.query("SELECT delete_deck($1)", &[deck_hash]).await?;
+        drop(client);
+
+        if let Err(e) = purge_s3_deck_assets(db_state, deck_hash).await {
+            tracing::warn!(error = %e, deck_hash = %deck_hash, "Failed to purge S3 assets for deck");
+        }
+    }
+
+    if !expired.is_empty() {
+        // Expensive, but only runs when something was actually purged
+        database::client(db_state)
+            .await?
+            .execute(
+                "DELETE FROM notetype WHERE id NOT IN (SELECT DISTINCT notetype FROM notes)",
+                &[],
+            )
+            .await?;
+        tracing::info!(count = expired.len(), "Purged deleted decks");
+    }
+
+    Ok(expired.len())
+}
+
+/// Remove any deck-specific assets stored under the S3 prefix for this deck.
+pub async fn purge_s3_deck_assets(
+    db_state: &Arc<database::AppState>,
+    deck_hash: &str,
+) -> Result<(), aws_sdk_s3::Error> {
+    let bucket = match env::var("S3_MEDIA_BUCKET") {
+        Ok(bucket) if !bucket.trim().is_empty() => bucket.trim().to_owned(),
+        _ => return Ok(()),
+    };
+
+    let prefix = format!("decks/{deck_hash}/");
+    let client = &db_state.s3_client;
+    let mut continuation_token: Option<String> = None;
+
+    loop {
+        let mut request = client
+            .list_objects_v2()
+            .bucket(&bucket)
+            .prefix(&prefix);
+
+        if let Some(ref token) = continuation_token {
+            request = request.continuation_token(token);
+        }
+
+        let response = request.send().await?;
+
+        let keys: Vec<String> = response
+            .contents()
+            .iter()
+            .filter_map(|object| object.key().map(str::to_owned))
+            .collect();
+
+        for key in keys {
+            client
+                .delete_object()
+                .bucket(&bucket)
+                .key(key)
+                .send()
+                .await?;
+        }
+
+        if response.is_truncated().unwrap_or(false) {
+            continuation_token = response
+                .next_continuation_token()
+                .map(std::borrow::ToOwned::to_owned);
+        } else {
+            break;
+        }
+    }
+
+    let marker_key = format!("decks/{deck_hash}");
+    let _ = client
+        .delete_object()
+        .bucket(&bucket)
+        .key(marker_key)
+        .send()
+        .await;
+
+    Ok(())
+}
+
+/// Copies the deck tree below `source_id` with all reviewed notes, fields, tags, the notetypes
+/// they use and their media references into a new private deck owned by `user`. Suggestions,
+/// history and subscriptions stay with the original. Returns the hash of the new deck.
+///
+/// The add-on writes more columns than the website knows about, so rows are copied through
+/// `jsonb_populate_record` with only the keys that have to change overridden.
+pub async fn fork_deck(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    source_id: DeckId,
+) -> Return<DeckHash> {
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    let source = tx
+        .query_one(
+            "SELECT name, full_path FROM decks WHERE id = $1",
+            &[&source_id],
+        )
+        .await?;
+    let source_name: String = source.get(0);
+    let source_path: String = source.get(1);
+
+    tx.batch_execute(
+        "CREATE TEMP TABLE fork_decks (old_id BIGINT PRIMARY KEY, new_id BIGINT, new_hash TEXT) ON COMMIT DROP;
+         CREATE TEMP TABLE fork_notetypes (old_id BIGINT PRIMARY KEY, new_id BIGINT) ON COMMIT DROP;
+         CREATE TEMP TABLE fork_notes (old_id BIGINT PRIMARY KEY, new_id BIGINT) ON COMMIT DROP;",
+    )
+    .await?;
+
+    tx.execute(
+        r"
+        INSERT INTO fork_decks
+        WITH RECURSIVE subtree AS (
+            SELECT id FROM decks WHERE id = $1
+            UNION ALL
+            SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id WHERE d.deleted_at IS NULL
+        )
+        SELECT id, nextval(pg_get_serial_sequence('decks', 'id')), replace(gen_random_uuid()::text, '-', '')
+        FROM subtree",
+        &[&source_id],
+    )
+    .await?;
+
+    tx.execute(
+        "INSERT INTO fork_notes
+         SELECT n.id, nextval(pg_get_serial_sequence('notes', 'id'))
+         FROM notes n JOIN fork_decks fd ON fd.old_id = n.deck
+         WHERE n.reviewed = true AND n.deleted = false",
+        &[],
+    )
+    .await?;
+
+    tx.execute(
+        "INSERT INTO fork_notetypes
+         SELECT used.notetype, nextval(pg_get_serial_sequence('notetype', 'id'))
+         FROM (SELECT DISTINCT n.notetype FROM notes n JOIN fork_notes fn ON fn.old_id = n.id) used",
+        &[],
+    )
+    .await?;
+
+    // A forked subdeck becomes a top-level deck, so its own path prefix is replaced by its name
+    tx.execute(
+        r"
+        INSERT INTO decks
+        SELECT (jsonb_populate_record(NULL::decks, to_jsonb(d) || jsonb_build_object(
+            'id', fd.new_id,
+            'parent', parent_fd.new_id,
+            'human_hash', fd.new_hash,
+            'full_path', $2::TEXT || substr(d.full_path, length($3::TEXT) + 1),
+            'owner', $4::INT,
+            'private', true,
+            'stats_enabled', false,
+            'moderation_hidden', false,
+            'deleted_at', NULL,
+            'purge_warned', false,
+            'forked_from', CASE WHEN d.id = $1 THEN d.id END,
+            'last_update', NOW()
+        ))).*
+        FROM decks d
+        JOIN fork_decks fd ON fd.old_id = d.id
+        LEFT JOIN fork_decks parent_fd ON parent_fd.old_id = d.parent AND d.id <> $1",
+        &[&source_id, &source_name, &source_path, &user.id()],
+    )
+    .await?;
+
+    tx.execute(
+        r"
+        INSERT INTO notetype
+        SELECT (jsonb_populate_record(NULL::notetype, to_jsonb(nt) || jsonb_build_object(
+            'id', fnt.new_id,
+            'guid', replace(gen_random_uuid()::text, '-', ''),
+            'owner', $1::INT
+        ))).*
+        FROM notetype nt JOIN fork_notetypes fnt ON fnt.old_id = nt.id",
+        &[&user.id()],
+    )
+    .await?;
+
+    for table in ["notetype_field", "notetype_template"] {
+        tx.execute(
+            &format!(
+                "INSERT INTO {table}
+                 SELECT (jsonb_populate_record(NULL::{table}, to_jsonb(t) || jsonb_build_object(
+                     'id', nextval(pg_get_serial_sequence('{table}', 'id')),
+                     'notetype', fnt.new_id
+                 ))).*
+                 FROM {table} t JOIN fork_notetypes fnt ON fnt.old_id = t.notetype"
+            ),
+            &[],
+        )
+        .await?;
+    }
+
+    // New guids, otherwise Anki would treat the fork and the original as the same notes
+    tx.execute(
+        r"
+        INSERT INTO notes
+        SELECT (jsonb_populate_record(NULL::notes, to_jsonb(n) || jsonb_build_object(
+            'id', fn.new_id,
+            'guid', substr(md5(random()::text || fn.new_id::text), 1, 10),
+            'deck', fd.new_id,
+            'notetype', fnt.new_id,
+            'last_update', NOW()
+        ))).*
+        FROM notes n
+        JOIN fork_notes fn ON fn.old_id = n.id
+        JOIN fork_decks fd ON fd.old_id = n.deck
+        JOIN fork_notetypes fnt ON fnt.old_id = n.notetype",
+        &[],
+    )
+    .await?;
+
+    tx.execute(
+        r"
+        INSERT INTO fields
+        SELECT (jsonb_populate_record(NULL::fields, to_jsonb(f) || jsonb_build_object(
+            'id', nextval(pg_get_serial_sequence('fields', 'id')),
+            'note', fn.new_id,
+            'commit', NULL,
+            'creator_ip', NULL
+        ))).*
+        FROM fields f JOIN fork_notes fn ON fn.old_id = f.note
+        WHERE f.reviewed = true",
+        &[],
+    )
+    .await?;
+
+    tx.execute(
+        r"
+        INSERT INTO tags
+        SELECT (jsonb_populate_record(NULL::tags, to_jsonb(t) || jsonb_build_object(
+            'id', nextval(pg_get_serial_sequence('tags', 'id')),
+            'note', fn.new_id,
+            'commit', NULL,
+            'creator_ip', NULL
+        ))).*
+        FROM tags t JOIN fork_notes fn ON fn.old_id = t.note
+        WHERE t.reviewed = true AND t.action = true",
+        &[],
+    )
+    .await?;
+
+    tx.execute(
+        "INSERT INTO media_references (note_id, file_name)
+         SELECT fn.new_id, mr.file_name
+         FROM media_references mr JOIN fork_notes fn ON fn.old_id = mr.note_id",
+        &[],
+    )
+    .await?;
+
+    let row = tx
+        .query_one(
+            "SELECT fd.new_id, fd.new_hash, d.human_hash
+             FROM fork_decks fd JOIN decks d ON d.id = fd.old_id
+             WHERE fd.old_id = $1",
+            &[&source_id],
+        )
+        .await?;
+    let new_id: DeckId = row.get(0);
+    let new_hash: DeckHash = row.get(1);
+    let source_hash: String = row.get(2);
+
+    tx.execute(
+        "INSERT INTO changelogs (deck, message, timestamp) VALUES ($1, $2, NOW())",
+        &[
+            &new_id,
+            &format!(
+                "Forked from <a href=\"/notes/{source_hash}\">{}</a>.",
+                cleanser::clean(&source_name)
+            ),
+        ],
+    )
+    .await?;
+
+    tx.commit().await?;
+    Ok(new_hash)
+}
+
+#[derive(Serialize)]
+pub struct DeckLink {
+    pub name: String,
+    pub hash: String,
+}
+
+/// Where the deck was forked from (if that deck is still visible) and its public forks
+pub async fn fork_provenance(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+) -> Return<(Option<DeckLink>, Vec<DeckLink>)> {
+    let client = database::client(db_state).await?;
+    let to_link = |row: &tokio_postgres::Row| DeckLink {
+        name: row.get(0),
+        hash: row.get(1),
+    };
+
+    let forked_from = client
+        .query_opt(
+            "SELECT s.name, s.human_hash FROM decks d
+             JOIN decks s ON s.id = d.forked_from
+             WHERE d.id = $1 AND s.deleted_at IS NULL",
+            &[&deck_id],
+        )
+        .await?
+        .as_ref()
+        .map(to_link);
+
+    let forks = client
+        .query(
+            "SELECT name, human_hash FROM decks
+             WHERE forked_from = $1 AND private = false AND unlisted = false AND deleted_at IS NULL
+             ORDER BY name",
+            &[&deck_id],
+        )
+        .await?
+        .iter()
+        .map(to_link)
+        .collect();
+
+    Ok((forked_from, forks))
+}
+
+#[derive(Serialize)]
+pub struct NotetypeOption {
+    pub id: i64,
+    pub name: String,
+}
+
+#[derive(Serialize)]
+pub struct DeckOption {
+    pub id: DeckId,
+    pub hash: String,
+    pub path: String,
+}
+
+/// CTE `tree` holding the live deck `$1` and all its live subdecks
+pub const DECK_TREE: &str = "
+    WITH RECURSIVE tree AS (
+        SELECT id FROM decks WHERE id = $1 AND deleted_at IS NULL
+        UNION ALL
+        SELECT d.id FROM decks d JOIN tree ON d.parent = tree.id WHERE d.deleted_at IS NULL
+    )";
+
+/// Subdecks and the notetypes their notes use, offered by the bulk tools
+pub async fn tree_options(
+    db_state: &Arc<database::AppState>,
+    root: DeckId,
+) -> Return<(Vec<DeckOption>, Vec<NotetypeOption>)> {
+    let client = database::client(db_state).await?;
+    let decks = client
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT d.id, d.human_hash, d.full_path FROM decks d JOIN tree ON tree.id = d.id
+                ORDER BY d.full_path"
+            ),
+            &[&root],
+        )
+        .await?
+        .iter()
+        .map(|row| DeckOption {
+            id: row.get(0),
+            hash: row.get(1),
+            path: row.get(2),
+        })
+        .collect();
+    let notetypes = client
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT nt.id, nt.name FROM notetype nt
+                WHERE nt.id IN (SELECT n.notetype FROM notes n JOIN tree ON tree.id = n.deck
+                                WHERE NOT n.deleted)
+                ORDER BY nt.name"
+            ),
+            &[&root],
+        )
+        .await?
+        .iter()
+        .map(|row| NotetypeOption {
+            id: row.get(0),
+            name: row.get(1),
+        })
+        .collect();
+    Ok((decks, notetypes))
+}
@@ -0,0 +1,246 @@
+//! The `/decks` explore page: filtering by category and language, sorting and the
+//! featured decks picked by admins.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::cleanser;
+use crate::database;
+use crate::error::Error::{BadRequest, DeckNotFound};
+use crate::user::User;
+use crate::{DeckId, Return};
+
+pub const PAGE_SIZE: i64 = 25;
+/// Keeps the offset computation far away from overflowing
+pub const MAX_PAGE: i64 = 10_000;
+const MAX_CATEGORIES: usize = 3;
+
+/// (slug, label)
+pub const CATEGORIES: [(&str, &str); 12] = [
+    ("medicine", "Medicine"),
+    ("languages", "Languages"),
+    ("law", "Law"),
+    ("natural-sciences", "Natural Sciences"),
+    ("mathematics", "Mathematics"),
+    ("computer-science", "Computer Science"),
+    ("engineering", "Engineering"),
+    ("humanities", "Humanities"),
+    ("social-sciences", "Social Sciences"),
+    ("business", "Business"),
+    ("geography", "Geography"),
+    ("other", "Other"),
+];
+
+/// ISO 639-1 code and label
+pub const LANGUAGES: [(&str, &str); 20] = [
+    ("ar", "Arabic"),
+    ("zh", "Chinese"),
+    ("cs", "Czech"),
+    ("nl", "Dutch"),
+    ("en", "English"),
+    ("fr", "French"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("hi", "Hindi"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("la", "Latin"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("es", "Spanish"),
+    ("sv", "Swedish"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    #[default]
+    Subscribers,
+    Notes,
+    Recent,
+    Retention,
+}
+
+impl SortOrder {
+    #[must_use]
+    pub fn parse(sort: &str) -> Self {
+        match sort {
+            "notes" => Self::Notes,
+            "recent" => Self::Recent,
+            "retention" => Self::Retention,
+            _ => Self::Subscribers,
+        }
+    }
+
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Subscribers => "subscribers",
+            Self::Notes => "notes",
+            Self::Recent => "recent",
+            Self::Retention => "retention",
+        }
+    }
+
+    const fn order_by(self) -> &'static str {
+        match self {
+            Self::Subscribers => "subs DESC",
+            // note_count in deck_stats is preformatted for display (e.g. "1.5k"), the stats job
+            // stores the plain count in decks
+            Self::Notes => "d.note_count DESC NULLS LAST",
+            Self::Recent => "ds.last_update DESC",
+            Self::Retention => "d.retention DESC NULLS LAST",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DiscoverDeck {
+    pub id: DeckId,
+    pub name: String,
+    pub desc: String,
+    pub hash: String,
+    pub last_update: String,
+    pub notes: String,
+    pub subscriptions: i64,
+    pub language: Option<String>,
+    pub categories: Vec<String>,
+    pub retention: Option<f32>,
+}
+
+pub struct DeckFilter<'a> {
+    pub category: Option<&'a str>,
+    pub language: Option<&'a str>,
+    pub sort: SortOrder,
+    /// 1-based
+    pub page: i64,
+}
+
+const DECK_COLUMNS: &str = "
+    ds.id, ds.name, ds.description, ds.human_hash,
+    TO_CHAR(ds.last_update, 'MM/DD/YYYY'), ds.note_count::TEXT,
+    (SELECT COUNT(*) FROM subscriptions WHERE deck_id = ds.id) AS subs,
+    d.language, d.categories, d.retention";
+
+fn deck_row(row: &tokio_postgres::Row) -> DiscoverDeck {
+    DiscoverDeck {
+        id: row.get(0),
+        name: row.get(1),
+        desc: cleanser::clean(row.get(2)),
+        hash: row.get(3),
+        last_update: row.get(4),
+        notes: row.get(5),
+        subscriptions: row.get(6),
+        language: row.get(7),
+        categories: row.get(8),
+        retention: row.get(9),
+    }
+}
+
+/// One page of listed decks and the total number of matches. Owners also see their own
+/// private and unlisted decks.
+pub async fn list_decks(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+    filter: &DeckFilter<'_>,
+) -> Return<(Vec<DiscoverDeck>, i64)> {
+    let client = database::client(db_state).await?;
+    let sql = format!(
+        "SELECT {DECK_COLUMNS}, COUNT(*) OVER ()
+         FROM deck_stats ds
+         JOIN decks d ON d.id = ds.id
+         WHERE (ds.private = false OR ds.owner = $1)
+           AND d.deleted_at IS NULL
+           AND (d.unlisted = false OR d.owner = $1)
+           AND ($2::TEXT IS NULL OR $2 = ANY(d.categories))
+           AND ($3::TEXT IS NULL OR d.language = $3)
+         ORDER BY {}, ds.id
+         LIMIT $4 OFFSET $5",
+        filter.sort.order_by()
+    );
+    let offset = (filter.page.clamp(1, MAX_PAGE) - 1) * PAGE_SIZE;
+    let rows = client
+        .query(
+            &sql,
+            &[
+                &user_id,
+                &filter.category,
+                &filter.language,
+                &PAGE_SIZE,
+                &offset,
+            ],
+        )
+        .await?;
+    let total = rows.first().map_or(0, |row| row.get(10));
+    Ok((rows.iter().map(deck_row).collect(), total))
+}
+
+pub async fn featured_decks(db_state: &Arc<database::AppState>) -> Return<Vec<DiscoverDeck>> {
+    let client = database::client(db_state).await?;
+    let sql = format!(
+        "SELECT {DECK_COLUMNS}
+         FROM featured_decks f
+         JOIN deck_stats ds ON ds.id = f.deck_id
+         JOIN decks d ON d.id = ds.id
+         WHERE ds.private = false AND d.unlisted = false AND d.deleted_at IS NULL
+         ORDER BY f.created_at DESC"
+    );
+    let rows = client.query(&sql, &[]).await?;
+    Ok(rows.iter().map(deck_row).collect())
+}
+
+pub async fn set_featured(
+    db_state: &Arc<database::AppState>,
+    admin: &User,
+    deck_hash: &str,
+    featured: bool,
+) -> Return<DeckId> {
+    let client = database::client(db_state).await?;
+    let deck_id: DeckId = client
+        .query_opt(
+            "SELECT id FROM decks WHERE human_hash = $1 AND parent IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(DeckNotFound)?
+        .get(0);
+    if featured {
+        client
+            .execute(
+                "INSERT INTO featured_decks (deck_id, featured_by) VALUES ($1, $2)
+                 ON CONFLICT (deck_id) DO NOTHING",
+                &[&deck_id, &admin.id()],
+            )
+            .await?;
+    } else {
+        client
+            .execute("DELETE FROM featured_decks WHERE deck_id = $1", &[&deck_id])
+            .await?;
+    }
+    Ok(deck_id)
+}
+
+/// Checks the owner's choices against `LANGUAGES` and `CATEGORIES`
+pub fn validate_tags(language: &str, categories: &[String]) -> Return<Option<String>> {
+    let language = match language.trim() {
+        "" => None,
+        code if LANGUAGES.iter().any(|(c, _)| *c == code) => Some(code.to_owned()),
+        _ => return Err(BadRequest("Unknown language".to_string())),
+    };
+    if categories.len() > MAX_CATEGORIES {
+        return Err(BadRequest(format!(
+            "Please pick at most {MAX_CATEGORIES} categories"
+        )));
+    }
+    if let Some(unknown) = categories
+        .iter()
+        .find(|c| !CATEGORIES.iter().any(|(slug, _)| *slug == c.as_str()))
+    {
+        return Err(BadRequest(format!("Unknown category {unknown}")));
+    }
+    Ok(language)
+}
@@ -72,6 +72,11 @@ fn is_expected_client_error(message: &str) -> bool {
         || lower.contains("ambiguous fields")
         || lower.contains("first field of a note cannot be empty")
         || lower.contains("account has been deleted")
+        || lower.contains("account has been suspended")
+        || lower.contains("too many failed login attempts")
+        || lower.contains("too many accounts")
+        || lower.contains("username was changed recently")
+        || lower.contains("csrf token")
 }
 
 impl Reporter {
@@ -356,6 +361,12 @@ pub enum Error {
     NoNoteTypesAffected,
     #[error("Deck not found")]
     DeckNotFound,
+    #[error("Data export not found or expired")]
+    ExportNotFound,
+    #[error("This invite link is invalid, expired or was revoked")]
+    InviteNotFound,
+    #[error("Changelog entry not found")]
+    ChangelogNotFound,
     #[error("Error while authenticating: {0}")]
     Auth(AuthError),
     #[error("Database error: {0}")]
@@ -368,6 +379,8 @@ pub enum Error {
     DatabaseConnection,
     #[error("Bad request: {0}")]
     BadRequest(String),
+    #[error("Invalid or missing CSRF token. Please reload the page and try again.")]
+    InvalidCsrfToken,
 }
 
 impl Error {
@@ -377,9 +390,11 @@ impl Error {
             Self::DB(_) | Self::BB8(_) | Self::Database(_) | Self::DatabaseConnection => {
                 ErrorCategory::Database
             }
-            Self::Unauthorized | Self::Auth(_) => ErrorCategory::Authorization,
+            Self::Unauthorized | Self::Auth(_) | Self::InvalidCsrfToken => {
+                ErrorCategory::Authorization
+            }
             Self::UserNotFound | Self::CommitNotFound | Self::CommitDeckNotFound
-            | Self::NoteNotFound(_) | Self::DeckNotFound | Self::NoNotesAffected
+            | Self::NoteNotFound(_) | Self::DeckNotFound | Self::ExportNotFound | Self::InviteNotFound | Self::ChangelogNotFound | Self::NoNotesAffected
             | Self::NoNoteTypesAffected => ErrorCategory::NotFound,
             Self::TagAlreadyExists | Self::UserIsAlreadyMaintainer | Self::FolderIdTooLong
             | Self::InvalidNote | Self::FirstFieldEmpty | Self::AmbiguousFields(_) | Self::Serialization(_)
@@ -396,6 +411,7 @@ impl IntoResponse for Error {
     fn into_response(self) -> Response {
         let status_code = match &self {
             Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::InvalidCsrfToken => StatusCode::FORBIDDEN,
             Self::Redirect(_) => StatusCode::FOUND,
             Self::TagAlreadyExists => StatusCode::BAD_REQUEST,
             Self::UserIsAlreadyMaintainer => StatusCode::BAD_REQUEST,
@@ -406,6 +422,9 @@ impl IntoResponse for Error {
             Self::CommitDeckNotFound => StatusCode::NOT_FOUND,
             Self::NoteNotFound(_) => StatusCode::NOT_FOUND,
             Self::DeckNotFound => StatusCode::NOT_FOUND,
+            Self::ExportNotFound | Self::InviteNotFound | Self::ChangelogNotFound => {
+                StatusCode::NOT_FOUND
+            }
             Self::AmbiguousFields(_) => StatusCode::BAD_REQUEST,
             Self::InvalidNote => StatusCode::BAD_REQUEST,
             Self::FirstFieldEmpty => StatusCode::BAD_REQUEST,
@@ -417,6 +436,13 @@ impl IntoResponse for Error {
             }
             Self::Template(_) => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Serialization(_) => StatusCode::BAD_REQUEST,
+            Self::Auth(
+                AuthError::TooManyAttempts
+                | AuthError::TooManySignups
+                | AuthError::UsernameChangeTooSoon,
+            ) => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
             Self::Auth(_) => StatusCode::UNAUTHORIZED,
             Self::Unknown => StatusCode::INTERNAL_SERVER_ERROR,
         };
@@ -471,6 +497,14 @@ pub enum AuthError {
     UserNotFound,
     #[error("Account has been deleted")]
     AccountDeleted,
+    #[error("Account has been suspended")]
+    AccountSuspended,
+    #[error("Too many failed login attempts")]
+    TooManyAttempts,
+    #[error("Too many accounts created from this network")]
+    TooManySignups,
+    #[error("Username was changed recently")]
+    UsernameChangeTooSoon,
 }
 
 impl Clone for AuthError {
@@ -487,6 +521,10 @@ impl Clone for AuthError {
             Self::InvalidToken => Self::InvalidToken,
             Self::UserNotFound => Self::UserNotFound,
             Self::AccountDeleted => Self::AccountDeleted,
+            Self::AccountSuspended => Self::AccountSuspended,
+            Self::TooManyAttempts => Self::TooManyAttempts,
+            Self::TooManySignups => Self::TooManySignups,
+            Self::UsernameChangeTooSoon => Self::UsernameChangeTooSoon,
             Self::Database(_error) => {
                 // tokio_postgres::Error doesn't implement Clone, so we degrade gracefully.
                 Self::PasswordHash("Database Error".to_string())
@@ -522,6 +560,22 @@ impl AuthError {
             Self::UsernameAlreadyExists => (StatusCode::BAD_REQUEST, "Username already in use"),
             Self::PasswordWeak => (StatusCode::BAD_REQUEST, "Password is too weak"),
             Self::AccountDeleted => (StatusCode::FORBIDDEN, "This account has been deleted"),
+            Self::AccountSuspended => (
+                StatusCode::FORBIDDEN,
+                "This account has been suspended. Please contact us if you think this is a mistake",
+            ),
+            Self::TooManyAttempts => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many failed login attempts. Please wait a few minutes and try again",
+            ),
+            Self::TooManySignups => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many accounts have been created from your network. Please try again tomorrow",
+            ),
+            Self::UsernameChangeTooSoon => (
+                StatusCode::TOO_MANY_REQUESTS,
+                "You can only change your username once every 30 days",
+            ),
         }
     }
 }
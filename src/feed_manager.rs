@@ -0,0 +1,292 @@
+//! Atom feeds of deck activity: changelog entries, approved commits and newly added notes.
+//!
+//! Public decks can be followed without an account. Private decks and the feed of the decks
+//! a user maintains need the user's secret feed token, only its hash is stored.
+
+use std::fmt::Write as _;
+use std::sync::Arc;
+
+use chrono::{SecondsFormat, Utc};
+
+use crate::commit_manager::get_string_from_rationale;
+use crate::database;
+use crate::user::{random_token, token_hash, User};
+use crate::{DeckId, Return};
+
+/// Entries per source, the merged feed is cut to the same length
+const FEED_LENGTH: i64 = 50;
+
+pub struct FeedEntry {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    /// RFC 3339 in UTC
+    pub updated: String,
+    /// HTML
+    pub content: String,
+}
+
+pub struct Feed {
+    pub id: String,
+    pub title: String,
+    pub link: String,
+    pub self_link: String,
+    pub entries: Vec<FeedEntry>,
+}
+
+pub fn site_url() -> String {
+    std::env::var("SITE_URL").unwrap_or_else(|_| "https://www.ankicollab.com".to_string())
+}
+
+fn escape_xml(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+impl Feed {
+    pub fn to_atom(&self) -> String {
+        let updated = self.entries.first().map_or_else(
+            || Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            |entry| entry.updated.clone(),
+        );
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+        out.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+        let _ = writeln!(out, "  <id>{}</id>", escape_xml(&self.id));
+        let _ = writeln!(out, "  <title>{}</title>", escape_xml(&self.title));
+        let _ = writeln!(out, "  <updated>{updated}</updated>");
+        let _ = writeln!(out, "  <link rel=\"alternate\" href=\"{}\"/>", escape_xml(&self.link));
+        let _ = writeln!(out, "  <link rel=\"self\" href=\"{}\"/>", escape_xml(&self.self_link));
+        out.push_str("  <author><name>AnkiCollab</name></author>\n");
+        for entry in &self.entries {
+            out.push_str("  <entry>\n");
+            let _ = writeln!(out, "    <id>{}</id>", escape_xml(&entry.id));
+            let _ = writeln!(out, "    <title>{}</title>", escape_xml(&entry.title));
+            let _ = writeln!(out, "    <updated>{}</updated>", entry.updated);
+            let _ = writeln!(
+                out,
+                "    <link rel=\"alternate\" href=\"{}\"/>",
+                escape_xml(&entry.link)
+            );
+            let _ = writeln!(
+                out,
+                "    <content type=\"html\">{}</content>",
+                escape_xml(&entry.content)
+            );
+            out.push_str("  </entry>\n");
+        }
+        out.push_str("</feed>\n");
+        out
+    }
+}
+
+// Every top-level deck in $1 with its subdecks, tagged with the top-level deck
+const DECK_TREE: &str = "
+    WITH RECURSIVE tree AS (
+        SELECT id, id AS root FROM decks WHERE id = ANY($1) AND deleted_at IS NULL
+        UNION ALL
+        SELECT d.id, tree.root FROM decks d JOIN tree ON d.parent = tree.id
+        WHERE d.deleted_at IS NULL
+    )";
+
+const UTC_FORMAT: &str = "'YYYY-MM-DD\"T\"HH24:MI:SS\"Z\"'";
+
+/// Activity of the given top-level decks, newest first. With `prefix_deck` each title starts
+/// with the deck name, for feeds that cover several decks.
+pub async fn deck_entries(
+    db_state: &Arc<database::AppState>,
+    roots: &[DeckId],
+    prefix_deck: bool,
+) -> Return<Vec<FeedEntry>> {
+    let client = database::client(db_state).await?;
+    let site = site_url();
+    let title = |deck: &str, text: String| {
+        if prefix_deck {
+            format!("{deck}: {text}")
+        } else {
+            text
+        }
+    };
+    let mut entries = vec![];
+
+    let changelogs = format!(
+        "{DECK_TREE}
+        SELECT c.id, r.name, r.human_hash,
+               CASE WHEN c.source IS NULL THEN replace(c.message, E'\\n', '<br>') ELSE c.message END,
+               TO_CHAR(c.timestamp::TIMESTAMPTZ AT TIME ZONE 'UTC', {UTC_FORMAT})
+        FROM changelogs c
+        JOIN tree ON tree.id = c.deck
+        JOIN decks r ON r.id = tree.root
+        ORDER BY c.timestamp DESC
+        LIMIT $2"
+    );
+    for row in client.query(&changelogs, &[&roots, &FEED_LENGTH]).await? {
+        let id: i64 = row.get(0);
+        let deck: String = row.get(1);
+        let hash: String = row.get(2);
+        entries.push(FeedEntry {
+            id: format!("urn:ankicollab:changelog:{id}"),
+            title: title(&deck, "New changelog entry".to_string()),
+            link: format!("{site}/changelog/{hash}"),
+            updated: row.get(4),
+            content: row.get(3),
+        });
+    }
+
+    let commits = format!(
+        "{DECK_TREE}
+        SELECT e.commit_id, r.name, r.human_hash, c.rationale, c.info,
+               TO_CHAR(MAX(e.created_at)::TIMESTAMPTZ AT TIME ZONE 'UTC', {UTC_FORMAT}),
+               COUNT(DISTINCT e.note_id)
+        FROM note_events e
+        JOIN notes n ON n.id = e.note_id
+        JOIN tree ON tree.id = n.deck
+        JOIN decks r ON r.id = tree.root
+        JOIN commits c ON c.commit_id = e.commit_id
+        WHERE e.approved IS TRUE
+        GROUP BY e.commit_id, r.name, r.human_hash, c.rationale, c.info
+        ORDER BY MAX(e.created_at) DESC
+        LIMIT $2"
+    );
+    for row in client.query(&commits, &[&roots, &FEED_LENGTH]).await? {
+        let commit_id: i32 = row.get(0);
+        let deck: String = row.get(1);
+        let hash: String = row.get(2);
+        let rationale = get_string_from_rationale(row.get(3));
+        let info: String = row.get(4);
+        let note_count: i64 = row.get(6);
+        let mut content = format!(
+            "<p>{rationale}, {note_count} note{}</p>",
+            if note_count == 1 { "" } else { "s" }
+        );
+        if !info.trim().is_empty() {
+            let _ = write!(content, "<p>{}</p>", escape_xml(&info));
+        }
+        entries.push(FeedEntry {
+            id: format!("urn:ankicollab:commit:{commit_id}:{hash}"),
+            title: title(&deck, format!("Commit #{commit_id} approved")),
+            link: format!("{site}/commit_history/{commit_id}"),
+            updated: row.get(5),
+            content,
+        });
+    }
+
+    // One entry per day, single notes would flood the feed after bulk approvals
+    let new_notes = format!(
+        "{DECK_TREE}
+        SELECT r.name, r.human_hash,
+               TO_CHAR(e.created_at::TIMESTAMPTZ AT TIME ZONE 'UTC', 'YYYY-MM-DD') AS day,
+               COUNT(DISTINCT e.note_id),
+               TO_CHAR(MAX(e.created_at)::TIMESTAMPTZ AT TIME ZONE 'UTC', {UTC_FORMAT})
+        FROM note_events e
+        JOIN notes n ON n.id = e.note_id AND NOT n.deleted
+        JOIN tree ON tree.id = n.deck
+        JOIN decks r ON r.id = tree.root
+        WHERE e.event_type = 'note_created' AND e.approved IS TRUE
+        GROUP BY r.name, r.human_hash, day
+        ORDER BY day DESC
+        LIMIT $2"
+    );
+    for row in client.query(&new_notes, &[&roots, &FEED_LENGTH]).await? {
+        let deck: String = row.get(0);
+        let hash: String = row.get(1);
+        let day: String = row.get(2);
+        let note_count: i64 = row.get(3);
+        let text = format!(
+            "{note_count} new note{}",
+            if note_count == 1 { "" } else { "s" }
+        );
+        entries.push(FeedEntry {
+            id: format!("urn:ankicollab:notes:{hash}:{day}"),
+            title: title(&deck, text.clone()),
+            link: format!("{site}/notes/{hash}"),
+            updated: row.get(4),
+            content: format!("<p>{text} added on {day}</p>"),
+        });
+    }
+
+    // RFC 3339 timestamps in UTC sort chronologically as strings
+    entries.sort_by(|a, b| b.updated.cmp(&a.updated));
+    entries.truncate(FEED_LENGTH as usize);
+    Ok(entries)
+}
+
+/// Top-level decks the user owns or maintains
+pub async fn maintained_decks(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+) -> Return<Vec<DeckId>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            "SELECT id FROM decks
+             WHERE parent IS NULL AND deleted_at IS NULL
+               AND (owner = $1 OR id IN (SELECT deck FROM maintainers WHERE user_id = $1))",
+            &[&user_id],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Creates a new feed token for the user, replacing the previous one
+pub async fn create_token(db_state: &Arc<database::AppState>, user_id: i32) -> Return<String> {
+    let token = random_token();
+    database::client(db_state)
+        .await?
+        .execute(
+            "INSERT INTO feed_tokens (user_id, token_hash) VALUES ($1, $2)
+             ON CONFLICT (user_id) DO UPDATE
+             SET token_hash = EXCLUDED.token_hash, created_at = NOW(), last_used_at = NULL",
+            &[&user_id, &token_hash(&token)],
+        )
+        .await?;
+    Ok(token)
+}
+
+pub async fn revoke_token(db_state: &Arc<database::AppState>, user_id: i32) -> Return<()> {
+    database::client(db_state)
+        .await?
+        .execute("DELETE FROM feed_tokens WHERE user_id = $1", &[&user_id])
+        .await?;
+    Ok(())
+}
+
+/// When the user's current token was created, if they have one
+pub async fn token_created_at(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+) -> Return<Option<String>> {
+    let row = database::client(db_state)
+        .await?
+        .query_opt(
+            "SELECT TO_CHAR(created_at, 'MM/DD/YYYY') FROM feed_tokens WHERE user_id = $1",
+            &[&user_id],
+        )
+        .await?;
+    Ok(row.map(|row| row.get(0)))
+}
+
+pub async fn user_for_token(
+    db_state: &Arc<database::AppState>,
+    token: &str,
+) -> Return<Option<User>> {
+    let row = database::client(db_state)
+        .await?
+        .query_opt(
+            "UPDATE feed_tokens f SET last_used_at = NOW()
+             FROM users u
+             WHERE f.token_hash = $1 AND u.id = f.user_id
+               AND u.deleted_at IS NULL AND u.suspended_at IS NULL
+             RETURNING u.id, u.username, u.is_admin",
+            &[&token_hash(token)],
+        )
+        .await?;
+    Ok(row.map(|row| User {
+        id: row.get(0),
+        username: row.get(1),
+        is_admin: row.get(2),
+    }))
+}
@@ -0,0 +1,312 @@
+//! Find and replace across the reviewed fields of a deck tree as a single "Bulk Suggestion" commit.
+//!
+//! The same search runs twice, once for the dry-run preview and once when the commit is created,
+//! so the commit only contains what the deck looks like at that moment. Protected fields are left
+//! alone unless the user may approve, inherited fields are skipped like in the editor.
+
+use std::sync::Arc;
+
+use regex::{NoExpand, Regex, RegexBuilder};
+use serde::Serialize;
+
+use crate::commit_manager::{BULK_PREVIEW_NOTES, MAX_BULK_NOTES, RATIONALE_BULK_SUGGESTION};
+use crate::database;
+use crate::deck_manager::DECK_TREE;
+use crate::error::Error::BadRequest;
+use crate::permissions::{self, Capability};
+use crate::structs::FieldSuggestionUpdate;
+use crate::suggestion_manager;
+use crate::user::User;
+use crate::{cleanser, DeckId, Return};
+
+/// Compiled size of user patterns
+const PATTERN_SIZE_LIMIT: usize = 1 << 20;
+
+pub struct Search {
+    /// Deck whose tree is searched, also the deck of the commit
+    pub deck: DeckId,
+    pub find: String,
+    pub replace: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub notetype: Option<i64>,
+    /// Field name, fields are matched by name so it works across notetypes
+    pub field: Option<String>,
+    /// Only notes with this tag or one of its children
+    pub tag: Option<String>,
+}
+
+struct Matcher {
+    pattern: Regex,
+    replace: String,
+    regex: bool,
+}
+
+impl Matcher {
+    fn new(search: &Search) -> Return<Self> {
+        if search.find.is_empty() {
+            return Err(BadRequest("Enter the text to find".to_string()));
+        }
+        let source = if search.regex {
+            search.find.clone()
+        } else {
+            regex::escape(&search.find)
+        };
+        let pattern = RegexBuilder::new(&source)
+            .case_insensitive(!search.case_sensitive)
+            .size_limit(PATTERN_SIZE_LIMIT)
+            .build()
+            .map_err(|e| BadRequest(format!("Invalid regular expression: {e}")))?;
+        Ok(Self {
+            pattern,
+            replace: search.replace.clone(),
+            regex: search.regex,
+        })
+    }
+
+    /// The content as the suggestion would store it, `None` if nothing changes
+    fn apply(&self, content: &str) -> Option<String> {
+        if !self.pattern.is_match(content) {
+            return None;
+        }
+        // `$1` style groups only mean something for regular expressions
+        let replaced = if self.regex {
+            self.pattern.replace_all(content, self.replace.as_str())
+        } else {
+            self.pattern.replace_all(content, NoExpand(&self.replace))
+        };
+        let replaced = cleanser::clean(&replaced.replace('\u{200B}', ""));
+        (replaced != cleanser::clean(content)).then_some(replaced)
+    }
+}
+
+struct FieldChange {
+    position: u32,
+    name: String,
+    old: String,
+    new: String,
+}
+
+struct NoteChange {
+    note_id: i64,
+    fields: Vec<FieldChange>,
+}
+
+#[derive(Serialize)]
+pub struct PreviewField {
+    pub name: String,
+    pub diff: String,
+}
+
+#[derive(Serialize)]
+pub struct PreviewNote {
+    pub note_id: i64,
+    pub fields: Vec<PreviewField>,
+}
+
+#[derive(Serialize)]
+pub struct Preview {
+    pub note_count: usize,
+    pub field_count: usize,
+    /// Matching protected fields that are left alone
+    pub protected_skipped: usize,
+    /// The first `BULK_PREVIEW_NOTES` notes
+    pub notes: Vec<PreviewNote>,
+}
+
+#[derive(Serialize)]
+pub struct ReplaceSummary {
+    pub commit_id: i32,
+    pub notes: usize,
+    pub fields: usize,
+}
+
+/// Field names of the notetypes used in the deck tree, for the field filter
+pub async fn field_names(db_state: &Arc<database::AppState>, deck: DeckId) -> Return<Vec<String>> {
+    let rows = database::client(db_state)
+        .await?
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT DISTINCT nf.name FROM notetype_field nf
+                WHERE nf.notetype IN (SELECT n.notetype FROM notes n JOIN tree ON tree.id = n.deck
+                                      WHERE NOT n.deleted)
+                ORDER BY nf.name"
+            ),
+            &[&deck],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+/// Returns the changed notes and the number of matching protected fields that were skipped
+async fn find_changes(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    root: DeckId,
+    search: &Search,
+) -> Return<(Vec<NoteChange>, usize)> {
+    let matcher = Matcher::new(search)?;
+    // Protected fields stay with the maintainers, like in the add-on
+    let may_edit_protected =
+        permissions::has_capability(db_state, user, root, Capability::Approve).await?;
+    let tag = search.tag.as_deref().map(cleanser::clean);
+
+    let rows = database::client(db_state)
+        .await?
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT n.id, f.position, f.content, nf.name, nf.protected
+                FROM notes n
+                JOIN tree ON tree.id = n.deck
+                JOIN fields f ON f.note = n.id AND f.reviewed = true
+                JOIN notetype_field nf ON nf.notetype = n.notetype AND nf.position = f.position
+                LEFT JOIN note_inheritance ni ON ni.subscriber_note_id = n.id
+                WHERE NOT n.deleted
+                  AND ($2::bigint IS NULL OR n.notetype = $2)
+                  AND ($3::text IS NULL OR nf.name = $3)
+                  AND ($4::text IS NULL OR EXISTS (
+                        SELECT 1 FROM tags t WHERE t.note = n.id AND t.reviewed = true
+                          AND (t.content = $4 OR left(t.content, length($4) + 2) = $4 || '::')))
+                  AND (ni.subscriber_note_id IS NULL
+                       OR (ni.subscribed_fields IS NOT NULL
+                           AND NOT (f.position::int = ANY(ni.subscribed_fields))))
+                ORDER BY n.id, f.position"
+            ),
+            &[&search.deck, &search.notetype, &search.field, &tag],
+        )
+        .await?;
+
+    let mut changes: Vec<NoteChange> = vec![];
+    let mut protected_skipped = 0;
+    for row in &rows {
+        let content: String = row.get(2);
+        let Some(new) = matcher.apply(&content) else {
+            continue;
+        };
+        if row.get::<_, bool>(4) && !may_edit_protected {
+            protected_skipped += 1;
+            continue;
+        }
+        let note_id: i64 = row.get(0);
+        let change = FieldChange {
+            position: row.get(1),
+            name: row.get(3),
+            old: content,
+            new,
+        };
+        match changes.last_mut() {
+            Some(note) if note.note_id == note_id => note.fields.push(change),
+            _ => changes.push(NoteChange {
+                note_id,
+                fields: vec![change],
+            }),
+        }
+    }
+    Ok((changes, protected_skipped))
+}
+
+/// Dry run of the search with a diff for every changed field
+pub async fn preview(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    root: DeckId,
+    search: &Search,
+) -> Return<Preview> {
+    let (changes, protected_skipped) = find_changes(db_state, user, root, search).await?;
+    Ok(Preview {
+        note_count: changes.len(),
+        field_count: changes.iter().map(|note| note.fields.len()).sum(),
+        protected_skipped,
+        notes: changes
+            .into_iter()
+            .take(BULK_PREVIEW_NOTES)
+            .map(|note| PreviewNote {
+                note_id: note.note_id,
+                fields: note
+                    .fields
+                    .into_iter()
+                    .map(|field| PreviewField {
+                        // Same baseline as the review page
+                        diff: htmldiff::htmldiff(&cleanser::clean(&field.old), &field.new),
+                        name: field.name,
+                    })
+                    .collect(),
+            })
+            .collect(),
+    })
+}
+
+/// Runs the search again and stores the result as one pending commit
+pub async fn replace(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    client_ip: &str,
+    root: DeckId,
+    search: &Search,
+    info: &str,
+) -> Return<ReplaceSummary> {
+    let (changes, _) = find_changes(db_state, user, root, search).await?;
+    if changes.is_empty() {
+        return Err(BadRequest("Nothing matches the search anymore".to_string()));
+    }
+    if changes.len() > MAX_BULK_NOTES {
+        return Err(BadRequest(format!(
+            "The search changes {} notes, narrow it down to at most {MAX_BULK_NOTES}",
+            changes.len()
+        )));
+    }
+
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+    let info = cleanser::clean(&if info.trim().is_empty() {
+        format!("Replaced \"{}\" with \"{}\"", search.find, search.replace)
+    } else {
+        info.trim().to_string()
+    });
+    let commit_id: i32 = tx
+        .query_one(
+            "INSERT INTO commits (rationale, info, timestamp, deck, user_id)
+             VALUES ($1, $2, NOW(), $3, $4) RETURNING commit_id",
+            &[&RATIONALE_BULK_SUGGESTION, &info, &search.deck, &user.id()],
+        )
+        .await?
+        .get(0);
+
+    let mut summary = ReplaceSummary {
+        commit_id,
+        notes: 0,
+        fields: 0,
+    };
+    for note in changes {
+        let updates: Vec<FieldSuggestionUpdate> = note
+            .fields
+            .into_iter()
+            .map(|field| FieldSuggestionUpdate {
+                position: field.position,
+                content: field.new,
+            })
+            .collect();
+        let results = suggestion_manager::batch_create_or_update_field_suggestions(
+            &tx,
+            note.note_id,
+            commit_id,
+            &updates,
+            user.id(),
+            client_ip,
+        )
+        .await?;
+        let created = results.iter().filter(|r| r.action == "created").count();
+        if created > 0 {
+            summary.notes += 1;
+            summary.fields += created;
+        }
+    }
+
+    if summary.fields == 0 {
+        return Err(BadRequest("Nothing matches the search anymore".to_string()));
+    }
+    tx.commit().await?;
+    Ok(summary)
+}
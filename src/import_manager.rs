@@ -0,0 +1,441 @@
+//! Bulk import of notes from CSV/TSV files as a single "Bulk Suggestion" commit.
+//!
+//! Columns are mapped to the fields of one notetype, to tags or to the note GUID. Rows whose
+//! GUID matches a note of the deck become field and tag suggestions on that note, all other rows
+//! become new unreviewed notes. Maintainers review the result like any other commit.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::commit_manager::{MAX_BULK_NOTES, RATIONALE_BULK_SUGGESTION};
+use crate::database;
+use crate::deck_manager::DECK_TREE;
+use crate::error::Error::BadRequest;
+use crate::permissions::{self, Capability};
+use crate::structs::FieldSuggestionUpdate;
+use crate::suggestion_manager;
+use crate::user::User;
+use crate::{cleanser, DeckId, Return};
+
+/// Rows shown on the mapping step
+pub const PREVIEW_ROWS: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ColumnTarget {
+    Ignore,
+    Field(u32),
+    Tags,
+    Guid,
+}
+
+impl ColumnTarget {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "" | "ignore" => Some(Self::Ignore),
+            "tags" => Some(Self::Tags),
+            "guid" => Some(Self::Guid),
+            _ => value
+                .strip_prefix("field:")
+                .and_then(|position| position.parse().ok())
+                .map(Self::Field),
+        }
+    }
+
+    pub fn to_value(self) -> String {
+        match self {
+            Self::Ignore => "ignore".to_string(),
+            Self::Field(position) => format!("field:{position}"),
+            Self::Tags => "tags".to_string(),
+            Self::Guid => "guid".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Clone)]
+pub struct ImportField {
+    pub position: u32,
+    pub name: String,
+    pub protected: bool,
+}
+
+#[derive(Serialize)]
+pub struct PreviewColumn {
+    pub index: usize,
+    pub label: String,
+    pub samples: Vec<String>,
+    pub target: String,
+}
+
+#[derive(Serialize)]
+pub struct ImportSummary {
+    pub commit_id: i32,
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+}
+
+/// Comma unless the first line has more tabs or semicolons
+pub fn detect_delimiter(content: &str) -> char {
+    let first_line = content.lines().next().unwrap_or_default();
+    [',', '\t', ';']
+        .into_iter()
+        .max_by_key(|&d| (first_line.matches(d).count(), d == ','))
+        .unwrap_or(',')
+}
+
+/// RFC 4180 style parsing: quoted values may contain the delimiter, line breaks and doubled
+/// quotes. Blank lines are dropped.
+pub fn parse_delimited(content: &str, delimiter: char) -> Vec<Vec<String>> {
+    let content = content.trim_start_matches('\u{feff}');
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut value = String::new();
+    let mut in_quotes = false;
+    let mut chars = content.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            if c == '"' {
+                if chars.peek() == Some(&'"') {
+                    value.push('"');
+                    chars.next();
+                } else {
+                    in_quotes = false;
+                }
+            } else {
+                value.push(c);
+            }
+        } else if c == '"' && value.is_empty() {
+            in_quotes = true;
+        } else if c == delimiter {
+            row.push(std::mem::take(&mut value));
+        } else if c == '\n' || c == '\r' {
+            if c == '\r' && chars.peek() == Some(&'\n') {
+                chars.next();
+            }
+            row.push(std::mem::take(&mut value));
+            if row.iter().any(|v| !v.trim().is_empty()) {
+                rows.push(std::mem::take(&mut row));
+            } else {
+                row.clear();
+            }
+        } else {
+            value.push(c);
+        }
+    }
+    row.push(value);
+    if row.iter().any(|v| !v.trim().is_empty()) {
+        rows.push(row);
+    }
+    rows
+}
+
+/// Guesses the target of each column from the header (field name, "tags" or "guid"). Without a
+/// header the columns are taken as the fields in order.
+pub fn default_mapping(
+    header: Option<&[String]>,
+    fields: &[ImportField],
+    columns: usize,
+) -> Vec<ColumnTarget> {
+    (0..columns)
+        .map(|i| match header.and_then(|h| h.get(i)) {
+            Some(name) => {
+                let name = name.trim();
+                if name.eq_ignore_ascii_case("tags") {
+                    ColumnTarget::Tags
+                } else if name.eq_ignore_ascii_case("guid") {
+                    ColumnTarget::Guid
+                } else {
+                    fields
+                        .iter()
+                        .find(|f| f.name.eq_ignore_ascii_case(name))
+                        .map_or(ColumnTarget::Ignore, |f| ColumnTarget::Field(f.position))
+                }
+            }
+            None => fields
+                .get(i)
+                .map_or(ColumnTarget::Ignore, |f| ColumnTarget::Field(f.position)),
+        })
+        .collect()
+}
+
+pub fn preview_columns(
+    header: Option<&[String]>,
+    rows: &[Vec<String>],
+    mapping: &[ColumnTarget],
+) -> Vec<PreviewColumn> {
+    mapping
+        .iter()
+        .enumerate()
+        .map(|(index, target)| PreviewColumn {
+            index,
+            label: header
+                .and_then(|h| h.get(index))
+                .filter(|label| !label.trim().is_empty())
+                .cloned()
+                .unwrap_or_else(|| format!("Column {}", index + 1)),
+            samples: rows
+                .iter()
+                .take(PREVIEW_ROWS)
+                .map(|row| row.get(index).cloned().unwrap_or_default())
+                .collect(),
+            target: target.to_value(),
+        })
+        .collect()
+}
+
+pub async fn notetype_fields(
+    db_state: &Arc<database::AppState>,
+    notetype: i64,
+) -> Return<Vec<ImportField>> {
+    let rows = database::client(db_state)
+        .await?
+        .query(
+            "SELECT position, name, protected FROM notetype_field
+             WHERE notetype = $1 ORDER BY position",
+            &[&notetype],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| ImportField {
+            position: row.get(0),
+            name: row.get(1),
+            protected: row.get(2),
+        })
+        .collect())
+}
+
+/// Anki tags are separated by whitespace
+fn row_tags(row: &[String], mapping: &[ColumnTarget]) -> Vec<String> {
+    let mut tags = vec![];
+    for (value, target) in row.iter().zip(mapping) {
+        if *target == ColumnTarget::Tags {
+            for tag in value.split_whitespace() {
+                let tag = cleanser::clean(tag);
+                if !tag.is_empty() && !tags.contains(&tag) {
+                    tags.push(tag);
+                }
+            }
+        }
+    }
+    tags
+}
+
+pub struct ImportPlan {
+    /// Top-level deck the GUIDs are matched in
+    pub root: DeckId,
+    /// Deck new notes are added to
+    pub target: DeckId,
+    pub notetype: i64,
+    /// Commit description
+    pub info: String,
+    pub mapping: Vec<ColumnTarget>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Creates one pending commit from the rows of the plan
+pub async fn import_rows(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    client_ip: &str,
+    plan: &ImportPlan,
+) -> Return<ImportSummary> {
+    let ImportPlan {
+        root,
+        target,
+        notetype,
+        ref info,
+        ref mapping,
+        ref rows,
+    } = *plan;
+    if rows.is_empty() {
+        return Err(BadRequest("The file has no rows to import".to_string()));
+    }
+    if rows.len() > MAX_BULK_NOTES {
+        return Err(BadRequest(format!(
+            "At most {MAX_BULK_NOTES} rows can be imported at once, split the file"
+        )));
+    }
+    let fields = notetype_fields(db_state, notetype).await?;
+    if !mapping
+        .iter()
+        .any(|target| matches!(target, ColumnTarget::Field(_)))
+    {
+        return Err(BadRequest("Map at least one column to a field".to_string()));
+    }
+    let valid: HashSet<u32> = fields.iter().map(|f| f.position).collect();
+    let protected: HashSet<u32> = fields
+        .iter()
+        .filter(|f| f.protected)
+        .map(|f| f.position)
+        .collect();
+    // Protected fields stay with the maintainers, like in the add-on
+    let may_edit_protected =
+        permissions::has_capability(db_state, user, root, Capability::Approve).await?;
+
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+    let info = if info.trim().is_empty() {
+        format!("Imported {} rows from a spreadsheet", rows.len())
+    } else {
+        cleanser::clean(info.trim())
+    };
+    let commit_id: i32 = tx
+        .query_one(
+            "INSERT INTO commits (rationale, info, timestamp, deck, user_id)
+             VALUES ($1, $2, NOW(), $3, $4) RETURNING commit_id",
+            &[&RATIONALE_BULK_SUGGESTION, &info, &target, &user.id()],
+        )
+        .await?
+        .get(0);
+
+    let find_note = tx
+        .prepare(&format!(
+            "{DECK_TREE}
+            SELECT n.id, n.notetype FROM notes n JOIN tree ON tree.id = n.deck
+            WHERE n.guid = $2 AND NOT n.deleted"
+        ))
+        .await?;
+    let mut summary = ImportSummary {
+        commit_id,
+        created: 0,
+        updated: 0,
+        skipped: 0,
+    };
+    let mut seen_guids = HashSet::new();
+
+    for row in rows {
+        let mut values: HashMap<u32, String> = HashMap::new();
+        let mut guid = String::new();
+        for (value, target) in row.iter().zip(mapping) {
+            match target {
+                ColumnTarget::Field(position) if valid.contains(position) => {
+                    values.insert(*position, value.clone());
+                }
+                ColumnTarget::Guid => guid = value.trim().to_string(),
+                _ => {}
+            }
+        }
+        let tags = row_tags(row, mapping);
+
+        if !guid.is_empty() && !seen_guids.insert(guid.clone()) {
+            summary.skipped += 1;
+            continue;
+        }
+
+        let existing = if guid.is_empty() {
+            None
+        } else {
+            tx.query_opt(&find_note, &[&root, &guid]).await?
+        };
+
+        if let Some(note) = existing {
+            let note_id: i64 = note.get(0);
+            if note.get::<_, i64>(1) != notetype {
+                summary.skipped += 1;
+                continue;
+            }
+            let updates: Vec<FieldSuggestionUpdate> = values
+                .into_iter()
+                .filter(|(position, _)| may_edit_protected || !protected.contains(position))
+                .map(|(position, content)| FieldSuggestionUpdate { position, content })
+                .collect();
+            let results = suggestion_manager::batch_create_or_update_field_suggestions(
+                &tx, note_id, commit_id, &updates, user.id(), client_ip,
+            )
+            .await?;
+            let mut changed = results.iter().any(|r| r.action == "created");
+
+            let current_tags: HashSet<String> = tx
+                .query(
+                    "SELECT content FROM tags WHERE note = $1 AND reviewed = true AND content IS NOT NULL",
+                    &[&note_id],
+                )
+                .await?
+                .iter()
+                .map(|r| r.get(0))
+                .collect();
+            for tag in tags.iter().filter(|tag| !current_tags.contains(*tag)) {
+                suggestion_manager::create_commit_tag_suggestion(
+                    &tx,
+                    note_id,
+                    commit_id,
+                    tag,
+                    true,
+                    user.id(),
+                    client_ip,
+                )
+                .await?;
+                changed = true;
+            }
+
+            if changed {
+                summary.updated += 1;
+            } else {
+                summary.skipped += 1;
+            }
+            continue;
+        }
+
+        // New note. A GUID that is taken outside this deck can't be reused
+        let first_field = values
+            .get(&0)
+            .map(|content| cleanser::clean(&content.replace('\u{200B}', "")))
+            .unwrap_or_default();
+        if first_field.trim().is_empty() {
+            summary.skipped += 1;
+            continue;
+        }
+        if !guid.is_empty()
+            && tx
+                .query_opt("SELECT 1 FROM notes WHERE guid = $1", &[&guid])
+                .await?
+                .is_some()
+        {
+            summary.skipped += 1;
+            continue;
+        }
+        let note_id: i64 = tx
+            .query_one(
+                "INSERT INTO notes (guid, notetype, deck, last_update, reviewed)
+                 VALUES (COALESCE(NULLIF($1, ''), substr(md5(random()::text || clock_timestamp()::text), 1, 10)),
+                         $2, $3, NOW(), false)
+                 RETURNING id",
+                &[&guid, &notetype, &target],
+            )
+            .await?
+            .get(0);
+        for (position, content) in &values {
+            let content = cleanser::clean(&content.replace('\u{200B}', ""));
+            if content.is_empty() {
+                continue;
+            }
+            tx.execute(
+                "INSERT INTO fields (note, position, content, creator_ip, commit, reviewed)
+                 VALUES ($1, $2, $3, $4, $5, false)",
+                &[&note_id, position, &content, &client_ip, &commit_id],
+            )
+            .await?;
+        }
+        for tag in &tags {
+            tx.execute(
+                "INSERT INTO tags (note, content, reviewed, action, commit, creator_ip)
+                 VALUES ($1, $2, false, true, $3, $4)",
+                &[&note_id, tag, &commit_id, &client_ip],
+            )
+            .await?;
+        }
+        summary.created += 1;
+    }
+
+    if summary.created == 0 && summary.updated == 0 {
+        return Err(BadRequest(
+            "None of the rows would change the deck, nothing was imported".to_string(),
+        ));
+    }
+    tx.commit().await?;
+    Ok(summary)
+}
@@ -0,0 +1,207 @@
+//! Invite links for private decks.
+//!
+//! The link carries a random token, only its hash is stored. Redeeming a link gives the
+//! user a `deck_access_grants` row with the access level of the invite. Revoking an invite
+//! also removes the grants that were handed out through it.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::database;
+use crate::error::Error::{BadRequest, InviteNotFound};
+use crate::permissions::DeckAccess;
+use crate::user::{random_token, token_hash, User};
+use crate::{DeckId, Return};
+
+const MAX_LABEL_LEN: usize = 100;
+const MAX_EXPIRY_DAYS: i32 = 365;
+
+#[derive(Serialize)]
+pub struct InviteInfo {
+    pub id: i64,
+    pub label: String,
+    pub access: String,
+    pub created_by: Option<String>,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    pub max_uses: Option<i32>,
+    pub use_count: i32,
+    pub active: bool,
+}
+
+/// Creates an invite for the top-level deck `deck_id` and returns the token for the link
+pub async fn create_invite(
+    db_state: &Arc<database::AppState>,
+    creator: &User,
+    deck_id: DeckId,
+    access: DeckAccess,
+    label: &str,
+    expires_in_days: Option<i32>,
+    max_uses: Option<i32>,
+) -> Return<String> {
+    let access = match access {
+        DeckAccess::View => "view",
+        DeckAccess::Subscribe => "subscribe",
+        DeckAccess::None => return Err(BadRequest("Unknown access level".to_string())),
+    };
+    let label = label.trim();
+    if label.chars().count() > MAX_LABEL_LEN {
+        return Err(BadRequest(format!(
+            "Please keep the label below {MAX_LABEL_LEN} characters"
+        )));
+    }
+    if expires_in_days.is_some_and(|days| !(1..=MAX_EXPIRY_DAYS).contains(&days)) {
+        return Err(BadRequest(format!(
+            "Links can expire after 1 to {MAX_EXPIRY_DAYS} days"
+        )));
+    }
+    if max_uses.is_some_and(|uses| uses < 1) {
+        return Err(BadRequest("The usage limit must be at least 1".to_string()));
+    }
+
+    let client = database::client(db_state).await?;
+    // `deck_access` looks grants up on the top-level deck, an invite for a subdeck would never apply
+    let is_root: bool = client
+        .query_one("SELECT parent IS NULL FROM decks WHERE id = $1", &[&deck_id])
+        .await?
+        .get(0);
+    if !is_root {
+        return Err(BadRequest(
+            "Invite links can only be created for top-level decks".to_string(),
+        ));
+    }
+
+    let token = random_token();
+    client
+        .execute(
+            "INSERT INTO deck_invites (deck_id, token_hash, access, label, created_by, expires_at, max_uses)
+             VALUES ($1, $2, $3, $4, $5, NOW() + make_interval(days => $6), $7)",
+            &[
+                &deck_id,
+                &token_hash(&token),
+                &access,
+                &label,
+                &creator.id(),
+                &expires_in_days,
+                &max_uses,
+            ],
+        )
+        .await?;
+    Ok(token)
+}
+
+pub async fn list_invites(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+) -> Return<Vec<InviteInfo>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            "SELECT i.id, i.label, i.access, u.username,
+                    TO_CHAR(i.created_at, 'MM/DD/YYYY'),
+                    TO_CHAR(i.expires_at, 'MM/DD/YYYY HH24:MI'),
+                    i.max_uses, i.use_count,
+                    i.revoked_at IS NULL
+                        AND (i.expires_at IS NULL OR i.expires_at > NOW())
+                        AND (i.max_uses IS NULL OR i.use_count < i.max_uses)
+             FROM deck_invites i
+             LEFT JOIN users u ON u.id = i.created_by
+             WHERE i.deck_id = $1 AND i.revoked_at IS NULL
+             ORDER BY i.created_at DESC",
+            &[&deck_id],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| InviteInfo {
+            id: row.get(0),
+            label: row.get(1),
+            access: row.get(2),
+            created_by: row.get(3),
+            created_at: row.get(4),
+            expires_at: row.get(5),
+            max_uses: row.get(6),
+            use_count: row.get(7),
+            active: row.get(8),
+        })
+        .collect())
+}
+
+pub async fn revoke_invite(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+    invite_id: i64,
+) -> Return<()> {
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+    let updated = tx
+        .execute(
+            "UPDATE deck_invites SET revoked_at = NOW()
+             WHERE id = $1 AND deck_id = $2 AND revoked_at IS NULL",
+            &[&invite_id, &deck_id],
+        )
+        .await?;
+    if updated == 0 {
+        return Err(InviteNotFound);
+    }
+    tx.execute(
+        "DELETE FROM deck_access_grants WHERE invite_id = $1",
+        &[&invite_id],
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Grants `user` access through the invite behind `token`. Returns the deck hash to redirect to.
+pub async fn redeem_invite(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    token: &str,
+) -> Return<String> {
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    // Lock the invite so concurrent redemptions cannot exceed the usage limit
+    let invite = tx
+        .query_opt(
+            "SELECT i.id, i.deck_id, i.access, d.human_hash
+             FROM deck_invites i
+             JOIN decks d ON d.id = i.deck_id
+             WHERE i.token_hash = $1 AND i.revoked_at IS NULL
+               AND (i.expires_at IS NULL OR i.expires_at > NOW())
+               AND (i.max_uses IS NULL OR i.use_count < i.max_uses)
+               AND d.deleted_at IS NULL
+             FOR UPDATE OF i",
+            &[&token_hash(token)],
+        )
+        .await?
+        .ok_or(InviteNotFound)?;
+    let invite_id: i64 = invite.get(0);
+    let deck_id: DeckId = invite.get(1);
+    let access: String = invite.get(2);
+    let deck_hash: String = invite.get(3);
+
+    // Never downgrade an existing grant, and only count users that gained something
+    let changed = tx
+        .execute(
+            "INSERT INTO deck_access_grants (deck_id, user_id, access, invite_id)
+             VALUES ($1, $2, $3, $4)
+             ON CONFLICT (deck_id, user_id) DO UPDATE
+                SET access = EXCLUDED.access, invite_id = EXCLUDED.invite_id
+                WHERE deck_access_grants.access = 'view' AND EXCLUDED.access = 'subscribe'",
+            &[&deck_id, &user.id(), &access, &invite_id],
+        )
+        .await?;
+    if changed > 0 {
+        tx.execute(
+            "UPDATE deck_invites SET use_count = use_count + 1 WHERE id = $1",
+            &[&invite_id],
+        )
+        .await?;
+    }
+    tx.commit().await?;
+
+    Ok(deck_hash)
+}
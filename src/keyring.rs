@@ -0,0 +1,193 @@
+//! Signing keys with ids, so secrets can be rotated without invalidating every token.
+//!
+//! New tokens are always signed with the active key and carry its `kid`. Verification
+//! accepts every configured key that is not retired yet. A rotation therefore looks like:
+//! add the new key, make it active, schedule the old one for retirement once all tokens
+//! signed with it have expired, and finally remove it.
+//!
+//! Configuration for a prefix such as `JWT`:
+//! - `JWT_KEYS`: comma separated `kid:secret` pairs, e.g. `2025a:...,2025b:...`
+//! - `JWT_ACTIVE_KID`: kid used for signing, defaults to the last key in `JWT_KEYS`
+//! - `JWT_RETIRE`: optional comma separated `kid=RFC3339 timestamp` pairs
+//!
+//! Without `JWT_KEYS` the old single `JWT_SECRET` is used under the kid `default`. Tokens
+//! without a kid are verified with the `default` key, so keep the old secret around under
+//! that kid while migrating.
+
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+/// Kid of keys configured through the legacy single-secret variable and of tokens without a kid
+pub const DEFAULT_KID: &str = "default";
+
+#[derive(Clone)]
+pub struct SigningKey {
+    pub kid: String,
+    secret: Vec<u8>,
+    retire_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    #[must_use]
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    fn is_retired(&self) -> bool {
+        self.retire_at.is_some_and(|at| Utc::now() >= at)
+    }
+}
+
+#[derive(Clone)]
+pub struct Keyring {
+    keys: Arc<Vec<SigningKey>>,
+    active: usize,
+}
+
+impl fmt::Debug for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kids: Vec<&str> = self.keys.iter().map(|k| k.kid.as_str()).collect();
+        f.debug_struct("Keyring")
+            .field("kids", &kids)
+            .field("active", &self.keys[self.active].kid)
+            .finish()
+    }
+}
+
+impl Keyring {
+    /// Keyring with a single key, e.g. from a legacy `*_SECRET` variable
+    pub fn single(kid: &str, secret: Vec<u8>, min_len: usize) -> Result<Self, KeyringError> {
+        Self::build(
+            vec![SigningKey {
+                kid: kid.to_owned(),
+                secret,
+                retire_at: None,
+            }],
+            None,
+            min_len,
+        )
+    }
+
+    /// Reads `{prefix}_KEYS`, `{prefix}_ACTIVE_KID` and `{prefix}_RETIRE`, falling back to
+    /// `{prefix}_SECRET`. Every secret must be at least `min_len` bytes.
+    pub fn from_env(prefix: &str, min_len: usize) -> Result<Self, KeyringError> {
+        let Ok(key_list) = env::var(format!("{prefix}_KEYS")) else {
+            let secret = env::var(format!("{prefix}_SECRET"))
+                .map_err(|_| KeyringError::Missing(format!("{prefix}_KEYS or {prefix}_SECRET")))?;
+            return Self::single(DEFAULT_KID, secret.into_bytes(), min_len);
+        };
+
+        let mut keys = Vec::new();
+        for entry in key_list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (kid, secret) = entry
+                .split_once(':')
+                .ok_or_else(|| KeyringError::Malformed(format!("{prefix}_KEYS entry without kid")))?;
+            keys.push(SigningKey {
+                kid: kid.trim().to_owned(),
+                secret: secret.trim().as_bytes().to_vec(),
+                retire_at: None,
+            });
+        }
+
+        if let Ok(retire_list) = env::var(format!("{prefix}_RETIRE")) {
+            for entry in retire_list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+                let (kid, at) = entry.split_once('=').ok_or_else(|| {
+                    KeyringError::Malformed(format!("{prefix}_RETIRE entries must be kid=timestamp"))
+                })?;
+                let at = DateTime::parse_from_rfc3339(at.trim())
+                    .map_err(|_| KeyringError::Malformed(format!("{prefix}_RETIRE timestamp for {kid}")))?
+                    .with_timezone(&Utc);
+                let key = keys
+                    .iter_mut()
+                    .find(|k| k.kid == kid.trim())
+                    .ok_or_else(|| KeyringError::UnknownKid(kid.trim().to_owned()))?;
+                key.retire_at = Some(at);
+            }
+        }
+
+        let active_kid = env::var(format!("{prefix}_ACTIVE_KID")).ok();
+        Self::build(keys, active_kid.as_deref(), min_len)
+    }
+
+    fn build(
+        keys: Vec<SigningKey>,
+        active_kid: Option<&str>,
+        min_len: usize,
+    ) -> Result<Self, KeyringError> {
+        if keys.is_empty() {
+            return Err(KeyringError::Missing("signing keys".to_string()));
+        }
+        for (i, key) in keys.iter().enumerate() {
+            if key.kid.is_empty() {
+                return Err(KeyringError::Malformed("empty kid".to_string()));
+            }
+            if key.secret.len() < min_len {
+                return Err(KeyringError::TooShort(key.kid.clone(), min_len));
+            }
+            if keys[..i].iter().any(|k| k.kid == key.kid) {
+                return Err(KeyringError::Malformed(format!("duplicate kid {}", key.kid)));
+            }
+        }
+
+        let active = match active_kid {
+            Some(kid) => keys
+                .iter()
+                .position(|k| k.kid == kid)
+                .ok_or_else(|| KeyringError::UnknownKid(kid.to_owned()))?,
+            None => keys.len() - 1,
+        };
+        if keys[active].retire_at.is_some() {
+            return Err(KeyringError::ActiveKeyRetiring(keys[active].kid.clone()));
+        }
+
+        Ok(Self {
+            keys: Arc::new(keys),
+            active,
+        })
+    }
+
+    /// Key for signing new tokens
+    #[must_use]
+    pub fn active(&self) -> &SigningKey {
+        &self.keys[self.active]
+    }
+
+    /// Key for verifying a token. Tokens without a kid map to `DEFAULT_KID`.
+    /// Retired keys are not returned.
+    #[must_use]
+    pub fn verification_key(&self, kid: Option<&str>) -> Option<&SigningKey> {
+        let kid = kid.unwrap_or(DEFAULT_KID);
+        self.keys
+            .iter()
+            .find(|k| k.kid == kid)
+            .filter(|k| !k.is_retired())
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyringError {
+    Missing(String),
+    Malformed(String),
+    UnknownKid(String),
+    TooShort(String, usize),
+    ActiveKeyRetiring(String),
+}
+
+impl fmt::Display for KeyringError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Missing(what) => write!(f, "{what} must be set"),
+            Self::Malformed(what) => write!(f, "Malformed key configuration: {what}"),
+            Self::UnknownKid(kid) => write!(f, "Unknown key id: {kid}"),
+            Self::TooShort(kid, len) => write!(f, "Key {kid} must be at least {len} bytes"),
+            Self::ActiveKeyRetiring(kid) => {
+                write!(f, "Active key {kid} must not be scheduled for retirement")
+            }
+        }
+    }
+}
+
+impl std::error::Error for KeyringError {}
@@ -0,0 +1,7 @@
+#![warn(clippy::all, clippy::pedantic, clippy::nursery)]
+
+//! Code shared by the website and the command line tools in `src/bin`. It must not depend on
+//! the website's state, the tools only get a database connection.
+
+pub mod csv;
+pub mod note_export;
@@ -0,0 +1,204 @@
+//! Brute-force protection for the login and signup forms.
+//!
+//! Failed logins are counted per username (reset by a successful login) and per IP.
+//! Once a source crosses its threshold every further failure doubles the lockout,
+//! capped at `MAX_LOCKOUT_SECS`. Signups are rate limited per `signup_ip`.
+
+use std::net::IpAddr;
+
+use serde::Serialize;
+use tokio_postgres::Client;
+
+use crate::error::AuthError;
+
+/// Failures per username before the lockout kicks in
+const USERNAME_THRESHOLD: i64 = 5;
+/// Failures per IP (across all usernames) before the lockout kicks in
+const IP_THRESHOLD: i64 = 20;
+const BASE_LOCKOUT_SECS: i64 = 30;
+const MAX_LOCKOUT_SECS: i64 = 60 * 60;
+/// Accounts that may be created from the same IP within 24 hours
+const MAX_SIGNUPS_PER_IP: i64 = 3;
+/// We don't need to store absurdly long garbage usernames
+const MAX_USERNAME_LEN: usize = 64;
+
+#[derive(Debug, Serialize, Clone)]
+pub struct BlockedSource {
+    pub kind: String,
+    pub value: String,
+    pub failures: i64,
+    pub last_attempt: String,
+    pub remaining_secs: i64,
+}
+
+fn lockout_secs(failures: i64, threshold: i64) -> i64 {
+    if failures < threshold {
+        return 0;
+    }
+    let exponent = u32::try_from((failures - threshold).min(16)).unwrap_or(16);
+    BASE_LOCKOUT_SECS
+        .saturating_mul(2_i64.pow(exponent))
+        .min(MAX_LOCKOUT_SECS)
+}
+
+fn normalize(username: &str) -> String {
+    username
+        .trim()
+        .to_lowercase()
+        .chars()
+        .take(MAX_USERNAME_LEN)
+        .collect()
+}
+
+const USERNAME_FAILURES_QUERY: &str = "
+    SELECT COUNT(*)::BIGINT,
+           COALESCE(EXTRACT(EPOCH FROM (NOW() - MAX(attempted_at)))::BIGINT, 0)
+    FROM login_attempts
+    WHERE username = $1 AND NOT success
+      AND attempted_at > NOW() - INTERVAL '24 hours'
+      AND attempted_at > COALESCE(
+            (SELECT MAX(attempted_at) FROM login_attempts WHERE username = $1 AND success),
+            '-infinity'::TIMESTAMPTZ)";
+
+// A successful login does not reset the IP counter, otherwise an attacker
+// with one valid account could keep resetting it.
+const IP_FAILURES_QUERY: &str = "
+    SELECT COUNT(*)::BIGINT,
+           COALESCE(EXTRACT(EPOCH FROM (NOW() - MAX(attempted_at)))::BIGINT, 0)
+    FROM login_attempts
+    WHERE ip_address = $1::INET AND NOT success
+      AND attempted_at > NOW() - INTERVAL '1 hour'";
+
+/// Returns `Err(AuthError::TooManyAttempts)` if either the username or the IP is currently locked out.
+pub async fn check_login_allowed(db: &Client, username: &str, ip: IpAddr) -> Result<(), AuthError> {
+    let username = normalize(username);
+
+    let row = db.query_one(USERNAME_FAILURES_QUERY, &[&username]).await?;
+    let (failures, since_last): (i64, i64) = (row.get(0), row.get(1));
+    if since_last < lockout_secs(failures, USERNAME_THRESHOLD) {
+        return Err(AuthError::TooManyAttempts);
+    }
+
+    let row = db.query_one(IP_FAILURES_QUERY, &[&ip]).await?;
+    let (failures, since_last): (i64, i64) = (row.get(0), row.get(1));
+    if since_last < lockout_secs(failures, IP_THRESHOLD) {
+        return Err(AuthError::TooManyAttempts);
+    }
+
+    Ok(())
+}
+
+/// Best-effort: a failing insert must never block a login.
+pub async fn record_login_attempt(db: &Client, username: &str, ip: IpAddr, success: bool) {
+    let username = normalize(username);
+    if let Err(e) = db
+        .execute(
+            "INSERT INTO login_attempts (username, ip_address, success) VALUES ($1, $2::INET, $3)",
+            &[&username, &ip, &success],
+        )
+        .await
+    {
+        tracing::warn!(error = %e, "Failed to record login attempt");
+    }
+}
+
+pub async fn check_signup_allowed(db: &Client, ip: IpAddr) -> Result<(), AuthError> {
+    let recent_signups: i64 = db
+        .query_one(
+            "SELECT COUNT(*) FROM users
+             WHERE signup_ip = $1::INET AND created_at > NOW() - INTERVAL '24 hours'",
+            &[&ip],
+        )
+        .await?
+        .get(0);
+
+    if recent_signups >= MAX_SIGNUPS_PER_IP {
+        return Err(AuthError::TooManySignups);
+    }
+    Ok(())
+}
+
+/// All usernames and IPs that are over their threshold, for the admin overview.
+pub async fn get_blocked_sources(db: &Client) -> Result<Vec<BlockedSource>, tokio_postgres::Error> {
+    let mut sources = Vec::new();
+
+    let username_rows = db
+        .query(
+            "SELECT la.username, COUNT(*)::BIGINT,
+                    TO_CHAR(MAX(la.attempted_at), 'MM/DD/YYYY HH24:MI:SS'),
+                    EXTRACT(EPOCH FROM (NOW() - MAX(la.attempted_at)))::BIGINT
+             FROM login_attempts la
+             WHERE NOT la.success
+               AND la.attempted_at > NOW() - INTERVAL '24 hours'
+               AND la.attempted_at > COALESCE(
+                    (SELECT MAX(s.attempted_at) FROM login_attempts s
+                     WHERE s.username = la.username AND s.success),
+                    '-infinity'::TIMESTAMPTZ)
+             GROUP BY la.username
+             HAVING COUNT(*) >= $1
+             ORDER BY MAX(la.attempted_at) DESC",
+            &[&USERNAME_THRESHOLD],
+        )
+        .await?;
+
+    for row in username_rows {
+        let failures: i64 = row.get(1);
+        let since_last: i64 = row.get(3);
+        sources.push(BlockedSource {
+            kind: "username".to_string(),
+            value: row.get(0),
+            failures,
+            last_attempt: row.get(2),
+            remaining_secs: (lockout_secs(failures, USERNAME_THRESHOLD) - since_last).max(0),
+        });
+    }
+
+    let ip_rows = db
+        .query(
+            "SELECT HOST(ip_address), COUNT(*)::BIGINT,
+                    TO_CHAR(MAX(attempted_at), 'MM/DD/YYYY HH24:MI:SS'),
+                    EXTRACT(EPOCH FROM (NOW() - MAX(attempted_at)))::BIGINT
+             FROM login_attempts
+             WHERE NOT success AND attempted_at > NOW() - INTERVAL '1 hour'
+             GROUP BY ip_address
+             HAVING COUNT(*) >= $1
+             ORDER BY MAX(attempted_at) DESC",
+            &[&IP_THRESHOLD],
+        )
+        .await?;
+
+    for row in ip_rows {
+        let failures: i64 = row.get(1);
+        let since_last: i64 = row.get(3);
+        sources.push(BlockedSource {
+            kind: "ip".to_string(),
+            value: row.get(0),
+            failures,
+            last_attempt: row.get(2),
+            remaining_secs: (lockout_secs(failures, IP_THRESHOLD) - since_last).max(0),
+        });
+    }
+
+    Ok(sources)
+}
+
+/// Forget the failed attempts of a source, which lifts its lockout immediately.
+pub async fn clear_failures(db: &Client, kind: &str, value: &str) -> Result<u64, tokio_postgres::Error> {
+    match kind {
+        "username" => {
+            db.execute(
+                "DELETE FROM login_attempts WHERE username = $1 AND NOT success",
+                &[&normalize(value)],
+            )
+            .await
+        }
+        "ip" => {
+            db.execute(
+                "DELETE FROM login_attempts WHERE HOST(ip_address) = $1 AND NOT success",
+                &[&value],
+            )
+            .await
+        }
+        _ => Ok(0),
+    }
+}
@@ -1,38 +1,62 @@
 #![warn(clippy::all, clippy::pedantic, clippy::nursery)]
 
+pub mod admin_manager;
+pub mod apkg_export;
+pub mod apkg_import;
+pub mod background_tasks;
 pub mod changelog_manager;
 pub mod cleanser;
 pub mod commit_manager;
+pub mod csrf;
+pub mod data_export;
 pub mod database;
+pub mod deck_manager;
+pub mod discovery_manager;
 pub mod error;
+pub mod feed_manager;
+pub mod find_replace_manager;
 pub mod gdrive_manager;
+pub mod import_manager;
+pub mod invite_manager;
+pub mod keyring;
+pub mod login_guard;
 pub mod maintainer_manager;
 pub mod media_reference_manager;
 pub mod media_tokens;
+pub mod moderation_manager;
 pub mod notification_manager;
 pub mod note_history;
 pub mod note_manager;
 pub mod notetype_manager;
 pub mod optional_tags_manager;
+pub mod permissions;
+pub mod restructure_manager;
 pub mod stats_manager;
 pub mod structs;
 pub mod suggestion_manager;
+pub mod tag_tools_manager;
 pub mod user;
 
 use crate::error::Error;
 use crate::error::NoteNotFoundContext;
-use database::owned_deck_id;
 use database::AppState;
+use permissions::{Capability, DeckAccess};
 use net::SocketAddr;
 use sync::Arc;
 use tokio::signal;
 use tower::ServiceBuilder;
-use user::{Auth, ChangePasswordRequest, Credentials, User, purge_deleted_account_data};
+use user::{
+    Auth, ChangePasswordRequest, ChangeUsernameRequest, Credentials, User,
+    expired_deleted_accounts, purge_deleted_account_data,
+};
 
 use axum_client_ip::{ClientIp, ClientIpSource};
+use axum_extra::extract::cookie::CookieJar;
 use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use clap::ValueEnum as _;
+use website::note_export;
 
 use axum::{
     extract::{Path, Query, State},
@@ -45,7 +69,7 @@ use axum::{
 
 use structs::{
     BasicDeckInfo, DeckHash, DeckId, DeckOverview, FieldId, NoteId, Return, UpdateNotetype,
-    UpdateNotetypeTemplate, UserId,
+    UpdateNotetypeTemplate,
 };
 use structs::{
     SubscriptionPolicyGetResponse, SubscriptionPolicyItem, SubscriptionPolicyPostRequest,
@@ -67,7 +91,8 @@ use std::{
 };
 use serde::{Deserialize, Serialize};
 
-type SharedConn = bb8_postgres::bb8::PooledConnection<'static, bb8_postgres::PostgresConnectionManager<tokio_postgres::NoTls>>;
+/// How often the purge jobs look for expired decks, accounts and data exports
+const PURGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
 
 fn check_login(user: Option<User>) -> Result<User, Error> {
     match user {
@@ -76,6 +101,21 @@ fn check_login(user: Option<User>) -> Result<User, Error> {
     }
 }
 
+fn check_admin(user: Option<User>) -> Result<User, Error> {
+    let user = check_login(user)?;
+    if !user.is_admin {
+        return Err(Error::Unauthorized);
+    }
+    Ok(user)
+}
+
+/// Moderation is a site-wide grant, admins hold it implicitly
+async fn check_moderator(appstate: &Arc<AppState>, user: Option<User>) -> Result<User, Error> {
+    let user = check_login(user)?;
+    permissions::require_site(appstate, &user, Capability::Moderate).await?;
+    Ok(user)
+}
+
 async fn forward_donation() -> impl IntoResponse {
     Redirect::permanent("https://ankiweb.net/shared/review/1957538407")
 }
@@ -90,13 +130,15 @@ async fn post_login(
     Extension(auth): Extension<Arc<Auth>>,
     axum::Form(form): axum::Form<Credentials>,
 ) -> Result<impl IntoResponse, Error> {
-    let res = auth.login(form, ip).await?;
+    let cookies = auth.login(form, ip).await?;
 
     let mut response = axum::response::Redirect::to("/").into_response();
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        header::HeaderValue::from_str(&res).unwrap(),
-    );
+    for cookie in cookies {
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            header::HeaderValue::from_str(&cookie).unwrap(),
+        );
+    }
 
     Ok(response)
 }
@@ -164,13 +206,18 @@ async fn accessibility_page(State(appstate): State<Arc<AppState>>) -> Result<imp
     Ok(Html(rendered_template))
 }
 
-async fn logout(Extension(auth): Extension<Arc<Auth>>) -> Result<impl IntoResponse, Error> {
-    let exp_cookie = auth.logout().await;
+async fn logout(
+    Extension(auth): Extension<Arc<Auth>>,
+    jar: CookieJar,
+) -> Result<impl IntoResponse, Error> {
+    let exp_cookies = auth.logout(&jar).await;
     let mut response = axum::response::Redirect::to("/").into_response();
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        header::HeaderValue::from_str(&exp_cookie).unwrap(),
-    );
+    for cookie in exp_cookies {
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            header::HeaderValue::from_str(&cookie).unwrap(),
+        );
+    }
     // add a Clear-Site-Data header for complete cleanup
     response.headers_mut().insert(
         header::HeaderName::from_static("clear-site-data"),
@@ -180,19 +227,78 @@ async fn logout(Extension(auth): Extension<Arc<Auth>>) -> Result<impl IntoRespon
     Ok(response)
 }
 
+async fn render_profile(
+    appstate: &Arc<AppState>,
+    user: &User,
+    export_token: Option<&str>,
+) -> Result<Html<String>, Error> {
+    let export = data_export::latest_export(appstate, user.id).await?;
+    let mut context = tera::Context::new();
+    context.insert("user", user);
+    context.insert("export", &export);
+    context.insert("export_token", &export_token);
+    let rendered_template = appstate.tera.render("profile.html", &context)?;
+    Ok(Html(rendered_template))
+}
+
 async fn get_profile(
     State(appstate): State<Arc<AppState>>,
     user: Option<User>,
 ) -> Result<impl IntoResponse, Error> {
     let user = check_login(user)?;
-    let mut context = tera::Context::new();
-    context.insert("user", &user);
-    let rendered_template = appstate.tera.render("profile.html", &context)?;
-    Ok(Html(rendered_template))
+    render_profile(&appstate, &user, None).await
+}
+
+async fn post_request_export(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    let (export_id, token) = data_export::request_export(&appstate, &user).await?;
+    let db_state = Arc::clone(&appstate);
+    let export_user = user.clone();
+    appstate.tasks.spawn("data_export", async move {
+        data_export::build_export(&db_state, export_id, &export_user)
+            .await
+            .map_err(|e| e.to_string())
+    });
+
+    // The link is only shown on this response, we only keep the hash of its token
+    render_profile(&appstate, &user, Some(&token)).await
+}
+
+async fn download_export(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    let payload = data_export::get_export(&appstate, user.id, &token).await?;
+    let body = serde_json::to_vec_pretty(&payload)?;
+    let disposition = format!(
+        "attachment; filename=\"ankicollab-{}-data.json\"",
+        user.username
+    );
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static("application/json")),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition)
+                    .unwrap_or_else(|_| HeaderValue::from_static("attachment")),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static("no-store")),
+        ],
+        body,
+    ))
 }
 
 async fn post_change_password(
     Extension(auth): Extension<Arc<Auth>>,
+    jar: CookieJar,
     user: Option<User>,
     axum::Form(form): axum::Form<ChangePasswordRequest>,
 ) -> Result<impl IntoResponse, Error> {
@@ -207,52 +313,54 @@ async fn post_change_password(
     auth.change_password(user.id, &form.current_password, &form.new_password)
         .await?;
 
-    // Log the user out so they need to re-authenticate with new password
-    let exp_cookie = auth.logout().await;
-    let mut response = axum::response::Redirect::to("/login").into_response();
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        header::HeaderValue::from_str(&exp_cookie).unwrap(),
-    );
-    response.headers_mut().insert(
-        header::HeaderName::from_static("clear-site-data"),
-        header::HeaderValue::from_static("\"cookies\""),
-    );
+    // Every other session is revoked now, keep this browser logged in with new tokens
+    let cookies = auth.reissue_session(user.id, &jar).await?;
+    let mut response = axum::response::Redirect::to("/profile").into_response();
+    for cookie in cookies {
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            header::HeaderValue::from_str(&cookie).unwrap(),
+        );
+    }
 
     Ok(response)
 }
 
-async fn delete_account(
-    State(appstate): State<Arc<AppState>>,
+async fn post_change_username(
     Extension(auth): Extension<Arc<Auth>>,
     user: Option<User>,
+    axum::Form(form): axum::Form<ChangeUsernameRequest>,
 ) -> Result<impl IntoResponse, Error> {
     let user = check_login(user)?;
 
-    // Fast soft-delete: invalidates password + sets deleted_at (instant)
-    let username = auth.soft_delete_account(user.id).await?;
+    let new_username = auth
+        .change_username(user.id, &form.current_password, &form.new_username)
+        .await?;
+    tracing::info!(user_id = user.id, old = %user.username, new = %new_username, "Username changed");
 
-    // Spawn the heavy cleanup work in the background so the user isn't waiting
-    let pool = appstate.db_pool.clone();
-    let user_id = user.id;
-    tokio::spawn(async move {
-        match pool.get().await {
-            Ok(conn) => {
-                purge_deleted_account_data(&conn, user_id, &username).await;
-            }
-            Err(e) => {
-                tracing::error!(user_id, error = %e, "Failed to get DB connection for account purge");
-            }
-        }
-    });
+    Ok(Redirect::to("/profile"))
+}
+
+async fn delete_account(
+    Extension(auth): Extension<Arc<Auth>>,
+    jar: CookieJar,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    // Fast soft-delete: invalidates password + sets deleted_at (instant). The data stays
+    // restorable until the account purge job removes it after the restore window.
+    auth.soft_delete_account(user.id).await?;
 
     // Log the user out immediately
-    let exp_cookie = auth.logout().await;
+    let exp_cookies = auth.logout(&jar).await;
     let mut response = axum::response::Redirect::to("/").into_response();
-    response.headers_mut().insert(
-        header::SET_COOKIE,
-        header::HeaderValue::from_str(&exp_cookie).unwrap(),
-    );
+    for cookie in exp_cookies {
+        response.headers_mut().append(
+            header::SET_COOKIE,
+            header::HeaderValue::from_str(&cookie).unwrap(),
+        );
+    }
     response.headers_mut().insert(
         header::HeaderName::from_static("clear-site-data"),
         header::HeaderValue::from_static("\"cookies\""),
@@ -261,444 +369,1750 @@ async fn delete_account(
     Ok(response)
 }
 
-async fn render_optional_tags(
-    appstate: &Arc<AppState>,
-    deck_hash: &String,
-    deck_id: i64,
-    user: User,
-) -> Result<impl IntoResponse, Error> {
-    // Get Tags by deck id
-    let tags = match optional_tags_manager::get_tags(appstate, deck_id).await {
-        Ok(tags) => tags,
-        Err(e) => {
-            tracing::warn!(error = %e, deck_id = deck_id, "Failed to retrieve optional tags");
-            return Ok(Html(
-                "Error retrieving optional tags. Please notify us.".to_string(),
-            ));
+fn spawn_account_purge(appstate: &Arc<AppState>, user_id: i32, username: String) {
+    let pool = appstate.db_pool.clone();
+    appstate.tasks.spawn("account_purge", async move {
+        match pool.get().await {
+            Ok(conn) => purge_deleted_account_data(&conn, user_id, &username)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => {
+                tracing::error!(user_id, error = %e, "Failed to get DB connection for account purge");
+                Err(e.to_string())
+            }
         }
-    };
+    });
+}
 
-    let mut context = tera::Context::new();
-    context.insert("optional_tags", &tags);
-    context.insert("hash", &deck_hash);
-    context.insert("user", &user);
+fn spawn_account_purge_job(appstate: &Arc<AppState>) {
+    let pool = appstate.db_pool.clone();
+    appstate
+        .tasks
+        .spawn_periodic("account_purge", PURGE_INTERVAL, move || {
+            let pool = pool.clone();
+            async move {
+                let conn = pool.get().await.map_err(|e| e.to_string())?;
+                let expired = expired_deleted_accounts(&conn)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                for (user_id, username) in expired {
+                    purge_deleted_account_data(&conn, user_id, &username)
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+                Ok(())
+            }
+        });
+}
 
-    let rendered_template = appstate
-        .tera
-        .render("optional_tags.html", &context)
-        .expect("Failed to render template");
-    Ok(Html(rendered_template))
+fn spawn_export_purge_job(appstate: &Arc<AppState>) {
+    let db_state = Arc::clone(appstate);
+    appstate
+        .tasks
+        .spawn_periodic("export_purge", PURGE_INTERVAL, move || {
+            let db_state = Arc::clone(&db_state);
+            async move {
+                data_export::purge_expired_exports(&db_state)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+        });
 }
 
-async fn post_optional_tags(
+async fn admin_dashboard(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Json(edit_optional_tag): Json<structs::UpdateOptionalTag>,
+    user: Option<User>,
 ) -> Result<impl IntoResponse, Error> {
-    let data = edit_optional_tag;
+    let user = check_admin(user)?;
 
-    let deck_id: i64 = owned_deck_id(&appstate, &data.deck, user.id()).await?;
+    let counts = admin_manager::get_site_counts(&appstate).await?;
+    let audit_log = admin_manager::get_audit_log(&appstate, 50).await?;
+    let tasks = appstate.tasks.snapshot();
 
-    // Add new tag
-    if data.action == 1 {
-        optional_tags_manager::add_tag(&appstate, deck_id, cleanser::clean(&data.taggroup)).await
-    } else {
-        // Delete existing optional_tag
-        optional_tags_manager::remove_tag(&appstate, deck_id, cleanser::clean(&data.taggroup)).await
-    }
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("counts", &counts);
+    context.insert("audit_log", &audit_log);
+    context.insert("tasks", &tasks);
+    let rendered_template = appstate.tera.render("admin.html", &context)?;
+    Ok(Html(rendered_template))
 }
 
-async fn show_optional_tags(
+async fn admin_users(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Path(deck_hash): Path<DeckHash>,
+    user: Option<User>,
+    Query(params): Query<structs::AdminSearchQuery>,
 ) -> Result<impl IntoResponse, Error> {
-    let deck_id: i64 = owned_deck_id(&appstate, &deck_hash, user.id()).await?;
+    let user = check_admin(user)?;
 
-    Ok(render_optional_tags(&appstate, &deck_hash, deck_id, user).await)
+    let query = params.q.unwrap_or_default();
+    let users = if query.trim().is_empty() {
+        vec![]
+    } else {
+        admin_manager::search_users(&appstate, &query).await?
+    };
+
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("query", &query);
+    context.insert("users", &users);
+    let rendered_template = appstate.tera.render("admin_users.html", &context)?;
+    Ok(Html(rendered_template))
 }
 
-async fn render_maintainers(
+async fn render_admin_user(
     appstate: &Arc<AppState>,
-    deck_hash: &String,
-    deck_id: i64,
-    user: User,
-) -> impl IntoResponse {
-    // Get Maintainers by deck id
-    let maintainers = match maintainer_manager::get_maintainers(appstate, deck_id).await {
-        Ok(maintainers) => maintainers,
-        Err(e) => {
-            tracing::warn!(error = %e, deck_id = deck_id, "Failed to get maintainers");
-            return Html("Error getting maintainers.".to_string());
-        }
-    };
+    user: &User,
+    target_id: i32,
+    temporary_password: Option<String>,
+) -> Result<Html<String>, Error> {
+    let target = admin_manager::get_user(appstate, target_id).await?;
+    let login_logs = admin_manager::get_login_logs(appstate, target_id, 100).await?;
+    let decks = admin_manager::get_owned_decks(appstate, target_id).await?;
+    let is_moderator =
+        permissions::has_site_grant(appstate, target_id, Capability::Moderate).await?;
 
     let mut context = tera::Context::new();
-    context.insert("maintainers", &maintainers);
-    context.insert("hash", &deck_hash);
-    context.insert("user", &user);
+    context.insert("user", user);
+    context.insert("target", &target);
+    context.insert("is_moderator", &is_moderator);
+    context.insert("login_logs", &login_logs);
+    context.insert("decks", &decks);
+    context.insert("temporary_password", &temporary_password);
+    let rendered_template = appstate.tera.render("admin_user.html", &context)?;
+    Ok(Html(rendered_template))
+}
 
-    let rendered_template = appstate
-        .tera
-        .render("maintainers.html", &context)
-        .expect("Failed to render template");
-    Html(rendered_template)
+async fn admin_user_detail(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
+    render_admin_user(&appstate, &user, target_id, None).await
 }
 
-async fn post_maintainers(
+async fn admin_delete_user(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Json(edit_maintainer): Json<structs::UpdateMaintainer>,
+    Extension(auth): Extension<Arc<Auth>>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
 ) -> Result<impl IntoResponse, Error> {
-    let data = edit_maintainer;
+    let user = check_admin(user)?;
+    if target_id == user.id {
+        return Err(Error::BadRequest(
+            "Use the profile page to delete your own account".to_string(),
+        ));
+    }
 
-    let deck_id: i64 = owned_deck_id(&appstate, &data.deck, user.id()).await?;
+    let username = auth.soft_delete_account(target_id).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "soft_delete_user",
+        "user",
+        &target_id.to_string(),
+        serde_json::json!({ "username": username }),
+    )
+    .await?;
 
-    // Add new maintainer
-    if data.action == 1 {
-        maintainer_manager::add_maintainer(&appstate, deck_id, data.username).await
-    } else {
-        // Delete existing maintainer
-        maintainer_manager::remove_maintainer(&appstate, deck_id, data.username).await
-    }
+    Ok(Redirect::to(&format!("/admin/users/{target_id}")))
 }
 
-// async fn post_media_manager(
-//     State(appstate): State<Arc<AppState>>,
-//     user: User,
-//     Json(update_media): Json<structs::GDriveInfo>
-// ) -> Result<impl IntoResponse, Error> {
-//     let data = update_media;
+async fn admin_restore_user(
+    State(appstate): State<Arc<AppState>>,
+    Extension(auth): Extension<Arc<Auth>>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
 
-//     let deck_id: i64 = owned_deck_id(&appstate, &data.deck, user.id()).await?;
+    let temporary_password = auth.restore_account(target_id).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "restore_user",
+        "user",
+        &target_id.to_string(),
+        serde_json::Value::Null,
+    )
+    .await?;
 
-//     gdrive_manager::update_media(&appstate, deck_id, data).await
-// }
+    // Rendered directly instead of redirecting so the password is only ever shown once
+    render_admin_user(&appstate, &user, target_id, Some(temporary_password)).await
+}
 
-// async fn media_manager(
-//     State(appstate): State<Arc<AppState>>,
-//     user: User,
-//     Path(deck_hash): Path<String>,
-// ) -> Result<impl IntoResponse, Error> {
-//     let _ = owned_deck_id(&appstate, &deck_hash, user.id()).await?;
-//     let mut context = tera::Context::new();
-//     context.insert("hash", &deck_hash);
-//     context.insert("user", &user);
+async fn admin_purge_user(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
 
-//     let rendered_template = appstate.tera
-//         .render("media_manager.html", &context)
-//         .expect("Failed to render template");
-//     Ok(Html(rendered_template))
-// }
+    let target = admin_manager::get_user(&appstate, target_id).await?;
+    if target.deleted_at.is_none() {
+        return Err(Error::BadRequest(
+            "Only deleted accounts can be purged".to_string(),
+        ));
+    }
 
-async fn show_maintainers(
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "purge_user",
+        "user",
+        &target_id.to_string(),
+        serde_json::json!({ "username": target.username }),
+    )
+    .await?;
+    spawn_account_purge(&appstate, target_id, target.username);
+
+    Ok(Redirect::to("/admin"))
+}
+
+async fn admin_set_moderator(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Path(deck_hash): Path<String>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
+    axum::Form(form): axum::Form<structs::AdminModeratorRequest>,
 ) -> Result<impl IntoResponse, Error> {
-    let deck_id: i64 = owned_deck_id(&appstate, &deck_hash, user.id()).await?;
+    let user = check_admin(user)?;
 
-    Ok(render_maintainers(&appstate, &deck_hash, deck_id, user).await)
+    permissions::set_site_grant(
+        &appstate,
+        target_id,
+        Capability::Moderate,
+        form.moderator,
+        user.id(),
+    )
+    .await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        if form.moderator { "grant_moderator" } else { "revoke_moderator" },
+        "user",
+        &target_id.to_string(),
+        serde_json::Value::Null,
+    )
+    .await?;
+
+    Ok(Redirect::to(&format!("/admin/users/{target_id}")))
 }
 
-async fn edit_notetype(
+async fn admin_decks(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Path(notetype_id): Path<i64>,
+    user: Option<User>,
+    Query(params): Query<structs::AdminSearchQuery>,
 ) -> Result<impl IntoResponse, Error> {
-    let client = database::client(&appstate).await?;
+    let user = check_admin(user)?;
 
-    let owned_info = client
-        .query(
-            "SELECT 1 FROM notetype WHERE (owner = $1 AND id = $3) OR $2 LIMIT 1",
-            &[&user.id(), &user.is_admin, &notetype_id],
-        )
-        .await
-        .expect("Error preparing edit notetype statement");
-    if owned_info.is_empty() {
-        return error_page(&appstate, error::Error::Unauthorized.to_string()).await;
-    }
+    let query = params.q.unwrap_or_default();
+    let decks = if query.trim().is_empty() {
+        vec![]
+    } else {
+        admin_manager::search_decks(&appstate, &query).await?
+    };
 
-    let notetype_info = client
-        .query(
-            "Select name, css from notetype where id = $1",
-            &[&notetype_id],
-        )
-        .await
-        .expect("Error preparing edit notetype statement");
-    let notetype_template_info = client
-        .query(
-            "Select id, qfmt, afmt, name from notetype_template where notetype = $1",
-            &[&notetype_id],
-        )
-        .await
-        .expect("Error preparing edit notetype statement");
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("query", &query);
+    context.insert("decks", &decks);
+    let rendered_template = appstate.tera.render("admin_decks.html", &context)?;
+    Ok(Html(rendered_template))
+}
 
-    let protected_fields = notetype_manager::get_protected_fields(&appstate, notetype_id).await?;
+async fn admin_transfer_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::AdminTransferDeckRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
 
-    let name: String = notetype_info[0].get(0);
-    let styling: String = notetype_info[0].get(1);
+    let (deck_id, old_owner, new_owner) =
+        admin_manager::transfer_deck(&appstate, &form.deck_hash, &form.new_owner).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "transfer_deck",
+        "deck",
+        &form.deck_hash,
+        serde_json::json!({ "deck_id": deck_id, "from": old_owner, "to": new_owner }),
+    )
+    .await?;
 
-    let mut templates: Vec<UpdateNotetypeTemplate> = Vec::new();
-    for row in notetype_template_info {
-        templates.push(UpdateNotetypeTemplate {
-            front: row.get(1),
-            back: row.get(2),
-            template_id: row.get(0),
-            name: row.get(3),
-        });
+    Ok(Redirect::to(&format!("/admin/decks?q={}", form.deck_hash)))
+}
+
+async fn admin_refresh_stats(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
+
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "refresh_stats",
+        "site",
+        "stats",
+        serde_json::Value::Null,
+    )
+    .await?;
+    spawn_stats_update(&appstate);
+
+    Ok(Redirect::to("/admin"))
+}
+
+async fn admin_suspend_user(
+    State(appstate): State<Arc<AppState>>,
+    Extension(auth): Extension<Arc<Auth>>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
+    if target_id == user.id {
+        return Err(Error::BadRequest("You cannot suspend yourself".to_string()));
     }
 
+    auth.suspend_account(target_id).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "suspend_user",
+        "user",
+        &target_id.to_string(),
+        serde_json::Value::Null,
+    )
+    .await?;
+
+    Ok(Redirect::to(&format!("/admin/users/{target_id}")))
+}
+
+async fn admin_unsuspend_user(
+    State(appstate): State<Arc<AppState>>,
+    Extension(auth): Extension<Arc<Auth>>,
+    user: Option<User>,
+    Path(target_id): Path<i32>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
+
+    auth.unsuspend_account(target_id).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "unsuspend_user",
+        "user",
+        &target_id.to_string(),
+        serde_json::Value::Null,
+    )
+    .await?;
+
+    Ok(Redirect::to(&format!("/admin/users/{target_id}")))
+}
+
+async fn admin_unhide_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::AdminDeckRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
+
+    let deck_id = database::client(&appstate)
+        .await?
+        .query_opt("SELECT id FROM decks WHERE human_hash = $1", &[&form.deck_hash])
+        .await?
+        .ok_or(Error::DeckNotFound)?
+        .get::<_, DeckId>(0);
+    moderation_manager::set_deck_hidden(&appstate, deck_id, false).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "unhide_deck",
+        "deck",
+        &form.deck_hash,
+        serde_json::Value::Null,
+    )
+    .await?;
+
+    Ok(Redirect::to(&format!("/admin/decks?q={}", form.deck_hash)))
+}
+
+async fn admin_feature_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::AdminFeatureDeckRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
+
+    let deck_id =
+        discovery_manager::set_featured(&appstate, &user, &form.deck_hash, form.featured).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        if form.featured { "feature_deck" } else { "unfeature_deck" },
+        "deck",
+        &form.deck_hash,
+        serde_json::json!({ "deck_id": deck_id }),
+    )
+    .await?;
+
+    Ok(Redirect::to(&format!("/admin/decks?q={}", form.deck_hash)))
+}
+
+async fn post_report(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::ReportContentRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    moderation_manager::submit_report(
+        &appstate,
+        &user,
+        form.deck_hash.as_deref(),
+        form.note_id,
+        &form.category,
+        &form.comment,
+    )
+    .await?;
+
     let mut context = tera::Context::new();
-    context.insert("name", &name);
-    context.insert("styling", &styling);
-    context.insert("notetype_id", &notetype_id);
     context.insert("user", &user);
-    context.insert("protected_fields", &protected_fields);
-    context.insert("templates", &templates);
+    context.insert("deck_hash", &form.deck_hash);
+    context.insert("note_id", &form.note_id);
+    let rendered_template = appstate.tera.render("report_submitted.html", &context)?;
+    Ok(Html(rendered_template))
+}
 
-    let rendered_template = appstate.tera.render("edit_notetype.html", &context)?;
+async fn admin_reports(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_moderator(&appstate, user).await?;
+
+    let open_reports = moderation_manager::get_reports(&appstate, true, 200).await?;
+    let resolved_reports = moderation_manager::get_reports(&appstate, false, 50).await?;
+
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("open_reports", &open_reports);
+    context.insert("resolved_reports", &resolved_reports);
+    let rendered_template = appstate.tera.render("admin_reports.html", &context)?;
     Ok(Html(rendered_template))
 }
 
-async fn post_edit_notetype(
+async fn admin_resolve_report(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Json(edit_notetype): Json<UpdateNotetype>,
-) -> impl IntoResponse {
-    let data = edit_notetype;
+    Extension(auth): Extension<Arc<Auth>>,
+    user: Option<User>,
+    Path(report_id): Path<i64>,
+    axum::Form(form): axum::Form<structs::ResolveReportRequest>,
+) -> Result<impl IntoResponse, Error> {
+    use moderation_manager::ModerationAction;
 
-    match notetype_manager::update_notetype(&appstate, &user, &data).await {
-        Ok(_res) => "updated".to_owned(),
-        Err(e) => e.to_string(),
+    let user = check_moderator(&appstate, user).await?;
+    let action = ModerationAction::parse(&form.action)
+        .ok_or_else(|| Error::BadRequest("Unknown moderation action".to_string()))?;
+    let report = moderation_manager::get_open_report(&appstate, report_id).await?;
+
+    match action {
+        ModerationAction::Hide => {
+            moderation_manager::set_deck_hidden(&appstate, report.deck_id, true).await?;
+        }
+        ModerationAction::Suspend => {
+            // Suspending accounts stays with the admins, moderators only handle content
+            if !user.is_admin {
+                return Err(Error::Unauthorized);
+            }
+            let owner_id = report.owner_id.ok_or(Error::UserNotFound)?;
+            if owner_id == user.id {
+                return Err(Error::BadRequest("You cannot suspend yourself".to_string()));
+            }
+            auth.suspend_account(owner_id).await?;
+        }
+        ModerationAction::Dismiss => {}
     }
+
+    let notified = moderation_manager::resolve_report(&appstate, &user, &report, action).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "resolve_report",
+        "report",
+        &report_id.to_string(),
+        serde_json::json!({
+            "action": action.status(),
+            "deck": report.deck_hash,
+            "owner_id": report.owner_id,
+            "reporters_notified": notified,
+        }),
+    )
+    .await?;
+
+    Ok(Redirect::to("/admin/reports"))
 }
 
-async fn edit_deck(
+fn spawn_stats_update(appstate: &Arc<AppState>) {
+    let db_state_clone = Arc::clone(appstate);
+    appstate.tasks.spawn("stats_update", async move {
+        stats_manager::update_stats(&db_state_clone)
+            .await
+            .map_err(|e| e.to_string())
+    });
+}
+
+async fn admin_blocked_logins(
     State(appstate): State<Arc<AppState>>,
     user: Option<User>,
-    Path(deck_hash): Path<String>,
 ) -> Result<impl IntoResponse, Error> {
-    let user = check_login(user)?;
+    let user = check_admin(user)?;
+
     let client = database::client(&appstate).await?;
-    let owned_info = client
-        .query(
-            "Select owner, description, private, restrict_subdecks, restrict_notetypes from decks where human_hash = $1",
-            &[&deck_hash],
-        )
-        .await
-        .expect("Error preparing edit deck statement");
-    if owned_info.is_empty() {
-        return Ok(Html("Deck not found.".to_string()));
-    }
-    let owner: i32 = owned_info[0].get(0);
+    let blocked = login_guard::get_blocked_sources(&client).await?;
 
     let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("blocked", &blocked);
+    let rendered_template = appstate.tera.render("admin_blocked_logins.html", &context)?;
+    Ok(Html(rendered_template))
+}
 
-    if owner != user.id() {
-        return error_page(&appstate, error::Error::Unauthorized.to_string()).await;
-    }
+async fn admin_clear_login_block(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::ClearLoginBlockRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_admin(user)?;
 
-    let desc: String = owned_info[0].get(1);
-    let is_private: bool = owned_info[0].get(2);
-    let prevent_subdecks: bool = owned_info[0].get(3);
-    let restrict_notetypes: bool = owned_info[0].get(4);
+    let client = database::client(&appstate).await?;
+    let cleared = login_guard::clear_failures(&client, &form.kind, &form.value).await?;
+    admin_manager::record_audit(
+        &appstate,
+        &user,
+        "clear_login_block",
+        &form.kind,
+        &form.value,
+        serde_json::json!({ "cleared_attempts": cleared }),
+    )
+    .await?;
 
-    let changelogs = changelog_manager::get_changelogs(&appstate, &deck_hash).await?;
+    Ok(Redirect::to("/admin/blocked-logins"))
+}
 
-    // Load existing base subscriptions for this deck (as subscriber)
-    let subs_rows = client
-        .query(
-            "SELECT b.name, b.human_hash FROM deck_subscriptions ds JOIN decks s ON ds.subscriber_deck_id = s.id JOIN decks b ON ds.base_deck_id = b.id WHERE s.human_hash = $1",
-            &[&deck_hash],
-        )
-        .await
-        .unwrap_or_default();
-    let mut base_links: Vec<BasicDeckInfo> = Vec::new();
-    for r in subs_rows {
-        base_links.push(BasicDeckInfo {
-            name: r.get(0),
-            human_hash: r.get(1),
-        });
-    }
+async fn render_optional_tags(
+    appstate: &Arc<AppState>,
+    deck_hash: &String,
+    deck_id: i64,
+    user: User,
+) -> Result<impl IntoResponse, Error> {
+    // Get Tags by deck id
+    let tags = match optional_tags_manager::get_tags(appstate, deck_id).await {
+        Ok(tags) => tags,
+        Err(e) => {
+            tracing::warn!(error = %e, deck_id = deck_id, "Failed to retrieve optional tags");
+            return Ok(Html(
+                "Error retrieving optional tags. Please notify us.".to_string(),
+            ));
+        }
+    };
 
-    context.insert("user", &user);
+    let mut context = tera::Context::new();
+    context.insert("optional_tags", &tags);
     context.insert("hash", &deck_hash);
-    // Escape </ to <\/ to prevent </script> breakout when embedded in a <script> tag
-    context.insert("description", &desc.replace("</", "<\\/"));
-    context.insert("private", &is_private);
-    context.insert("prevent_subdecks", &prevent_subdecks);
-    context.insert("restrict_notetypes", &restrict_notetypes);
-    context.insert("changelogs", &changelogs);
-    context.insert("base_links", &base_links);
+    context.insert("user", &user);
 
     let rendered_template = appstate
         .tera
-        .render("edit_deck.html", &context)
+        .render("optional_tags.html", &context)
         .expect("Failed to render template");
     Ok(Html(rendered_template))
 }
 
-async fn post_edit_deck(
+async fn post_optional_tags(
     State(appstate): State<Arc<AppState>>,
     user: User,
-    Json(edit_deck_data): Json<structs::EditDecksData>,
+    Json(edit_optional_tag): Json<structs::UpdateOptionalTag>,
 ) -> Result<impl IntoResponse, Error> {
-    let client = database::client(&appstate).await?;
-    let data = edit_deck_data;
+    let data = edit_optional_tag;
 
-    owned_deck_id(&appstate, &data.hash, user.id()).await?; // only for checking if user owns the deck
+    let deck_id: i64 =
+        permissions::deck_id_with(&appstate, &data.deck, &user, Capability::EditDeckSettings).await?;
 
-    let cleaned_desc = cleanser::clean(&data.description);
-    client
-        .query(
-            "
-        UPDATE decks 
-        SET description = $1, private = $2, restrict_subdecks = $3, restrict_notetypes = $4
-        WHERE human_hash = $5
-        AND owner = $6",
-            &[
-                &cleaned_desc,
-                &data.is_private,
-                &data.prevent_subdecks,
-                &data.restrict_notetypes,
-                &data.hash,
-                &user.id(),
-            ],
+    // Add new tag
+    if data.action == 1 {
+        optional_tags_manager::add_tag(&appstate, deck_id, cleanser::clean(&data.taggroup)).await
+    } else {
+        // Delete existing optional_tag
+        optional_tags_manager::remove_tag(&appstate, deck_id, cleanser::clean(&data.taggroup)).await
+    }
+}
+
+async fn show_optional_tags(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Path(deck_hash): Path<DeckHash>,
+) -> Result<impl IntoResponse, Error> {
+    let deck_id: i64 =
+        permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::EditDeckSettings).await?;
+
+    Ok(render_optional_tags(&appstate, &deck_hash, deck_id, user).await)
+}
+
+async fn render_maintainers(
+    appstate: &Arc<AppState>,
+    deck_hash: &String,
+    deck_id: i64,
+    user: User,
+) -> impl IntoResponse {
+    // Get Maintainers by deck id
+    let maintainers = match maintainer_manager::get_maintainers(appstate, deck_id).await {
+        Ok(maintainers) => maintainers,
+        Err(e) => {
+            tracing::warn!(error = %e, deck_id = deck_id, "Failed to get maintainers");
+            return Html("Error getting maintainers.".to_string());
+        }
+    };
+
+    let mut context = tera::Context::new();
+    let roles: Vec<&str> = permissions::Role::ALL
+        .into_iter()
+        .map(permissions::Role::as_str)
+        .collect();
+
+    context.insert("maintainers", &maintainers);
+    context.insert("roles", &roles);
+    context.insert("hash", &deck_hash);
+    context.insert("user", &user);
+
+    let rendered_template = appstate
+        .tera
+        .render("maintainers.html", &context)
+        .expect("Failed to render template");
+    Html(rendered_template)
+}
+
+async fn post_maintainers(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Json(edit_maintainer): Json<structs::UpdateMaintainer>,
+) -> Result<impl IntoResponse, Error> {
+    let data = edit_maintainer;
+
+    let deck_id: i64 =
+        permissions::deck_id_with(&appstate, &data.deck, &user, Capability::ManageMaintainers).await?;
+
+    let role = data
+        .role
+        .as_deref()
+        .map_or(Some(permissions::Role::Maintainer), permissions::Role::parse)
+        .ok_or_else(|| Error::BadRequest("Unknown role".to_string()))?;
+
+    match data.action {
+        // Add new maintainer
+        1 => maintainer_manager::add_maintainer(&appstate, deck_id, data.username, role).await,
+        2 => maintainer_manager::set_maintainer_role(&appstate, deck_id, data.username, role).await,
+        // Delete existing maintainer
+        _ => maintainer_manager::remove_maintainer(&appstate, deck_id, data.username).await,
+    }
+}
+
+// async fn post_media_manager(
+//     State(appstate): State<Arc<AppState>>,
+//     user: User,
+//     Json(update_media): Json<structs::GDriveInfo>
+// ) -> Result<impl IntoResponse, Error> {
+//     let data = update_media;
+
+//     let deck_id: i64 = owned_deck_id(&appstate, &data.deck, user.id()).await?;
+
+//     gdrive_manager::update_media(&appstate, deck_id, data).await
+// }
+
+// async fn media_manager(
+//     State(appstate): State<Arc<AppState>>,
+//     user: User,
+//     Path(deck_hash): Path<String>,
+// ) -> Result<impl IntoResponse, Error> {
+//     let _ = owned_deck_id(&appstate, &deck_hash, user.id()).await?;
+//     let mut context = tera::Context::new();
+//     context.insert("hash", &deck_hash);
+//     context.insert("user", &user);
+
+//     let rendered_template = appstate.tera
+//         .render("media_manager.html", &context)
+//         .expect("Failed to render template");
+//     Ok(Html(rendered_template))
+// }
+
+async fn show_maintainers(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let deck_id: i64 =
+        permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::ManageMaintainers).await?;
+
+    Ok(render_maintainers(&appstate, &deck_hash, deck_id, user).await)
+}
+
+async fn edit_notetype(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Path(notetype_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    if !permissions::can_edit_notetype(&appstate, &user, notetype_id).await? {
+        return error_page(&appstate, error::Error::Unauthorized.to_string()).await;
+    }
+
+    let client = database::client(&appstate).await?;
+
+    let notetype_info = client
+        .query(
+            "Select name, css from notetype where id = $1",
+            &[&notetype_id],
+        )
+        .await
+        .expect("Error preparing edit notetype statement");
+    let notetype_template_info = client
+        .query(
+            "Select id, qfmt, afmt, name from notetype_template where notetype = $1",
+            &[&notetype_id],
+        )
+        .await
+        .expect("Error preparing edit notetype statement");
+
+    let protected_fields = notetype_manager::get_protected_fields(&appstate, notetype_id).await?;
+
+    let name: String = notetype_info[0].get(0);
+    let styling: String = notetype_info[0].get(1);
+
+    let mut templates: Vec<UpdateNotetypeTemplate> = Vec::new();
+    for row in notetype_template_info {
+        templates.push(UpdateNotetypeTemplate {
+            front: row.get(1),
+            back: row.get(2),
+            template_id: row.get(0),
+            name: row.get(3),
+        });
+    }
+
+    let mut context = tera::Context::new();
+    context.insert("name", &name);
+    context.insert("styling", &styling);
+    context.insert("notetype_id", &notetype_id);
+    context.insert("user", &user);
+    context.insert("protected_fields", &protected_fields);
+    context.insert("templates", &templates);
+
+    let rendered_template = appstate.tera.render("edit_notetype.html", &context)?;
+    Ok(Html(rendered_template))
+}
+
+async fn post_edit_notetype(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Json(edit_notetype): Json<UpdateNotetype>,
+) -> impl IntoResponse {
+    let data = edit_notetype;
+
+    match notetype_manager::update_notetype(&appstate, &user, &data).await {
+        Ok(_res) => "updated".to_owned(),
+        Err(e) => e.to_string(),
+    }
+}
+
+async fn edit_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let client = database::client(&appstate).await?;
+    let owned_info = client
+        .query(
+            "Select id, description, private, restrict_subdecks, restrict_notetypes, owner, moderation_hidden, unlisted, parent is null, language, categories from decks where human_hash = $1 and deleted_at is null",
+            &[&deck_hash],
+        )
+        .await
+        .expect("Error preparing edit deck statement");
+    if owned_info.is_empty() {
+        return Ok(Html("Deck not found.".to_string()));
+    }
+    let deck_id: i64 = owned_info[0].get(0);
+
+    let mut context = tera::Context::new();
+
+    if !permissions::has_capability(&appstate, &user, deck_id, Capability::EditDeckSettings).await? {
+        return error_page(&appstate, error::Error::Unauthorized.to_string()).await;
+    }
+
+    let desc: String = owned_info[0].get(1);
+    let is_private: bool = owned_info[0].get(2);
+    let prevent_subdecks: bool = owned_info[0].get(3);
+    let restrict_notetypes: bool = owned_info[0].get(4);
+    let owner: i32 = owned_info[0].get(5);
+    let moderation_hidden: bool = owned_info[0].get(6);
+    let is_unlisted: bool = owned_info[0].get(7);
+    let is_root: bool = owned_info[0].get(8);
+    let language: Option<String> = owned_info[0].get(9);
+    let deck_categories: Vec<String> = owned_info[0].get(10);
+    let can_manage_maintainers =
+        permissions::has_capability(&appstate, &user, deck_id, Capability::ManageMaintainers).await?;
+
+    let changelogs = changelog_manager::get_changelogs(&appstate, &deck_hash).await?;
+    let subdecks = restructure_manager::get_subdecks(&appstate, deck_id).await?;
+    // Access is granted on the top-level deck, so invites are only managed there
+    let invites = if is_root {
+        invite_manager::list_invites(&appstate, deck_id).await?
+    } else {
+        vec![]
+    };
+
+    // Load existing base subscriptions for this deck (as subscriber)
+    let subs_rows = client
+        .query(
+            "SELECT b.name, b.human_hash FROM deck_subscriptions ds JOIN decks s ON ds.subscriber_deck_id = s.id JOIN decks b ON ds.base_deck_id = b.id WHERE s.human_hash = $1",
+            &[&deck_hash],
+        )
+        .await
+        .unwrap_or_default();
+    let mut base_links: Vec<BasicDeckInfo> = Vec::new();
+    for r in subs_rows {
+        base_links.push(BasicDeckInfo {
+            name: r.get(0),
+            human_hash: r.get(1),
+        });
+    }
+
+    context.insert("user", &user);
+    context.insert("hash", &deck_hash);
+    // Escape </ to <\/ to prevent </script> breakout when embedded in a <script> tag
+    context.insert("description", &desc.replace("</", "<\\/"));
+    context.insert("private", &is_private);
+    context.insert("unlisted", &is_unlisted);
+    context.insert("is_root", &is_root);
+    context.insert("invites", &invites);
+    context.insert("language", &language.unwrap_or_default());
+    context.insert("deck_categories", &deck_categories);
+    context.insert("languages", &discovery_manager::LANGUAGES);
+    context.insert("categories", &discovery_manager::CATEGORIES);
+    context.insert("moderation_hidden", &moderation_hidden);
+    context.insert("prevent_subdecks", &prevent_subdecks);
+    context.insert("restrict_notetypes", &restrict_notetypes);
+    context.insert("changelogs", &changelogs);
+    context.insert("subdecks", &subdecks);
+    context.insert("base_links", &base_links);
+    context.insert("is_owner", &(owner == user.id()));
+    context.insert("can_manage_maintainers", &can_manage_maintainers);
+
+    let rendered_template = appstate
+        .tera
+        .render("edit_deck.html", &context)
+        .expect("Failed to render template");
+    Ok(Html(rendered_template))
+}
+
+async fn post_edit_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Json(edit_deck_data): Json<structs::EditDecksData>,
+) -> Result<impl IntoResponse, Error> {
+    let client = database::client(&appstate).await?;
+    let data = edit_deck_data;
+
+    let deck_id =
+        permissions::deck_id_with(&appstate, &data.hash, &user, Capability::EditDeckSettings).await?;
+
+    let cleaned_desc = cleanser::clean(&data.description);
+    let language = discovery_manager::validate_tags(&data.language, &data.categories)?;
+    client
+        .query(
+            "
+        UPDATE decks 
+        SET description = $1, private = $2 OR moderation_hidden, restrict_subdecks = $3, restrict_notetypes = $4,
+            unlisted = $6 AND NOT $2, language = $7, categories = $8
+        WHERE id = $5",
+            &[
+                &cleaned_desc,
+                &data.is_private,
+                &data.prevent_subdecks,
+                &data.restrict_notetypes,
+                &deck_id,
+                &data.is_unlisted,
+                &language,
+                &data.categories,
+            ],
+        )
+        .await?;
+
+    if !data.changelog.is_empty() {
+        changelog_manager::insert_new_changelog(
+            &appstate,
+            &data.hash,
+            &data.changelog,
+            &data.changelog_commits,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+async fn delete_changelog(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Path(changelog_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    match changelog_manager::delete_changelog(&appstate, changelog_id, &user).await {
+        Ok(hash) => Ok(Redirect::to(format!("/EditDeck/{hash}").as_str())),
+        Err(_err) => Ok(Redirect::to("/")),
+    }
+}
+
+async fn changelog_page(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+    Query(params): Query<structs::ChangelogPageQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let row = database::client(&appstate)
+        .await?
+        .query_opt(
+            "SELECT id, full_path FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(Error::DeckNotFound)?;
+    let deck_id: DeckId = row.get(0);
+    let deck_name: String = row.get(1);
+    if permissions::deck_access(&appstate, user.as_ref(), deck_id).await? == DeckAccess::None {
+        return Err(Error::Unauthorized);
+    }
+    let can_edit = match &user {
+        Some(user) => {
+            permissions::has_capability(&appstate, user, deck_id, Capability::EditDeckSettings)
+                .await?
+        }
+        None => false,
+    };
+
+    let page = params.page.unwrap_or(1).max(1);
+    let (changelogs, total) =
+        changelog_manager::get_changelog_page(&appstate, deck_id, page).await?;
+    let page_count =
+        ((total + changelog_manager::PAGE_SIZE - 1) / changelog_manager::PAGE_SIZE).max(1);
+
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("hash", &deck_hash);
+    context.insert("deck_name", &deck_name);
+    context.insert("changelogs", &changelogs);
+    context.insert("page", &page);
+    context.insert("page_count", &page_count);
+    context.insert("can_edit", &can_edit);
+    let rendered_template = appstate.tera.render("changelog.html", &context)?;
+    Ok(Html(rendered_template))
+}
+
+async fn edit_changelog_page(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(changelog_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    let changelog =
+        changelog_manager::get_editable_changelog(&appstate, &user, changelog_id).await?;
+    let revisions = changelog_manager::get_revisions(&appstate, changelog_id).await?;
+    let commits = changelog
+        .commits
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("changelog", &changelog);
+    context.insert("commits", &commits);
+    context.insert("revisions", &revisions);
+    let rendered_template = appstate.tera.render("edit_changelog.html", &context)?;
+    Ok(Html(rendered_template))
+}
+
+async fn post_edit_changelog(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(changelog_id): Path<i64>,
+    axum::Form(form): axum::Form<structs::EditChangelogForm>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    let commits = changelog_manager::parse_commit_ids(&form.commits)?;
+    let deck_hash = changelog_manager::update_changelog(
+        &appstate,
+        &user,
+        changelog_id,
+        &form.message,
+        &commits,
+    )
+    .await?;
+
+    Ok(Redirect::to(&format!("/changelog/{deck_hash}")))
+}
+
+fn atom_response(feed: &feed_manager::Feed, tokenized: bool) -> Response {
+    let cache = if tokenized {
+        "private, max-age=900"
+    } else {
+        "public, max-age=900"
+    };
+    (
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/atom+xml; charset=utf-8"),
+            ),
+            (header::CACHE_CONTROL, HeaderValue::from_static(cache)),
+        ],
+        feed.to_atom(),
+    )
+        .into_response()
+}
+
+async fn feed_user(
+    appstate: &Arc<AppState>,
+    token: Option<&str>,
+) -> Result<Option<User>, Error> {
+    match token {
+        Some(token) => feed_manager::user_for_token(appstate, token)
+            .await?
+            .map(Some)
+            .ok_or(Error::Unauthorized),
+        None => Ok(None),
+    }
+}
+
+async fn deck_feed(
+    State(appstate): State<Arc<AppState>>,
+    Path(deck_hash): Path<String>,
+    Query(params): Query<structs::FeedQuery>,
+) -> Result<Response, Error> {
+    // Feed readers have no session, private decks are read through the feed token
+    let user = feed_user(&appstate, params.token.as_deref()).await?;
+
+    let row = database::client(&appstate)
+        .await?
+        .query_opt(
+            "SELECT id, full_path FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(Error::DeckNotFound)?;
+    let deck_id: DeckId = row.get(0);
+    let deck_name: String = row.get(1);
+    if permissions::deck_access(&appstate, user.as_ref(), deck_id).await? == DeckAccess::None {
+        return Err(Error::Unauthorized);
+    }
+
+    let site = feed_manager::site_url();
+    let feed = feed_manager::Feed {
+        id: format!("{site}/feeds/deck/{deck_hash}"),
+        title: format!("{deck_name} - AnkiCollab"),
+        link: format!("{site}/notes/{deck_hash}"),
+        self_link: format!("{site}/feeds/deck/{deck_hash}"),
+        entries: feed_manager::deck_entries(&appstate, &[deck_id], false).await?,
+    };
+    Ok(atom_response(&feed, user.is_some()))
+}
+
+async fn maintained_decks_feed(
+    State(appstate): State<Arc<AppState>>,
+    Query(params): Query<structs::FeedQuery>,
+) -> Result<Response, Error> {
+    let user = feed_user(&appstate, params.token.as_deref())
+        .await?
+        .ok_or(Error::Unauthorized)?;
+
+    let decks = feed_manager::maintained_decks(&appstate, user.id()).await?;
+    let site = feed_manager::site_url();
+    let feed = feed_manager::Feed {
+        id: format!("{site}/feeds/maintained/{}", user.id()),
+        title: format!("Decks maintained by {} - AnkiCollab", user.username),
+        link: format!("{site}/ManageDecks"),
+        self_link: format!("{site}/feeds/maintained"),
+        entries: feed_manager::deck_entries(&appstate, &decks, true).await?,
+    };
+    Ok(atom_response(&feed, true))
+}
+
+async fn render_feeds_page(
+    appstate: &Arc<AppState>,
+    user: &User,
+    new_token: Option<&str>,
+) -> Result<Html<String>, Error> {
+    let token_created_at = feed_manager::token_created_at(appstate, user.id()).await?;
+
+    let mut context = tera::Context::new();
+    context.insert("user", user);
+    context.insert("token_created_at", &token_created_at);
+    context.insert("new_token", &new_token);
+    let rendered_template = appstate.tera.render("feeds.html", &context)?;
+    Ok(Html(rendered_template))
+}
+
+async fn feeds_page(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    render_feeds_page(&appstate, &user, None).await
+}
+
+async fn create_feed_token(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    // The token is only shown on this response, we only keep its hash
+    let token = feed_manager::create_token(&appstate, user.id()).await?;
+    render_feeds_page(&appstate, &user, Some(&token)).await
+}
+
+async fn revoke_feed_token(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    feed_manager::revoke_token(&appstate, user.id()).await?;
+    Ok(Redirect::to("/feeds"))
+}
+
+async fn delete_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let deck_id = permissions::owned_deck_id(&appstate, &deck_hash, &user).await?; // deleting stays reserved for the owner
+
+    // Only marks the deck, the purge job removes it once the restore window is over
+    deck_manager::soft_delete(&appstate, deck_id).await?;
+
+    Ok(Redirect::to("/ManageDecks"))
+}
+
+async fn fork_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    let deck_id: DeckId = database::client(&appstate)
+        .await?
+        .query_opt(
+            "SELECT id FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(Error::DeckNotFound)?
+        .get(0);
+    if permissions::deck_access(&appstate, Some(&user), deck_id).await? == DeckAccess::None {
+        return Err(Error::Unauthorized);
+    }
+
+    let new_hash = deck_manager::fork_deck(&appstate, &user, deck_id).await?;
+    Ok(Redirect::to(&format!("/EditDeck/{new_hash}")))
+}
+
+async fn export_apkg(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+) -> Result<Response, Error> {
+    let user = check_login(user)?;
+
+    let row = database::client(&appstate)
+        .await?
+        .query_opt(
+            "SELECT id, name FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(Error::DeckNotFound)?;
+    let deck_id: DeckId = row.get(0);
+    let deck_name: String = row.get(1);
+    // Same as subscribing, people who were only invited to browse can't take the notes along
+    if permissions::deck_access(&appstate, Some(&user), deck_id).await? != DeckAccess::Subscribe {
+        return Err(Error::Unauthorized);
+    }
+
+    let file_name: String = deck_name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, ' ' | '-' | '_') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let disposition =
+        HeaderValue::from_str(&format!("attachment; filename=\"{}.apkg\"", file_name.trim()))
+            .unwrap_or_else(|_| HeaderValue::from_static("attachment; filename=\"deck.apkg\""));
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("application/octet-stream"),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        axum::body::Body::from_stream(apkg_export::stream_package(appstate.clone(), deck_id)),
+    )
+        .into_response())
+}
+
+async fn export_notes(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+    Query(params): Query<structs::NoteExportQuery>,
+) -> Result<Response, Error> {
+    let user = check_login(user)?;
+    let format = note_export::Format::from_str(params.format.as_deref().unwrap_or("csv"), false)
+        .map_err(|_| Error::BadRequest("Unknown export format".to_string()))?;
+    // Pending suggestions are part of the export, so it needs the right to see them
+    permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::Review).await?;
+
+    let options = note_export::ExportOptions {
+        include_pending: params.pending,
+        resolve_inherited: params.inherited,
+    };
+    let client = database::client(&appstate).await?;
+    let rows = note_export::export_notes(&client, &deck_hash, &options)
+        .await?
+        .ok_or(Error::DeckNotFound)?;
+    let body = note_export::render(&rows, format, &options)?;
+
+    let disposition = format!(
+        "attachment; filename=\"notes-{deck_hash}.{}\"",
+        format.extension()
+    );
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(format.content_type()),
+            ),
+            (
+                header::CONTENT_DISPOSITION,
+                HeaderValue::from_str(&disposition)
+                    .unwrap_or_else(|_| HeaderValue::from_static("attachment")),
+            ),
+        ],
+        body,
+    )
+        .into_response())
+}
+
+async fn import_notes_page(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let (root, deck_name) = permissions::suggestion_root(&appstate, &user, &deck_hash).await?;
+    let (decks, notetypes) = deck_manager::tree_options(&appstate, root).await?;
+
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("step", "upload");
+    context.insert("deck_hash", &deck_hash);
+    context.insert("deck_name", &deck_name);
+    context.insert("decks", &decks);
+    context.insert("notetypes", &notetypes);
+    context.insert("max_rows", &commit_manager::MAX_BULK_NOTES);
+    let rendered = appstate.tera.render("import_notes.html", &context)?;
+    Ok(Html(rendered))
+}
+
+async fn post_import_notes(
+    State(appstate): State<Arc<AppState>>,
+    ClientIp(client_ip): ClientIp,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+    axum::Form(form): axum::Form<structs::ImportNotesForm>,
+) -> Result<impl IntoResponse, Error> {
+    use import_manager::ColumnTarget;
+
+    let user = check_login(user)?;
+    let (root, deck_name) = permissions::suggestion_root(&appstate, &user, &deck_hash).await?;
+    let (decks, notetypes) = deck_manager::tree_options(&appstate, root).await?;
+
+    let notetype = form
+        .notetype
+        .parse::<i64>()
+        .ok()
+        .filter(|id| notetypes.iter().any(|n| n.id == *id))
+        .ok_or_else(|| Error::BadRequest("Choose a notetype this deck uses".to_string()))?;
+    let target = decks
+        .iter()
+        .find(|d| d.hash == form.deck)
+        .ok_or_else(|| Error::BadRequest("Choose a deck for the new notes".to_string()))?;
+
+    let delimiter = match form.delimiter.as_str() {
+        "comma" => ',',
+        "tab" => '\t',
+        "semicolon" => ';',
+        _ => import_manager::detect_delimiter(&form.content),
+    };
+    let mut rows = import_manager::parse_delimited(&form.content, delimiter);
+    let header = if form.has_header.is_some() && !rows.is_empty() {
+        Some(rows.remove(0))
+    } else {
+        None
+    };
+    let columns = rows
+        .iter()
+        .chain(header.iter())
+        .map(Vec::len)
+        .max()
+        .unwrap_or(0);
+    if rows.is_empty() || columns == 0 {
+        return Err(Error::BadRequest("The file has no rows to import".to_string()));
+    }
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("deck_hash", &deck_hash);
+    context.insert("deck_name", &deck_name);
+
+    if form.step == "import" {
+        let mapping = (0..columns)
+            .map(|i| {
+                form.columns
+                    .get(&format!("column_{i}"))
+                    .and_then(|value| ColumnTarget::parse(value))
+                    .unwrap_or(ColumnTarget::Ignore)
+            })
+            .collect();
+        let plan = import_manager::ImportPlan {
+            root,
+            target: target.id,
+            notetype,
+            info: form.info,
+            mapping,
+            rows,
+        };
+        let summary =
+            import_manager::import_rows(&appstate, &user, &client_ip.to_string(), &plan).await?;
+        context.insert("step", "done");
+        context.insert("summary", &summary);
+        context.insert(
+            "can_review",
+            &access_check(&appstate, root, &user, Capability::Review).await?,
+        );
+    } else {
+        let fields = import_manager::notetype_fields(&appstate, notetype).await?;
+        let mapping = import_manager::default_mapping(header.as_deref(), &fields, columns);
+        context.insert("step", "map");
+        context.insert(
+            "columns",
+            &import_manager::preview_columns(header.as_deref(), &rows, &mapping),
+        );
+        context.insert("fields", &fields);
+        context.insert("row_count", &rows.len());
+        context.insert("max_rows", &commit_manager::MAX_BULK_NOTES);
+        context.insert("form_content", &form.content);
+        context.insert("form_delimiter", &form.delimiter);
+        context.insert("form_has_header", &form.has_header.is_some());
+        context.insert("form_notetype", &notetype);
+        context.insert(
+            "notetype_name",
+            &notetypes.iter().find(|n| n.id == notetype).map(|n| &n.name),
+        );
+        context.insert("form_deck", &target.hash);
+        context.insert("target_path", &target.path);
+        context.insert("form_info", &form.info);
+    }
+    let rendered = appstate.tera.render("import_notes.html", &context)?;
+    Ok(Html(rendered))
+}
+
+async fn import_deck_page(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("max_notes", &apkg_import::MAX_NOTES);
+    let rendered = appstate.tera.render("import_deck.html", &context)?;
+    Ok(Html(rendered))
+}
+
+async fn post_import_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    mut multipart: axum::extract::Multipart,
+) -> Result<impl IntoResponse, Error> {
+    use tokio::io::AsyncWriteExt;
+
+    let user = check_login(user)?;
+    let upload_error = |_| Error::BadRequest("The upload was interrupted".to_string());
+
+    let package = apkg_export::TempFile(
+        std::env::temp_dir().join(format!("import-{}.apkg", user::random_token())),
+    );
+    let mut has_file = false;
+    let mut deck_name = String::new();
+    let mut private = false;
+    while let Some(mut field) = multipart.next_field().await.map_err(upload_error)? {
+        match field.name() {
+            Some("package") => {
+                if deck_name.is_empty() {
+                    deck_name = field
+                        .file_name()
+                        .and_then(|name| name.strip_suffix(".apkg"))
+                        .unwrap_or_default()
+                        .to_string();
+                }
+                let mut file = tokio::fs::File::create(&package.0)
+                    .await
+                    .map_err(|e| Error::BadRequest(format!("Could not store the upload: {e}")))?;
+                while let Some(chunk) = field.chunk().await.map_err(upload_error)? {
+                    file.write_all(&chunk)
+                        .await
+                        .map_err(|e| Error::BadRequest(format!("Could not store the upload: {e}")))?;
+                }
+                file.flush()
+                    .await
+                    .map_err(|e| Error::BadRequest(format!("Could not store the upload: {e}")))?;
+                has_file = true;
+            }
+            Some("deck_name") => {
+                let name = field.text().await.map_err(upload_error)?;
+                if !name.trim().is_empty() {
+                    deck_name = name;
+                }
+            }
+            Some("private") => private = true,
+            _ => {}
+        }
+    }
+    if !has_file {
+        return Ok(Json(serde_json::json!({
+            "error": "Choose an .apkg file"
+        })));
+    }
+
+    // The page shows problems with the package next to the form
+    match apkg_import::import_package(&appstate, &user, &package.0, &deck_name, private).await {
+        Ok(report) => Ok(Json(serde_json::json!(report))),
+        Err(Error::BadRequest(message)) => Ok(Json(serde_json::json!({
+            "error": message
+        }))),
+        Err(error) => Err(error),
+    }
+}
+
+/// Search form of the find and replace page, filled with the last search
+async fn find_replace_context(
+    appstate: &Arc<AppState>,
+    user: &User,
+    deck_hash: &str,
+    form: Option<&structs::FindReplaceForm>,
+) -> Result<(tera::Context, DeckId, Vec<deck_manager::DeckOption>), Error> {
+    let (root, deck_name) = permissions::suggestion_root(appstate, user, deck_hash).await?;
+    let (decks, notetypes) = deck_manager::tree_options(appstate, root).await?;
+    let field_names = find_replace_manager::field_names(appstate, root).await?;
+
+    let mut context = tera::Context::new();
+    context.insert("user", user);
+    context.insert("deck_hash", deck_hash);
+    context.insert("deck_name", &deck_name);
+    context.insert("decks", &decks);
+    context.insert("notetypes", &notetypes);
+    context.insert("field_names", &field_names);
+    context.insert("max_notes", &commit_manager::MAX_BULK_NOTES);
+    context.insert("form_deck", &form.map_or(deck_hash, |f| f.deck.as_str()));
+    context.insert("form_find", &form.map_or("", |f| f.find.as_str()));
+    context.insert("form_replace", &form.map_or("", |f| f.replace.as_str()));
+    context.insert("form_regex", &form.is_some_and(|f| f.regex.is_some()));
+    context.insert(
+        "form_case_sensitive",
+        &form.is_some_and(|f| f.case_sensitive.is_some()),
+    );
+    context.insert("form_notetype", &form.map_or("", |f| f.notetype.as_str()));
+    context.insert("form_field", &form.map_or("", |f| f.field.as_str()));
+    context.insert("form_tag", &form.map_or("", |f| f.tag.as_str()));
+    context.insert("form_info", &form.map_or("", |f| f.info.as_str()));
+    Ok((context, root, decks))
+}
+
+async fn find_replace_page(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let (mut context, _, _) = find_replace_context(&appstate, &user, &deck_hash, None).await?;
+    context.insert("step", "search");
+    let rendered = appstate.tera.render("find_replace.html", &context)?;
+    Ok(Html(rendered))
+}
+
+async fn post_find_replace(
+    State(appstate): State<Arc<AppState>>,
+    ClientIp(client_ip): ClientIp,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+    axum::Form(form): axum::Form<structs::FindReplaceForm>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let (mut context, root, decks) =
+        find_replace_context(&appstate, &user, &deck_hash, Some(&form)).await?;
+
+    let deck = decks
+        .iter()
+        .find(|d| d.hash == form.deck)
+        .ok_or_else(|| Error::BadRequest("Choose a deck to search in".to_string()))?;
+    let notetype = match form.notetype.trim() {
+        "" => None,
+        id => Some(
+            id.parse::<i64>()
+                .map_err(|_| Error::BadRequest("Choose a notetype this deck uses".to_string()))?,
+        ),
+    };
+    let optional = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+    let search = find_replace_manager::Search {
+        deck: deck.id,
+        find: form.find.clone(),
+        replace: form.replace.clone(),
+        regex: form.regex.is_some(),
+        case_sensitive: form.case_sensitive.is_some(),
+        notetype,
+        field: optional(&form.field),
+        tag: optional(&form.tag),
+    };
+
+    if form.step == "replace" {
+        let summary = find_replace_manager::replace(
+            &appstate,
+            &user,
+            &client_ip.to_string(),
+            root,
+            &search,
+            &form.info,
         )
         .await?;
-
-    if !data.changelog.is_empty() {
-        changelog_manager::insert_new_changelog(&appstate, &data.hash, &data.changelog).await?;
+        context.insert("step", "done");
+        context.insert("summary", &summary);
+        context.insert(
+            "can_review",
+            &access_check(&appstate, root, &user, Capability::Review).await?,
+        );
+    } else {
+        let preview = find_replace_manager::preview(&appstate, &user, root, &search).await?;
+        context.insert("step", "preview");
+        context.insert("preview", &preview);
     }
+    let rendered = appstate.tera.render("find_replace.html", &context)?;
+    Ok(Html(rendered))
+}
 
-    Ok(())
+/// Form of the tag tools page, filled with the last operation
+async fn tag_tools_context(
+    appstate: &Arc<AppState>,
+    user: &User,
+    deck_hash: &str,
+    form: Option<&structs::TagToolsForm>,
+) -> Result<(tera::Context, DeckId, Vec<deck_manager::DeckOption>), Error> {
+    let (root, deck_name) = permissions::suggestion_root(appstate, user, deck_hash).await?;
+    let (decks, notetypes) = deck_manager::tree_options(appstate, root).await?;
+
+    let mut context = tera::Context::new();
+    context.insert("user", user);
+    context.insert("deck_hash", deck_hash);
+    context.insert("deck_name", &deck_name);
+    context.insert("decks", &decks);
+    context.insert("notetypes", &notetypes);
+    context.insert("max_notes", &commit_manager::MAX_BULK_NOTES);
+    context.insert("can_apply", &permissions::owns_deck(appstate, user, root).await?);
+    context.insert("form_operation", &form.map_or("rename", |f| f.operation.as_str()));
+    context.insert("form_deck", &form.map_or(deck_hash, |f| f.deck.as_str()));
+    context.insert("form_from", &form.map_or("", |f| f.from.as_str()));
+    context.insert("form_to", &form.map_or("", |f| f.to.as_str()));
+    context.insert("form_notetype", &form.map_or("", |f| f.notetype.as_str()));
+    context.insert("form_tag", &form.map_or("", |f| f.tag.as_str()));
+    context.insert("form_text", &form.map_or("", |f| f.text.as_str()));
+    context.insert("form_apply", &form.is_some_and(|f| f.apply.is_some()));
+    Ok((context, root, decks))
 }
 
-async fn delete_changelog(
+async fn tag_tools_page(
     State(appstate): State<Arc<AppState>>,
-    user: User,
-    Path(changelog_id): Path<i64>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
 ) -> Result<impl IntoResponse, Error> {
-    match changelog_manager::delete_changelog(&appstate, changelog_id, user.id()).await {
-        Ok(hash) => Ok(Redirect::to(format!("/EditDeck/{hash}").as_str())),
-        Err(_err) => Ok(Redirect::to("/")),
-    }
+    let user = check_login(user)?;
+    let (mut context, _, _) = tag_tools_context(&appstate, &user, &deck_hash, None).await?;
+    context.insert("step", "form");
+    let rendered = appstate.tera.render("tag_tools.html", &context)?;
+    Ok(Html(rendered))
 }
 
-async fn delete_deck(
+async fn post_tag_tools(
     State(appstate): State<Arc<AppState>>,
-    user: User,
+    ClientIp(client_ip): ClientIp,
+    user: Option<User>,
     Path(deck_hash): Path<String>,
+    axum::Form(form): axum::Form<structs::TagToolsForm>,
 ) -> Result<impl IntoResponse, Error> {
-    let db_state_clone = Arc::clone(&appstate);
+    let user = check_login(user)?;
+    let (mut context, root, decks) =
+        tag_tools_context(&appstate, &user, &deck_hash, Some(&form)).await?;
 
-    let client: SharedConn = match db_state_clone.db_pool.get_owned().await {
-            Ok(pool) => pool,
-            Err(err) => {
-                tracing::error!(error = %err, "Failed to get database connection pool");
-                return Err(error::Error::DatabaseConnection);
-            }
-        };
-    let _ = owned_deck_id(&appstate, &deck_hash, user.id()).await?; // only for checking if user owns the deck
+    let deck = decks
+        .iter()
+        .find(|d| d.hash == form.deck)
+        .ok_or_else(|| Error::BadRequest("Choose a deck".to_string()))?;
+    let notetype = match form.notetype.trim() {
+        "" => None,
+        id => Some(
+            id.parse::<i64>()
+                .map_err(|_| Error::BadRequest("Choose a notetype this deck uses".to_string()))?,
+        ),
+    };
+    let optional = |value: &str| Some(value.trim().to_string()).filter(|v| !v.is_empty());
+    let operation = tag_tools_manager::TagOperation::parse(&form.operation, &form.from, &form.to)?;
+    let filter = tag_tools_manager::NoteFilter {
+        deck: deck.id,
+        notetype,
+        tag: optional(&form.tag),
+        text: optional(&form.text),
+    };
 
-    client
-        .query("Select delete_deck($1)", &[&deck_hash])
+    if form.step == "run" {
+        let summary = tag_tools_manager::run(
+            &appstate,
+            &user,
+            &client_ip.to_string(),
+            root,
+            &operation,
+            &filter,
+            form.apply.is_some(),
+        )
         .await?;
-
-
-    // Run on the Tokio runtime
-    tokio::spawn(async move {
-        if let Err(e) = purge_s3_deck_assets(&db_state_clone, &deck_hash).await {
-            tracing::warn!(error = %e, deck_hash = %deck_hash, "Failed to purge S3 assets for deck");
-        }
-
-        let client: SharedConn = match db_state_clone.db_pool.get_owned().await {
-            Ok(pool) => pool,
-            Err(err) => {
-                tracing::error!(error = %err, "Failed to get database pool in background task");
-                return;
-            }
-        };
-        // This query is quite expensive, but it is only used when deleting a deck, so it should be fine. I use it to trigger a cleanup
-        client
-            .query(
-                "DELETE FROM notetype WHERE id NOT IN (SELECT DISTINCT notetype FROM notes)",
-                &[],
-            )
-            .await.unwrap();
-
-        if let Err(err) = purge_s3_deck_assets(&appstate, &deck_hash).await {
-            tracing::warn!(
-                error = %err,
-                deck_hash = %deck_hash,
-                "Failed to delete S3 assets for deck"
-            );
-        }
-    });
-
-    Ok(Redirect::to("/"))
+        context.insert("step", "done");
+        context.insert("summary", &summary);
+        context.insert(
+            "can_review",
+            &access_check(&appstate, root, &user, Capability::Review).await?,
+        );
+    } else {
+        let preview = tag_tools_manager::preview(&appstate, &operation, &filter).await?;
+        context.insert("step", "preview");
+        context.insert("preview", &preview);
+    }
+    let rendered = appstate.tera.render("tag_tools.html", &context)?;
+    Ok(Html(rendered))
 }
 
-// Remove any deck-specific assets stored under the S3 prefix for this deck.
-async fn purge_s3_deck_assets(
-    appstate: &Arc<AppState>,
-    deck_hash: &str,
-) -> Result<(), aws_sdk_s3::Error> {
-    let bucket = match env::var("S3_MEDIA_BUCKET") {
-        Ok(bucket) if !bucket.trim().is_empty() => bucket.trim().to_owned(),
-        _ => return Ok(()),
-    };
-
-    let prefix = format!("decks/{deck_hash}/");
-    let client = &appstate.s3_client;
-    let mut continuation_token: Option<String> = None;
+fn optional_number(value: &str, what: &str) -> Result<Option<i32>, Error> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| Error::BadRequest(format!("{what} must be a whole number")))
+}
 
-    loop {
-        let mut request = client
-            .list_objects_v2()
-            .bucket(&bucket)
-            .prefix(&prefix);
+async fn create_deck_invite(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+    axum::Form(form): axum::Form<structs::CreateInviteRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let deck_id =
+        permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::EditDeckSettings).await?;
 
-        if let Some(ref token) = continuation_token {
-            request = request.continuation_token(token);
-        }
+    let access = DeckAccess::parse(&form.access)
+        .ok_or_else(|| Error::BadRequest("Unknown access level".to_string()))?;
+    let token = invite_manager::create_invite(
+        &appstate,
+        &user,
+        deck_id,
+        access,
+        &form.label,
+        optional_number(&form.expires_in_days, "The expiry")?,
+        optional_number(&form.max_uses, "The usage limit")?,
+    )
+    .await?;
 
-        let response = request.send().await?;
+    let mut context = tera::Context::new();
+    context.insert("user", &user);
+    context.insert("hash", &deck_hash);
+    context.insert("invite_path", &format!("/invite/{token}"));
+    let rendered_template = appstate.tera.render("invite_created.html", &context)?;
+    Ok(Html(rendered_template))
+}
 
-        let keys: Vec<String> = response
-            .contents()
-            .iter()
-            .filter_map(|object| object.key().map(str::to_owned))
-            .collect();
+async fn revoke_deck_invite(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path((deck_hash, invite_id)): Path<(String, i64)>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let deck_id =
+        permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::EditDeckSettings).await?;
+    invite_manager::revoke_invite(&appstate, deck_id, invite_id).await?;
+    Ok(Redirect::to(&format!("/EditDeck/{deck_hash}")))
+}
 
-        for key in keys {
-            client
-                .delete_object()
-                .bucket(&bucket)
-                .key(key)
-                .send()
-                .await?;
-        }
+async fn redeem_deck_invite(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(token): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let Some(user) = user else {
+        return Ok(Redirect::to("/login"));
+    };
+    let deck_hash = invite_manager::redeem_invite(&appstate, &user, &token).await?;
+    Ok(Redirect::to(&format!("/notes/{deck_hash}")))
+}
 
-        if response.is_truncated().unwrap_or(false) {
-            continuation_token = response
-                .next_continuation_token()
-                .map(std::borrow::ToOwned::to_owned);
-        } else {
-            break;
-        }
-    }
+async fn restore_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let deck_id = permissions::owned_deck_id(&appstate, &deck_hash, &user).await?;
+    deck_manager::restore(&appstate, deck_id).await?;
 
-    let marker_key = format!("decks/{deck_hash}");
-    let _ = client
-        .delete_object()
-        .bucket(&bucket)
-        .key(marker_key)
-        .send()
-        .await;
+    Ok(Redirect::to("/ManageDecks"))
+}
 
-    Ok(())
+fn spawn_deck_purge_job(appstate: &Arc<AppState>) {
+    let db_state = Arc::clone(appstate);
+    appstate
+        .tasks
+        .spawn_periodic("deck_purge", PURGE_INTERVAL, move || {
+            let db_state = Arc::clone(&db_state);
+            async move {
+                deck_manager::purge_expired_decks(&db_state)
+                    .await
+                    .map(|_| ())
+                    .map_err(|e| e.to_string())
+            }
+        });
 }
 
 async fn approve_commit(
@@ -968,7 +2382,7 @@ async fn review_commit(
     }
     let deck_id: i64 = q_guid[0].get(0);
 
-    let access = suggestion_manager::is_authorized(&appstate, &user, deck_id).await?;
+    let access = permissions::has_capability(&appstate, &user, deck_id, Capability::Approve).await?;
     let notemodels = notetype_manager::notetypes_by_commit(&appstate, commit_id).await?;
 
     if wants_json {
@@ -1067,7 +2481,13 @@ async fn review_note(
         .map(|h| h.into_response());
     }
     let deck_id: i64 = q_guid[0].get(0);
-    let access = suggestion_manager::is_authorized(&appstate, current_user, deck_id).await?;
+    if permissions::deck_access(&appstate, Some(current_user), deck_id).await? == DeckAccess::None {
+        return error_page(&appstate, error::Error::Unauthorized.to_string())
+            .await
+            .map(|h| h.into_response());
+    }
+    let access =
+        permissions::has_capability(&appstate, current_user, deck_id, Capability::Approve).await?;
 
     context.insert("note", &note);
     context.insert("access", &access);
@@ -1103,9 +2523,6 @@ async fn note_history_page(
         .await
         .map(|h| h.into_response());
     }
-    let deck_id: i64 = row_opt.unwrap().get(0);
-    let u = user.as_ref().unwrap();
-    let _ = suggestion_manager::is_authorized(&appstate, u, deck_id).await?; // we still render even if not owner; access boolean not used here yet
     let history = note_history::fetch_note_history(&client, note_id).await?;
     let mut context = tera::Context::new();
     context.insert("note_id", &note_id);
@@ -1136,17 +2553,18 @@ async fn commit_history_page(
     Ok(Html(rendered_template).into_response())
 }
 
-async fn access_check(appstate: &Arc<AppState>, deck_id: i64, user: &User) -> Result<bool, Error> {
-    let access = match suggestion_manager::is_authorized(appstate, user, deck_id).await {
+async fn access_check(
+    appstate: &Arc<AppState>,
+    deck_id: i64,
+    user: &User,
+    capability: Capability,
+) -> Result<bool, Error> {
+    let access = match permissions::has_capability(appstate, user, deck_id, capability).await {
         Ok(access) => access,
         Err(_error) => return Ok(false),
     };
 
-    if !access {
-        return Ok(false);
-    }
-
-    Ok(true)
+    Ok(access)
 }
 
 async fn get_deck_id(
@@ -1195,7 +2613,7 @@ async fn deny_tag(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Err(error::Error::Unauthorized);
     }
 
@@ -1227,7 +2645,7 @@ async fn deny_note_move(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Err(error::Error::Unauthorized);
     }
 
@@ -1259,7 +2677,7 @@ async fn accept_note_move(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Err(error::Error::Unauthorized);
     }
 
@@ -1291,7 +2709,7 @@ async fn accept_tag(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Err(error::Error::Unauthorized);
     }
 
@@ -1323,7 +2741,7 @@ async fn deny_field(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Err(error::Error::Unauthorized);
     }
 
@@ -1355,7 +2773,7 @@ async fn accept_field(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Err(error::Error::Unauthorized);
     }
 
@@ -1403,7 +2821,7 @@ async fn accept_field(
 //         }
 //     };
 
-//     if !access_check(&appstate, deck_id, &user).await? {
+//     if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
 //         return Ok(String::new());
 //     }
 
@@ -1453,7 +2871,7 @@ async fn get_all_fields_for_edit(
     };
     
     // Check user has access to this deck
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Ok(Json(serde_json::json!({
             "error": "Unauthorized"
         })));
@@ -1475,7 +2893,7 @@ async fn get_all_fields_for_edit(
 
     let commit_deck_id: i64 = commit_deck_row.unwrap().get(0);
 
-    if !access_check(&appstate, commit_deck_id, &user).await? {
+    if !access_check(&appstate, commit_deck_id, &user, Capability::Approve).await? {
         return Ok(Json(serde_json::json!({
             "error": "Unauthorized"
         })));
@@ -1521,7 +2939,7 @@ async fn batch_update_field_suggestions(
     };
     
     // Check user has access to this deck
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Ok(Json(structs::BatchFieldSuggestionResponse {
             success: false,
             updated_count: 0,
@@ -1648,7 +3066,7 @@ async fn add_tag_suggestion(
         }
     };
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::Approve).await? {
         return Ok(Json(structs::AddTagSuggestionResponse {
             success: false,
             tag_id: None,
@@ -1806,10 +3224,7 @@ async fn refresh_stats_cache(
     if secret != *STATS_CACHE_KEY {
         return Ok(Redirect::to("/"));
     }
-    let db_state_clone = Arc::clone(&appstate);
-    tokio::spawn(async move {
-        stats_manager::update_stats(&db_state_clone).await.unwrap();
-    });
+    spawn_stats_update(&appstate);
     Ok(Redirect::to("/"))
 }
 
@@ -1818,24 +3233,11 @@ async fn toggle_stats(
     Path(deck_hash): Path<String>,
     user: User,
 ) -> Result<impl IntoResponse, Error> {
-    let client = database::client(&appstate).await?;
-    let owned_info = client
-        .query(
-            "Select owner from decks where human_hash = $1",
-            &[&deck_hash],
-        )
-        .await
-        .expect("Error preparing edit deck statement");
-    if owned_info.is_empty() {
-        return Ok(Redirect::to("/"));
-    }
-    let owner: i32 = owned_info[0].get(0);
-
-    if owner != user.id() {
+    let Ok(deck_id) =
+        permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::EditDeckSettings).await
+    else {
         return Ok(Redirect::to("/"));
-    }
-
-    let deck_id = owned_deck_id(&appstate, &deck_hash, user.id()).await?;
+    };
 
     stats_manager::toggle_stats(&appstate, deck_id)
         .await
@@ -1860,7 +3262,7 @@ async fn show_statistics(
     }
     let deck_id: i64 = owned_info[0].get(0);
 
-    if !access_check(&appstate, deck_id, &user).await? {
+    if !access_check(&appstate, deck_id, &user, Capability::ViewStats).await? {
         return Ok(Html("Unauthorized.".to_string()));
     }
 
@@ -1928,7 +3330,7 @@ async fn get_notes_from_deck(
     let notes = note_manager::retrieve_notes(&appstate, &deck_hash).await?;
 
     let client = database::client(&appstate).await?;
-    let deck_info = client.query("Select id, name, description, human_hash, owner, TO_CHAR(last_update, 'MM/DD/YYYY') AS last_update from decks where human_hash = $1 Limit 1", &[&deck_hash]).await.expect("Error preparing deck notes statement");
+    let deck_info = client.query("Select id, name, description, human_hash, owner, TO_CHAR(last_update, 'MM/DD/YYYY') AS last_update from decks where human_hash = $1 and deleted_at is null Limit 1", &[&deck_hash]).await.expect("Error preparing deck notes statement");
     if deck_info.is_empty() {
         return error_page(&appstate, error::Error::DeckNotFound.to_string())
             .await
@@ -1937,9 +3339,16 @@ async fn get_notes_from_deck(
 
     let id: i64 = deck_info[0].get(0);
 
+    let access = permissions::deck_access(&appstate, user.as_ref(), id).await?;
+    if access == DeckAccess::None {
+        return error_page(&appstate, error::Error::Unauthorized.to_string())
+            .await
+            .map(|h| h.into_response());
+    }
+
     let children_rows = client
         .query(
-            "Select name, human_hash from decks where parent = $1",
+            "Select name, human_hash from decks where parent = $1 and deleted_at is null",
             &[&id],
         )
         .await
@@ -1965,9 +3374,18 @@ async fn get_notes_from_deck(
         stats_enabled: false, // We don't care about this here
     };
 
+    let (forked_from, forks) = deck_manager::fork_provenance(&appstate, id).await?;
+
     context.insert("notes", &notes);
     context.insert("user", &user);
     context.insert("deck", &deck);
+    context.insert("forked_from", &forked_from);
+    context.insert("forks", &forks);
+    context.insert("can_subscribe", &(access == DeckAccess::Subscribe));
+    context.insert(
+        "public_feed",
+        &(permissions::deck_access(&appstate, None, id).await? != DeckAccess::None),
+    );
 
     let rendered_template = appstate
         .tera
@@ -2007,68 +3425,51 @@ async fn all_reviews(
 async fn deck_overview(
     State(appstate): State<Arc<AppState>>,
     user: Option<User>,
+    Query(params): Query<structs::DiscoverQuery>,
 ) -> Result<impl IntoResponse, Error> {
-    let mut decks: Vec<DeckOverview> = vec![];
-    let user_id: i32 = user.clone().map_or(1, |u| u.id());
-    let client = database::client(&appstate).await?;
-    let stmt = client
-        .prepare(
-            "
-        SELECT 
-            id, 
-            name, 
-            description, 
-            human_hash, 
-            owner,
-            TO_CHAR(last_update, 'MM/DD/YYYY') AS last_update,
-            (SELECT COUNT(*) FROM subscriptions WHERE deck_id = deck_stats.id) AS subs,
-            note_count
-        FROM deck_stats 
-        WHERE private = false OR owner = $1
-        ",
-        )
-        .await
-        .expect("Error preparing decks overview statement");
-
-    let rows = client
-        .query(&stmt, &[&user_id])
-        .await
-        .expect("Error executing decks overview statement");
-
-    for row in rows {
-        decks.push(DeckOverview {
-            owner: row.get(4),
-            id: row.get(0),
-            name: row.get(1),
-            desc: cleanser::clean(row.get(2)),
-            hash: row.get(3),
-            last_update: row.get(5),
-            notes: row.get(7),
-            children: vec![],
-            subscriptions: row.get(6),
-            stats_enabled: false, // We don't care about this here
-        });
-    }
-
-    // decks.sort_by(|a, b| {
-    //     if a.owner == user_id {
-    //         return std::cmp::Ordering::Less;
-    //     } else if b.owner == user_id {
-    //         return std::cmp::Ordering::Greater;
-    //     }
+    let user_id: i32 = user.as_ref().map_or(1, User::id);
+    let non_empty = |value: &Option<String>| {
+        value
+            .as_deref()
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(str::to_owned)
+    };
+    let category = non_empty(&params.category);
+    let language = non_empty(&params.language);
+    let sort = discovery_manager::SortOrder::parse(params.sort.as_deref().unwrap_or_default());
+    let page = params.page.unwrap_or(1).clamp(1, discovery_manager::MAX_PAGE);
+
+    let filter = discovery_manager::DeckFilter {
+        category: category.as_deref(),
+        language: language.as_deref(),
+        sort,
+        page,
+    };
+    let (decks, total) = discovery_manager::list_decks(&appstate, user_id, &filter).await?;
+    let page_count =
+        ((total + discovery_manager::PAGE_SIZE - 1) / discovery_manager::PAGE_SIZE).max(1);
 
-    //     b.notes.cmp(&a.notes)
-    // });
+    // Only show the featured section on the unfiltered landing page
+    let featured = if category.is_none() && language.is_none() && page == 1 {
+        discovery_manager::featured_decks(&appstate).await?
+    } else {
+        vec![]
+    };
 
     let mut context = tera::Context::new();
     context.insert("decks", &decks);
+    context.insert("featured", &featured);
+    context.insert("total", &total);
+    context.insert("page", &page);
+    context.insert("page_count", &page_count);
+    context.insert("category", &category.unwrap_or_default());
+    context.insert("language", &language.unwrap_or_default());
+    context.insert("sort", sort.as_str());
+    context.insert("categories", &discovery_manager::CATEGORIES);
+    context.insert("languages", &discovery_manager::LANGUAGES);
     context.insert("user", &user);
-    let rendered_template = appstate
-        .tera
-        .render("decks.html", &context)
-        .expect("Failed to render template");
-
-    // Return the rendered HTML as the response
+    let rendered_template = appstate.tera.render("decks.html", &context)?;
     Ok(Html(rendered_template))
 }
 
@@ -2084,6 +3485,83 @@ async fn resolve_deck_id_by_hash(appstate: &Arc<AppState>, hash: &str) -> Return
     Ok(rows[0].get(0))
 }
 
+async fn post_restructure_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::RestructureDeckRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let root_hash = apply_restructure(&appstate, &user, &form).await?;
+    Ok(Redirect::to(&format!("/EditDeck/{root_hash}")))
+}
+
+async fn api_post_restructure_deck(
+    State(appstate): State<Arc<AppState>>,
+    user: User,
+    Json(payload): Json<structs::RestructureDeckRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let root_hash = apply_restructure(&appstate, &user, &payload).await?;
+    Ok(Json(serde_json::json!({ "deck_hash": root_hash })))
+}
+
+async fn apply_restructure(
+    appstate: &Arc<AppState>,
+    user: &User,
+    request: &structs::RestructureDeckRequest,
+) -> Return<String> {
+    let action = restructure_manager::RestructureAction::parse(&request.action)
+        .ok_or_else(|| Error::BadRequest("Unknown restructure action".to_string()))?;
+    // Empty form fields arrive as empty strings
+    let target_hash = request.target_hash.as_deref().filter(|h| !h.is_empty());
+    restructure_manager::restructure(
+        appstate,
+        user,
+        action,
+        &request.deck_hash,
+        request.new_name.as_deref(),
+        target_hash,
+    )
+    .await
+}
+
+/// Polled by the add-on so subscribers' deck trees follow renames, moves and merges
+async fn api_get_restructure_log(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+    Query(params): Query<structs::RestructureLogQuery>,
+) -> Result<impl IntoResponse, Error> {
+    let deck_id: DeckId = database::client(&appstate)
+        .await?
+        .query_opt(
+            "SELECT id FROM decks WHERE human_hash = $1 AND parent IS NULL AND deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(Error::DeckNotFound)?
+        .get(0);
+    // Only subscribers rebuild their deck tree from the log
+    if permissions::deck_access(&appstate, user.as_ref(), deck_id).await? != DeckAccess::Subscribe {
+        return Err(Error::Unauthorized);
+    }
+
+    let entries = restructure_manager::get_log(&appstate, deck_id, params.since).await?;
+    Ok(Json(entries))
+}
+
+async fn api_get_changelog_draft(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(deck_hash): Path<String>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let deck_id =
+        permissions::deck_id_with(&appstate, &deck_hash, &user, Capability::EditDeckSettings).await?;
+
+    let draft = changelog_manager::draft_since_last_entry(&appstate, deck_id).await?;
+    Ok(Json(draft))
+}
+
 async fn api_get_subscription_policy(
     State(appstate): State<Arc<AppState>>,
     user: User,
@@ -2099,7 +3577,7 @@ async fn api_get_subscription_policy(
     if sub_id == 0 || base_id == 0 {
         return Ok((axum::http::StatusCode::BAD_REQUEST, "").into_response());
     }
-    if !access_check(&appstate, sub_id, &user).await? {
+    if !access_check(&appstate, sub_id, &user, Capability::EditDeckSettings).await? {
         return Ok((axum::http::StatusCode::FORBIDDEN, "").into_response());
     }
 
@@ -2131,7 +3609,7 @@ async fn api_post_subscription_policy(
     if sub_id == 0 || base_id == 0 {
         return Ok((axum::http::StatusCode::BAD_REQUEST, "").into_response());
     }
-    if !access_check(&appstate, sub_id, &user).await? {
+    if !access_check(&appstate, sub_id, &user, Capability::EditDeckSettings).await? {
         return Ok((axum::http::StatusCode::FORBIDDEN, "").into_response());
     }
 
@@ -2221,9 +3699,9 @@ async fn page_subscription_policy(
     Path((subscriber_hash, base_hash)): Path<(String, String)>,
 ) -> Result<impl IntoResponse, Error> {
     let user = check_login(user)?;
-    // Authorization: must be owner/maintainer of subscriber deck
+    // Authorization: subscriptions are a deck setting of the subscriber deck
     let sub_id = resolve_deck_id_by_hash(&appstate, &subscriber_hash).await?;
-    if sub_id == 0 || !access_check(&appstate, sub_id, &user).await? {
+    if sub_id == 0 || !access_check(&appstate, sub_id, &user, Capability::EditDeckSettings).await? {
         return error_page(&appstate, error::Error::Unauthorized.to_string())
             .await
             .map(IntoResponse::into_response);
@@ -2319,7 +3797,7 @@ async fn manage_decks(
             (SELECT COUNT(*) FROM subscriptions WHERE deck_id = decks.id) AS subs,
             stats_enabled
         FROM decks 
-        WHERE parent IS NULL and owner = $1
+        WHERE parent IS NULL and owner = $1 and deleted_at IS NULL
     ",
         )
         .await
@@ -2358,7 +3836,36 @@ async fn manage_decks(
         }
     };
 
+    let maintained_decks = client
+        .query(
+            "SELECT d.name, d.human_hash, m.role FROM maintainers m JOIN decks d ON d.id = m.deck WHERE m.user_id = $1 AND d.deleted_at IS NULL ORDER BY d.name",
+            &[&user.id()],
+        )
+        .await?
+        .into_iter()
+        .map(|row| {
+            let role: String = row.get(2);
+            let can_edit = permissions::Role::parse(&role)
+                .is_some_and(|r| r.grants(Capability::EditDeckSettings));
+            structs::MaintainedDeckInfo {
+                name: row.get(0),
+                hash: row.get(1),
+                role,
+                can_edit,
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let (incoming_transfers, outgoing_transfers) =
+        deck_manager::pending_transfers(&appstate, user.id()).await?;
+    let deleted_decks = deck_manager::deleted_decks(&appstate, user.id()).await?;
+
     context.insert("decks", &decks);
+    context.insert("maintained_decks", &maintained_decks);
+    context.insert("incoming_transfers", &incoming_transfers);
+    context.insert("outgoing_transfers", &outgoing_transfers);
+    context.insert("deleted_decks", &deleted_decks);
+    context.insert("restore_window_days", &deck_manager::RESTORE_WINDOW_DAYS);
     context.insert("user", &user);
     context.insert("notetypes", &notetypes);
 
@@ -2370,6 +3877,46 @@ async fn manage_decks(
     Ok(Html(rendered_template))
 }
 
+async fn offer_deck_transfer(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    axum::Form(form): axum::Form<structs::DeckTransferRequest>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    deck_manager::offer_transfer(&appstate, &user, &form.deck_hash, &form.recipient).await?;
+    Ok(Redirect::to("/ManageDecks"))
+}
+
+async fn accept_deck_transfer(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(transfer_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    let deck_hash = deck_manager::accept_transfer(&appstate, &user, transfer_id).await?;
+    Ok(Redirect::to(&format!("/EditDeck/{deck_hash}")))
+}
+
+async fn decline_deck_transfer(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(transfer_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    deck_manager::close_transfer(&appstate, &user, transfer_id, true).await?;
+    Ok(Redirect::to("/ManageDecks"))
+}
+
+async fn cancel_deck_transfer(
+    State(appstate): State<Arc<AppState>>,
+    user: Option<User>,
+    Path(transfer_id): Path<i64>,
+) -> Result<impl IntoResponse, Error> {
+    let user = check_login(user)?;
+    deck_manager::close_transfer(&appstate, &user, transfer_id, false).await?;
+    Ok(Redirect::to("/ManageDecks"))
+}
+
 async fn get_presigned_url(
     State(appstate): State<Arc<AppState>>,
     user: User,
@@ -2429,6 +3976,7 @@ async fn main() {
         }
     };
     tera.autoescape_on(vec![".html", ".sql", ".htm", ".xml"]);
+    tera.register_function("csrf_token", csrf::csrf_token_function);
 
     let pool = database::establish_pool_connection()
         .await
@@ -2465,19 +4013,32 @@ async fn main() {
     let s3_client = S3Client::from_conf(s3_service_config);
 
     // Initialize media token service
-    let media_token_secret = std::env::var("MEDIA_TOKEN_SECRET")
-        .expect("MEDIA_TOKEN_SECRET must be set");
+    let media_token_keys = keyring::Keyring::from_env("MEDIA_TOKEN", media_tokens::MIN_SECRET_LEN)
+        .expect("Failed to load media token keys");
+    // The media proxy verifies these tokens as well, only switch to version 2 once it supports kids
+    let media_token_version = env::var("MEDIA_TOKEN_VERSION")
+        .ok()
+        .map(|v| v.parse::<u8>().expect("MEDIA_TOKEN_VERSION must be 1 or 2"))
+        .unwrap_or(media_tokens::LEGACY_TOKEN_VERSION);
     let media_token_service = media_tokens::MediaTokenService::new(
-        media_token_secret.into_bytes(),
+        media_token_keys,
         std::time::Duration::from_secs(5 * 60), // 5 minutes
+        media_token_version,
     )
-    .expect("Failed to initialize media token service");
+    .expect("Failed to initialize media tokens");
+
+    let cookie_secure = env::var("COOKIE_SECURE").unwrap_or("false".to_string()) == "true";
+    let csrf_secret = std::env::var("CSRF_SECRET").expect("CSRF_SECRET must be set");
+    let csrf = csrf::CsrfService::new(csrf_secret.into_bytes(), cookie_secure)
+        .expect("Failed to initialize CSRF protection");
 
     let state = Arc::new(database::AppState {
         db_pool: Arc::new(pool),
         tera: Arc::new(tera),
         s3_client,
         media_token_service,
+        csrf,
+        tasks: background_tasks::TaskRegistry::new(),
     });
 
     // Enable tracing.
@@ -2551,12 +4112,12 @@ async fn main() {
     });
     let db = Arc::new(client);
     // Create Auth instance
-    let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let auth = Arc::new(Auth::new(
-        db.clone(),
-        jwt_secret,
-        env::var("COOKIE_SECURE").unwrap_or("false".to_string()) == "true",
-    ));
+    let jwt_keys = keyring::Keyring::from_env("JWT", 1).expect("Failed to load JWT signing keys");
+    let auth = Arc::new(Auth::new(db.clone(), jwt_keys, cookie_secure));
+
+    spawn_deck_purge_job(&state);
+    spawn_account_purge_job(&state);
+    spawn_export_purge_job(&state);
 
     let app = Router::new()
         .route("/login", get(get_login).post(post_login))
@@ -2570,7 +4131,29 @@ async fn main() {
         .route("/logout", get(logout))
         .route("/profile", get(get_profile))
         .route("/profile/change-password", post(post_change_password))
+        .route("/profile/change-username", post(post_change_username))
         .route("/profile/delete-account", post(delete_account))
+        .route("/profile/export", post(post_request_export))
+        .route("/profile/export/{token}", get(download_export))
+        .route("/admin", get(admin_dashboard))
+        .route("/admin/users", get(admin_users))
+        .route("/admin/users/{user_id}", get(admin_user_detail))
+        .route("/admin/users/{user_id}/delete", post(admin_delete_user))
+        .route("/admin/users/{user_id}/restore", post(admin_restore_user))
+        .route("/admin/users/{user_id}/purge", post(admin_purge_user))
+        .route("/admin/decks", get(admin_decks))
+        .route("/admin/decks/transfer", post(admin_transfer_deck))
+        .route("/admin/decks/unhide", post(admin_unhide_deck))
+        .route("/admin/decks/feature", post(admin_feature_deck))
+        .route("/admin/users/{user_id}/suspend", post(admin_suspend_user))
+        .route("/admin/users/{user_id}/unsuspend", post(admin_unsuspend_user))
+        .route("/admin/users/{user_id}/moderator", post(admin_set_moderator))
+        .route("/admin/reports", get(admin_reports))
+        .route("/admin/reports/{report_id}/resolve", post(admin_resolve_report))
+        .route("/report", post(post_report))
+        .route("/admin/stats/refresh", post(admin_refresh_stats))
+        .route("/admin/blocked-logins", get(admin_blocked_logins))
+        .route("/admin/blocked-logins/clear", post(admin_clear_login_block))
         .route("/OptionalTags", post(post_optional_tags))
         .route("/OptionalTags/{deck_hash}", get(show_optional_tags))
         .route("/Maintainers/{deck_hash}", get(show_maintainers))
@@ -2593,12 +4176,57 @@ async fn main() {
             "/api/subscription-field-policy",
             get(api_get_subscription_policy).post(api_post_subscription_policy),
         )
+        .route("/RestructureDeck", post(post_restructure_deck))
+        .route("/api/deck-restructure", post(api_post_restructure_deck))
+        .route("/api/deck-restructure/{deck_hash}", get(api_get_restructure_log))
+        .route("/api/changelog-draft/{deck_hash}", get(api_get_changelog_draft))
         .route("/DeleteChangelog/{changelog_id}", post(delete_changelog))
+        .route(
+            "/EditChangelog/{changelog_id}",
+            get(edit_changelog_page).post(post_edit_changelog),
+        )
+        .route("/changelog/{deck_hash}", get(changelog_page))
+        .route("/feeds", get(feeds_page))
+        .route("/feeds/token", post(create_feed_token))
+        .route("/feeds/token/revoke", post(revoke_feed_token))
+        .route("/feeds/deck/{deck_hash}", get(deck_feed))
+        .route("/feeds/maintained", get(maintained_decks_feed))
         .route("/DeleteDeck/{deck_hash}", post(delete_deck))
+        .route("/RestoreDeck/{deck_hash}", post(restore_deck))
+        .route("/ForkDeck/{deck_hash}", post(fork_deck))
+        .route("/ExportDeck/{deck_hash}", get(export_apkg))
+        .route("/ExportNotes/{deck_hash}", get(export_notes))
+        .route(
+            "/ImportNotes/{deck_hash}",
+            get(import_notes_page)
+                .post(post_import_notes)
+                .layer(axum::extract::DefaultBodyLimit::max(10 * 1024 * 1024)), // Spreadsheets with long fields
+        )
+        .route(
+            "/FindReplace/{deck_hash}",
+            get(find_replace_page).post(post_find_replace),
+        )
+        .route(
+            "/TagTools/{deck_hash}",
+            get(tag_tools_page).post(post_tag_tools),
+        )
+        .route(
+            "/ImportDeck",
+            get(import_deck_page)
+                .post(post_import_deck)
+                .layer(axum::extract::DefaultBodyLimit::max(512 * 1024 * 1024)), // Packages with media
+        )
+        .route("/DeckInvites/{deck_hash}", post(create_deck_invite))
+        .route("/DeckInvites/{deck_hash}/{invite_id}/revoke", post(revoke_deck_invite))
+        .route("/invite/{token}", get(redeem_deck_invite))
         .route("/leavereview", get(forward_donation))
         .route("/decks", get(deck_overview))
         .route("/notes/{deck_hash}", get(get_notes_from_deck))
         .route("/ManageDecks", get(manage_decks))
+        .route("/TransferDeck", post(offer_deck_transfer))
+        .route("/TransferDeck/{transfer_id}/accept", post(accept_deck_transfer))
+        .route("/TransferDeck/{transfer_id}/decline", post(decline_deck_transfer))
+        .route("/TransferDeck/{transfer_id}/cancel", post(cancel_deck_transfer))
         .route("/review/{note_id}", get(review_note))
         .route("/ToggleStats/{deck_hash}", post(toggle_stats))
         .route("/Statistics/{deck_hash}", get(show_statistics))
@@ -2644,6 +4272,11 @@ async fn main() {
             // requests don't hang forever. Causes issues for streaming large decks that take more than 10secs to generate. hence i disabled it
             //TimeoutLayer::new(Duration::from_secs(10)),
         ))
+        .layer(middleware::from_fn(user::session_refresh_middleware))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            csrf::csrf_middleware,
+        ))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             error::pretty_error_middleware,
@@ -1,43 +1,54 @@
 use std::sync::Arc;
 
 use crate::error::Error::{UserIsAlreadyMaintainer, UserNotFound};
+use crate::permissions::Role;
+use crate::structs::MaintainerInfo;
 use crate::{database, Return};
 
 pub async fn get_maintainers(
     db_state: &Arc<database::AppState>,
     deck: i64,
-) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let query =
-        "SELECT username from users WHERE id IN (SELECT user_id FROM maintainers WHERE deck = $1)";
+) -> Result<Vec<MaintainerInfo>, Box<dyn std::error::Error>> {
+    let query = "SELECT u.username, m.role FROM maintainers m JOIN users u ON u.id = m.user_id WHERE m.deck = $1 ORDER BY u.username";
     let client = database::client(db_state).await?;
     let users = client
         .query(query, &[&deck])
         .await?
         .into_iter()
-        .map(|row| row.get::<_, String>("username"))
-        .collect::<Vec<String>>();
+        .map(|row| MaintainerInfo {
+            username: row.get("username"),
+            role: row.get("role"),
+        })
+        .collect::<Vec<MaintainerInfo>>();
 
     Ok(users)
 }
 
-pub async fn add_maintainer(
-    db_state: &Arc<database::AppState>,
-    deck: i64,
-    username: String,
-) -> Return<String> {
+async fn user_id_by_name(
+    client: &tokio_postgres::Client,
+    username: &str,
+) -> Return<i32> {
     let normalized_username = username.to_lowercase();
-    let client = database::client(db_state).await?;
-    let user = match client
+    match client
         .query_one(
             "SELECT id FROM users WHERE username = $1",
             &[&normalized_username],
         )
         .await
     {
-        Ok(user) => user,
-        Err(_e) => return Err(UserNotFound),
-    };
-    let user_id: i32 = user.get(0);
+        Ok(user) => Ok(user.get(0)),
+        Err(_e) => Err(UserNotFound),
+    }
+}
+
+pub async fn add_maintainer(
+    db_state: &Arc<database::AppState>,
+    deck: i64,
+    username: String,
+    role: Role,
+) -> Return<String> {
+    let client = database::client(db_state).await?;
+    let user_id = user_id_by_name(&client, &username).await?;
 
     match client
         .query_one(
@@ -52,31 +63,41 @@ pub async fn add_maintainer(
 
     client
         .execute(
-            "INSERT INTO maintainers (deck, user_id) VALUES ($1, $2)",
-            &[&deck, &user_id],
+            "INSERT INTO maintainers (deck, user_id, role) VALUES ($1, $2, $3)",
+            &[&deck, &user_id, &role.as_str()],
         )
         .await?;
     Ok("added".to_string())
 }
 
-pub async fn remove_maintainer(
+pub async fn set_maintainer_role(
     db_state: &Arc<database::AppState>,
     deck: i64,
     username: String,
+    role: Role,
 ) -> Return<String> {
-    let normalized_username = username.to_lowercase();
     let client = database::client(db_state).await?;
-    let user = match client
-        .query_one(
-            "SELECT id FROM users WHERE username = $1",
-            &[&normalized_username],
+    let user_id = user_id_by_name(&client, &username).await?;
+
+    let updated = client
+        .execute(
+            "UPDATE maintainers SET role = $3 WHERE deck = $1 AND user_id = $2",
+            &[&deck, &user_id, &role.as_str()],
         )
-        .await
-    {
-        Ok(user) => user,
-        Err(_e) => return Err(UserNotFound),
-    };
-    let user_id: i32 = user.get(0);
+        .await?;
+    if updated == 0 {
+        return Err(UserNotFound);
+    }
+    Ok("updated".to_string())
+}
+
+pub async fn remove_maintainer(
+    db_state: &Arc<database::AppState>,
+    deck: i64,
+    username: String,
+) -> Return<String> {
+    let client = database::client(db_state).await?;
+    let user_id = user_id_by_name(&client, &username).await?;
 
     client
         .execute(
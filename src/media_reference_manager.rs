@@ -279,4 +279,19 @@ pub async fn get_presigned_url(
     let proxy_url = format!("{}/v1/media/{}?token={}", media_proxy_url, hash, token);
 
     Ok(proxy_url)
-}
\ No newline at end of file
+}
+
+/// Bucket the media files live in, `None` when no bucket is configured
+#[must_use]
+pub fn media_bucket() -> Option<String> {
+    std::env::var("S3_MEDIA_BUCKET")
+        .ok()
+        .map(|bucket| bucket.trim().to_owned())
+        .filter(|bucket| !bucket.is_empty())
+}
+
+/// Media files are stored below the top-level deck that uses them, so purging the deck removes them
+#[must_use]
+pub fn media_object_key(deck_hash: &str, hash: &str) -> String {
+    format!("decks/{deck_hash}/{hash}")
+}
@@ -1,5 +1,4 @@
 use std::fmt;
-use std::sync::Arc;
 use std::time::Duration;
 
 use base64::engine::general_purpose::URL_SAFE_NO_PAD;
@@ -9,37 +8,42 @@ use hmac::{Hmac, Mac};
 use serde::{Deserialize, Serialize};
 use sha2::Sha256;
 
+use crate::keyring::Keyring;
+
 type HmacSha256 = Hmac<Sha256>;
 
-const TOKEN_VERSION: u8 = 1;
+/// Version 2 envelopes name the signing key in `kid`. Version 1 tokens have no kid and are
+/// verified with the `default` key.
+pub const TOKEN_VERSION: u8 = 2;
+pub const LEGACY_TOKEN_VERSION: u8 = 1;
+pub const MIN_SECRET_LEN: usize = 32;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct MediaTokenService {
-    secret: Arc<Vec<u8>>,
+    keys: Keyring,
     download_ttl: Duration,
-}
-
-impl std::fmt::Debug for MediaTokenService {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("MediaTokenService")
-            .field("secret", &"<redacted>")
-            .field("download_ttl", &self.download_ttl)
-            .finish()
-    }
+    /// Version of newly issued tokens. The media proxy verifies them too, so version 2 may only
+    /// be issued once the proxy understands it. Both versions are always accepted here.
+    issue_version: u8,
 }
 
 impl MediaTokenService {
+    /// Fails when `issue_version` is unknown, or is 1 without a `default` key to sign with
     pub fn new(
-        secret: Vec<u8>,
+        keys: Keyring,
         download_ttl: Duration,
+        issue_version: u8,
     ) -> Result<Self, MediaTokenError> {
-        if secret.len() < 32 {
-            return Err(MediaTokenError::InvalidSecret);
+        match issue_version {
+            TOKEN_VERSION => {}
+            LEGACY_TOKEN_VERSION if keys.verification_key(None).is_some() => {}
+            LEGACY_TOKEN_VERSION => return Err(MediaTokenError::UnknownKey),
+            version => return Err(MediaTokenError::UnsupportedVersion(version)),
         }
-
         Ok(Self {
-            secret: Arc::new(secret),
+            keys,
             download_ttl,
+            issue_version,
         })
     }
 
@@ -73,8 +77,19 @@ impl MediaTokenService {
     }
 
     fn encode(&self, payload: TokenPayload) -> Result<String, MediaTokenError> {
+        let (key, kid) = if self.issue_version == TOKEN_VERSION {
+            let key = self.keys.active();
+            (key, Some(key.kid.clone()))
+        } else {
+            let key = self
+                .keys
+                .verification_key(None)
+                .ok_or(MediaTokenError::UnknownKey)?;
+            (key, None)
+        };
         let envelope = TokenEnvelope {
-            version: TOKEN_VERSION,
+            version: self.issue_version,
+            kid,
             payload,
         };
 
@@ -82,7 +97,7 @@ impl MediaTokenService {
             serde_json::to_vec(&envelope).map_err(MediaTokenError::Serialization)?;
 
         let mut mac =
-            HmacSha256::new_from_slice(&self.secret).map_err(|_| MediaTokenError::InvalidSecret)?;
+            HmacSha256::new_from_slice(key.secret()).map_err(|_| MediaTokenError::InvalidSecret)?;
         mac.update(&payload_bytes);
         let signature = mac.finalize().into_bytes();
 
@@ -108,19 +123,27 @@ impl MediaTokenService {
             .decode(signature_part)
             .map_err(MediaTokenError::Decode)?;
 
-        let mut mac =
-            HmacSha256::new_from_slice(&self.secret).map_err(|_| MediaTokenError::InvalidSecret)?;
-        mac.update(&payload_bytes);
-        mac.verify_slice(&signature)
-            .map_err(|_| MediaTokenError::InvalidSignature)?;
-
+        // The envelope is parsed before the signature check only to find the key,
+        // nothing in it is trusted until the MAC verified.
         let envelope: TokenEnvelope =
             serde_json::from_slice(&payload_bytes).map_err(MediaTokenError::Serialization)?;
 
-        if envelope.version != TOKEN_VERSION {
-            return Err(MediaTokenError::UnsupportedVersion(envelope.version));
+        match (envelope.version, envelope.kid.as_deref()) {
+            (TOKEN_VERSION, Some(_)) | (LEGACY_TOKEN_VERSION, None) => {}
+            (version, _) => return Err(MediaTokenError::UnsupportedVersion(version)),
         }
 
+        let key = self
+            .keys
+            .verification_key(envelope.kid.as_deref())
+            .ok_or(MediaTokenError::UnknownKey)?;
+
+        let mut mac =
+            HmacSha256::new_from_slice(key.secret()).map_err(|_| MediaTokenError::InvalidSecret)?;
+        mac.update(&payload_bytes);
+        mac.verify_slice(&signature)
+            .map_err(|_| MediaTokenError::InvalidSignature)?;
+
         Ok(envelope)
     }
 
@@ -147,6 +170,7 @@ pub enum MediaTokenError {
     InvalidTtl,
     InvalidFormat,
     InvalidSignature,
+    UnknownKey,
     Expired,
     UnsupportedVersion(u8),
     Decode(base64::DecodeError),
@@ -160,6 +184,7 @@ impl fmt::Display for MediaTokenError {
             MediaTokenError::InvalidTtl => write!(f, "Invalid token TTL"),
             MediaTokenError::InvalidFormat => write!(f, "Invalid token format"),
             MediaTokenError::InvalidSignature => write!(f, "Invalid token signature"),
+            MediaTokenError::UnknownKey => write!(f, "Token signed with an unknown or retired key"),
             MediaTokenError::Expired => write!(f, "Token expired"),
             MediaTokenError::UnsupportedVersion(v) => write!(f, "Unsupported token version: {v}"),
             MediaTokenError::Decode(err) => write!(f, "Token decode error: {err}"),
@@ -196,5 +221,7 @@ enum TokenPayload {
 #[derive(Serialize, Deserialize)]
 struct TokenEnvelope {
     version: u8,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kid: Option<String>,
     payload: TokenPayload,
 }
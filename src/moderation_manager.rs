@@ -0,0 +1,264 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::database;
+use crate::error::Error::{BadRequest, DeckNotFound};
+use crate::notification_manager;
+use crate::user::User;
+use crate::{DeckId, Return};
+
+pub const CATEGORIES: [&str; 4] = ["copyright", "harmful", "spam", "other"];
+const MAX_COMMENT_LEN: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModerationAction {
+    /// Force the deck private, the owner cannot publish it again
+    Hide,
+    /// Suspend the deck owner
+    Suspend,
+    Dismiss,
+}
+
+impl ModerationAction {
+    #[must_use]
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "hide" => Some(Self::Hide),
+            "suspend" => Some(Self::Suspend),
+            "dismiss" => Some(Self::Dismiss),
+            _ => None,
+        }
+    }
+
+    /// Value stored in `content_reports.status`
+    #[must_use]
+    pub const fn status(self) -> &'static str {
+        match self {
+            Self::Hide => "hidden",
+            Self::Suspend => "suspended",
+            Self::Dismiss => "dismissed",
+        }
+    }
+
+    const fn reporter_message(self) -> &'static str {
+        match self {
+            Self::Hide => "Thank you for your report. The deck has been removed from public listings.",
+            Self::Suspend => "Thank you for your report. The account of the deck owner has been suspended.",
+            Self::Dismiss => "Thank you for your report. We reviewed it and decided that no action is necessary.",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ReportRow {
+    pub id: i64,
+    pub deck_id: DeckId,
+    pub deck_name: String,
+    pub deck_hash: String,
+    pub owner_id: Option<i32>,
+    pub owner: Option<String>,
+    pub note_id: Option<i64>,
+    pub category: String,
+    pub comment: String,
+    pub reporter: Option<String>,
+    pub status: String,
+    pub created_at: String,
+    pub moderation_hidden: bool,
+    pub owner_suspended: bool,
+}
+
+const REPORT_COLUMNS: &str = "
+    r.id, r.deck_id, d.name, d.human_hash, d.owner, o.username, r.note_id, r.category,
+    r.comment, rep.username, r.status, TO_CHAR(r.created_at, 'MM/DD/YYYY HH24:MI:SS'),
+    d.moderation_hidden, o.suspended_at IS NOT NULL";
+
+const REPORT_JOINS: &str = "
+    FROM content_reports r
+    JOIN decks d ON d.id = r.deck_id
+    LEFT JOIN users o ON o.id = d.owner
+    LEFT JOIN users rep ON rep.id = r.reporter_id";
+
+fn report_row(row: &tokio_postgres::Row) -> ReportRow {
+    ReportRow {
+        id: row.get(0),
+        deck_id: row.get(1),
+        deck_name: row.get(2),
+        deck_hash: row.get(3),
+        owner_id: row.get(4),
+        owner: row.get(5),
+        note_id: row.get(6),
+        category: row.get(7),
+        comment: row.get(8),
+        reporter: row.get(9),
+        status: row.get(10),
+        created_at: row.get(11),
+        moderation_hidden: row.get(12),
+        owner_suspended: row.get::<_, Option<bool>>(13).unwrap_or(false),
+    }
+}
+
+/// Stores a report for a deck, or for a single note if `note_id` is given
+pub async fn submit_report(
+    db_state: &Arc<database::AppState>,
+    reporter: &User,
+    deck_hash: Option<&str>,
+    note_id: Option<i64>,
+    category: &str,
+    comment: &str,
+) -> Return<()> {
+    if !CATEGORIES.contains(&category) {
+        return Err(BadRequest("Unknown report category".to_string()));
+    }
+    let comment = comment.trim();
+    if comment.chars().count() > MAX_COMMENT_LEN {
+        return Err(BadRequest(format!(
+            "Please keep the comment below {MAX_COMMENT_LEN} characters"
+        )));
+    }
+
+    let client = database::client(db_state).await?;
+
+    // A note report is filed under the deck the note lives in
+    let deck_id: DeckId = match (note_id, deck_hash) {
+        (Some(note_id), _) => client
+            .query_opt("SELECT deck FROM notes WHERE id = $1", &[&note_id])
+            .await?
+            .ok_or(DeckNotFound)?
+            .get(0),
+        (None, Some(hash)) => client
+            .query_opt("SELECT id FROM decks WHERE human_hash = $1", &[&hash])
+            .await?
+            .ok_or(DeckNotFound)?
+            .get(0),
+        (None, None) => return Err(BadRequest("Nothing to report".to_string())),
+    };
+
+    let already_reported: bool = client
+        .query_one(
+            "SELECT EXISTS(SELECT 1 FROM content_reports
+                           WHERE reporter_id = $1 AND deck_id = $2
+                             AND note_id IS NOT DISTINCT FROM $3 AND status = 'open')",
+            &[&reporter.id(), &deck_id, &note_id],
+        )
+        .await?
+        .get(0);
+    if already_reported {
+        return Err(BadRequest(
+            "You already reported this. Our moderators will look at it soon.".to_string(),
+        ));
+    }
+
+    client
+        .execute(
+            "INSERT INTO content_reports (reporter_id, deck_id, note_id, category, comment)
+             VALUES ($1, $2, $3, $4, $5)",
+            &[&reporter.id(), &deck_id, &note_id, &category, &comment],
+        )
+        .await?;
+
+    Ok(())
+}
+
+pub async fn get_reports(
+    db_state: &Arc<database::AppState>,
+    open: bool,
+    limit: i64,
+) -> Return<Vec<ReportRow>> {
+    let client = database::client(db_state).await?;
+    let sql = format!(
+        "SELECT {REPORT_COLUMNS} {REPORT_JOINS}
+         WHERE (r.status = 'open') = $1
+         ORDER BY r.created_at DESC
+         LIMIT $2"
+    );
+    let rows = client.query(&sql, &[&open, &limit]).await?;
+    Ok(rows.iter().map(report_row).collect())
+}
+
+/// Only open reports can be acted on
+pub async fn get_open_report(
+    db_state: &Arc<database::AppState>,
+    report_id: i64,
+) -> Return<ReportRow> {
+    let client = database::client(db_state).await?;
+    let sql = format!("SELECT {REPORT_COLUMNS} {REPORT_JOINS} WHERE r.id = $1 AND r.status = 'open'");
+    let row = client
+        .query_opt(&sql, &[&report_id])
+        .await?
+        .ok_or_else(|| BadRequest("Report not found or already resolved".to_string()))?;
+    Ok(report_row(&row))
+}
+
+/// Listings only show top-level decks, so the whole tree is hidden through its root
+pub async fn set_deck_hidden(
+    db_state: &Arc<database::AppState>,
+    deck_id: DeckId,
+    hidden: bool,
+) -> Return<()> {
+    let client = database::client(db_state).await?;
+    let query = r"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent FROM decks WHERE id = $1
+            UNION ALL
+            SELECT d.id, d.parent FROM decks d JOIN ancestors a ON d.id = a.parent
+        )
+        UPDATE decks
+        SET moderation_hidden = $2, private = private OR $2
+        WHERE id = (SELECT id FROM ancestors WHERE parent IS NULL)
+    ";
+    let updated = client.execute(query, &[&deck_id, &hidden]).await?;
+    if updated == 0 {
+        return Err(DeckNotFound);
+    }
+    Ok(())
+}
+
+/// Closes the report and notifies the reporters. Hiding or suspending also closes all
+/// other open reports about the same deck. Returns how many reporters were notified.
+pub async fn resolve_report(
+    db_state: &Arc<database::AppState>,
+    admin: &User,
+    report: &ReportRow,
+    action: ModerationAction,
+) -> Return<usize> {
+    let client = database::client(db_state).await?;
+    let whole_deck = action != ModerationAction::Dismiss;
+    let rows = client
+        .query(
+            "UPDATE content_reports
+             SET status = $1, resolved_by = $2, resolved_at = NOW()
+             WHERE status = 'open' AND (id = $3 OR ($4 AND deck_id = $5))
+             RETURNING reporter_id",
+            &[
+                &action.status(),
+                &admin.id(),
+                &report.id,
+                &whole_deck,
+                &report.deck_id,
+            ],
+        )
+        .await?;
+    drop(client);
+
+    let reporters: BTreeSet<i32> = rows
+        .iter()
+        .filter_map(|row| row.get::<_, Option<i32>>(0))
+        .collect();
+
+    let status = format!("report_{}", action.status());
+    for reporter_id in &reporters {
+        notification_manager::create_notification(
+            db_state,
+            *reporter_id,
+            None,
+            report.deck_id,
+            &status,
+            Some(action.reporter_message()),
+        )
+        .await?;
+    }
+
+    Ok(reporters.len())
+}
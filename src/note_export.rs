@@ -0,0 +1,400 @@
+//! Export of a deck's notes as CSV or JSON lines for offline analysis and backups.
+//!
+//! Shared by the website and the `note_export` binary, which includes this file by path.
+//! It must only depend on `tokio_postgres` and external crates, not on the rest of the site.
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use clap::ValueEnum;
+use serde::ser::Serializer;
+use serde::Serialize;
+use tokio_postgres::Client;
+
+use crate::csv;
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    Csv,
+    #[value(name = "jsonl", alias = "json")]
+    JsonLines,
+}
+
+impl Format {
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::JsonLines => "jsonl",
+        }
+    }
+
+    #[must_use]
+    pub const fn content_type(self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::JsonLines => "application/x-ndjson; charset=utf-8",
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct ExportOptions {
+    /// Unreviewed notes and the open field and tag suggestions of reviewed ones
+    pub include_pending: bool,
+    /// Show subscribed notes with the content of their base note, like the note page does
+    pub resolve_inherited: bool,
+}
+
+/// Keeps the notetype's field order in the JSON object
+fn serialize_fields<S: Serializer>(
+    fields: &[(String, String)],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(fields.iter().map(|(name, content)| (name, content)))
+}
+
+#[derive(Serialize)]
+pub struct ExportRow {
+    pub note_id: i64,
+    pub guid: String,
+    pub deck: String,
+    pub notetype: String,
+    #[serde(serialize_with = "serialize_fields")]
+    pub fields: Vec<(String, String)>,
+    pub tags: Vec<String>,
+    pub reviewed: bool,
+    pub last_update: String,
+    /// Set for subscribed notes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_note_id: Option<i64>,
+    #[serde(
+        serialize_with = "serialize_fields",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pending_fields: Vec<(String, String)>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pending_tags_added: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pending_tags_removed: Vec<String>,
+}
+
+struct Inheritance {
+    base: i64,
+    /// `None` subscribes to all fields
+    positions: Option<Vec<i32>>,
+    removed_tags: Vec<String>,
+}
+
+/// Notes of the deck with the given hash and its subdecks, ordered by deck path. `None` if
+/// the deck doesn't exist.
+///
+/// # Errors
+/// Fails when a database query fails.
+pub async fn export_notes(
+    client: &Client,
+    deck_hash: &str,
+    options: &ExportOptions,
+) -> Result<Option<Vec<ExportRow>>, tokio_postgres::Error> {
+    let Some(root) = client
+        .query_opt(
+            "SELECT id FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+    else {
+        return Ok(None);
+    };
+    let root: i64 = root.get(0);
+
+    let note_rows = client
+        .query(
+            "
+            WITH RECURSIVE tree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN tree ON d.parent = tree.id WHERE d.deleted_at IS NULL
+            )
+            SELECT n.id, n.guid, d.full_path, n.notetype, nt.name, n.reviewed,
+                   TO_CHAR(n.last_update, 'YYYY-MM-DD HH24:MI:SS')
+            FROM notes n
+            JOIN tree ON tree.id = n.deck
+            JOIN decks d ON d.id = n.deck
+            JOIN notetype nt ON nt.id = n.notetype
+            WHERE NOT n.deleted AND (n.reviewed OR $2)
+            ORDER BY d.full_path, n.id",
+            &[&root, &options.include_pending],
+        )
+        .await?;
+    let note_ids: Vec<i64> = note_rows.iter().map(|row| row.get(0)).collect();
+    let mut notetype_ids: Vec<i64> = note_rows.iter().map(|row| row.get(3)).collect();
+    notetype_ids.sort_unstable();
+    notetype_ids.dedup();
+
+    let mut field_names: HashMap<i64, Vec<(i32, String)>> = HashMap::new();
+    for row in client
+        .query(
+            "SELECT notetype, position::int, name FROM notetype_field
+             WHERE notetype = ANY($1) ORDER BY notetype, position",
+            &[&notetype_ids],
+        )
+        .await?
+    {
+        field_names
+            .entry(row.get(0))
+            .or_default()
+            .push((row.get(1), row.get(2)));
+    }
+
+    // (note, position) -> content. Unreviewed rows of an unreviewed note are its content, of a
+    // reviewed note they are suggestions. The newest suggestion per field wins.
+    let reviewed_notes: HashMap<i64, bool> =
+        note_rows.iter().map(|row| (row.get(0), row.get(5))).collect();
+    let mut content: HashMap<(i64, i32), String> = HashMap::new();
+    let mut pending: HashMap<(i64, i32), String> = HashMap::new();
+    for row in client
+        .query(
+            "SELECT note, position::int, content, reviewed FROM fields
+             WHERE note = ANY($1) AND (reviewed OR $2) ORDER BY id",
+            &[&note_ids, &options.include_pending],
+        )
+        .await?
+    {
+        let note: i64 = row.get(0);
+        let key = (note, row.get(1));
+        let value: String = row.get::<_, Option<String>>(2).unwrap_or_default();
+        let reviewed: bool = row.get(3);
+        if reviewed || !reviewed_notes.get(&note).copied().unwrap_or(true) {
+            content.insert(key, value);
+        } else {
+            pending.insert(key, value);
+        }
+    }
+
+    let mut tags: HashMap<i64, Vec<String>> = HashMap::new();
+    let mut tags_added: HashMap<i64, Vec<String>> = HashMap::new();
+    let mut tags_removed: HashMap<i64, Vec<String>> = HashMap::new();
+    for row in client
+        .query(
+            "SELECT note, content, reviewed, action FROM tags
+             WHERE note = ANY($1) AND content IS NOT NULL AND (reviewed OR $2) ORDER BY id",
+            &[&note_ids, &options.include_pending],
+        )
+        .await?
+    {
+        let note: i64 = row.get(0);
+        let tag: String = row.get(1);
+        let reviewed: bool = row.get(2);
+        let added: bool = row.get(3);
+        let target = if reviewed || !reviewed_notes.get(&note).copied().unwrap_or(true) {
+            if !added {
+                continue;
+            }
+            &mut tags
+        } else if added {
+            &mut tags_added
+        } else {
+            &mut tags_removed
+        };
+        target.entry(note).or_default().push(tag);
+    }
+
+    let mut inheritance: HashMap<i64, Inheritance> = HashMap::new();
+    for row in client
+        .query(
+            "SELECT subscriber_note_id, base_note_id, subscribed_fields,
+                    COALESCE(removed_base_tags, '{}')
+             FROM note_inheritance WHERE subscriber_note_id = ANY($1)",
+            &[&note_ids],
+        )
+        .await?
+    {
+        inheritance.insert(
+            row.get(0),
+            Inheritance {
+                base: row.get(1),
+                positions: row.get(2),
+                removed_tags: row.get(3),
+            },
+        );
+    }
+
+    let mut base_content: HashMap<(i64, i32), String> = HashMap::new();
+    let mut base_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    if options.resolve_inherited && !inheritance.is_empty() {
+        let base_ids: Vec<i64> = inheritance.values().map(|i| i.base).collect();
+        for row in client
+            .query(
+                "SELECT note, position::int, content FROM fields
+                 WHERE note = ANY($1) AND reviewed = true",
+                &[&base_ids],
+            )
+            .await?
+        {
+            base_content.insert(
+                (row.get(0), row.get(1)),
+                row.get::<_, Option<String>>(2).unwrap_or_default(),
+            );
+        }
+        for row in client
+            .query(
+                "SELECT note, content FROM tags
+                 WHERE note = ANY($1) AND reviewed = true AND action = true AND content IS NOT NULL",
+                &[&base_ids],
+            )
+            .await?
+        {
+            base_tags.entry(row.get(0)).or_default().push(row.get(1));
+        }
+    }
+
+    let mut rows = Vec::with_capacity(note_rows.len());
+    for row in note_rows {
+        let note_id: i64 = row.get(0);
+        let notetype: i64 = row.get(3);
+        let inherited = inheritance.get(&note_id);
+        let names = field_names.get(&notetype).map_or(&[][..], Vec::as_slice);
+
+        let mut fields = Vec::with_capacity(names.len());
+        let mut pending_fields = vec![];
+        for (position, name) in names {
+            let mut value = content.remove(&(note_id, *position)).unwrap_or_default();
+            if let Some(inherited) = inherited.filter(|_| options.resolve_inherited) {
+                let subscribed = inherited
+                    .positions
+                    .as_ref()
+                    .is_none_or(|positions| positions.contains(position));
+                if subscribed {
+                    if let Some(base) = base_content.get(&(inherited.base, *position)) {
+                        value.clone_from(base);
+                    }
+                }
+            }
+            fields.push((name.clone(), value));
+            if let Some(suggestion) = pending.remove(&(note_id, *position)) {
+                pending_fields.push((name.clone(), suggestion));
+            }
+        }
+
+        let mut note_tags = tags.remove(&note_id).unwrap_or_default();
+        if let Some(inherited) = inherited.filter(|_| options.resolve_inherited) {
+            for tag in base_tags.get(&inherited.base).into_iter().flatten() {
+                if !inherited.removed_tags.contains(tag) && !note_tags.contains(tag) {
+                    note_tags.push(tag.clone());
+                }
+            }
+        }
+        note_tags.sort();
+        note_tags.dedup();
+
+        rows.push(ExportRow {
+            note_id,
+            guid: row.get(1),
+            deck: row.get(2),
+            notetype: row.get(4),
+            fields,
+            tags: note_tags,
+            reviewed: row.get(5),
+            last_update: row.get::<_, Option<String>>(6).unwrap_or_default(),
+            base_note_id: inherited.map(|i| i.base),
+            pending_fields,
+            pending_tags_added: tags_added.remove(&note_id).unwrap_or_default(),
+            pending_tags_removed: tags_removed.remove(&note_id).unwrap_or_default(),
+        });
+    }
+    Ok(Some(rows))
+}
+
+/// One column per field name in order of first appearance, notes of other notetypes leave it
+/// empty. Pending suggestions get a "(pending)" column next to the field.
+fn render_csv(rows: &[ExportRow], options: &ExportOptions) -> String {
+    let mut names: Vec<&str> = vec![];
+    for row in rows {
+        for (name, _) in &row.fields {
+            if !names.contains(&name.as_str()) {
+                names.push(name);
+            }
+        }
+    }
+
+    let mut header = vec![
+        "note_id".to_string(),
+        "guid".to_string(),
+        "deck".to_string(),
+        "notetype".to_string(),
+        "reviewed".to_string(),
+        "last_update".to_string(),
+        "base_note_id".to_string(),
+        "tags".to_string(),
+    ];
+    if options.include_pending {
+        header.push("pending_tags_added".to_string());
+        header.push("pending_tags_removed".to_string());
+    }
+    for name in &names {
+        header.push((*name).to_string());
+        if options.include_pending {
+            header.push(format!("{name} (pending)"));
+        }
+    }
+    let mut out = header
+        .iter()
+        .map(|h| csv::field(h))
+        .collect::<Vec<_>>()
+        .join(",");
+    out.push('\n');
+
+    for row in rows {
+        let mut values = vec![
+            row.note_id.to_string(),
+            csv::field(&row.guid),
+            csv::field(&row.deck),
+            csv::field(&row.notetype),
+            row.reviewed.to_string(),
+            row.last_update.clone(),
+            row.base_note_id.map(|id| id.to_string()).unwrap_or_default(),
+            csv::field(&row.tags.join(" ")),
+        ];
+        if options.include_pending {
+            values.push(csv::field(&row.pending_tags_added.join(" ")));
+            values.push(csv::field(&row.pending_tags_removed.join(" ")));
+        }
+        let find = |fields: &[(String, String)], name: &str| {
+            fields
+                .iter()
+                .find(|(field, _)| field == name)
+                .map(|(_, content)| csv::field(content))
+                .unwrap_or_default()
+        };
+        for name in &names {
+            values.push(find(&row.fields, name));
+            if options.include_pending {
+                values.push(find(&row.pending_fields, name));
+            }
+        }
+        let _ = writeln!(out, "{}", values.join(","));
+    }
+    out
+}
+
+/// The rows as a CSV file or as JSON lines
+///
+/// # Errors
+/// Fails when a row can't be serialized to JSON.
+pub fn render(
+    rows: &[ExportRow],
+    format: Format,
+    options: &ExportOptions,
+) -> Result<String, serde_json::Error> {
+    match format {
+        Format::Csv => Ok(render_csv(rows, options)),
+        Format::JsonLines => {
+            let mut out = String::new();
+            for row in rows {
+                out.push_str(&serde_json::to_string(row)?);
+                out.push('\n');
+            }
+            Ok(out)
+        }
+    }
+}
@@ -5,6 +5,7 @@ use crate::database;
 use crate::error::Error::{NoteNotFound, Unauthorized};
 use crate::error::NoteNotFoundContext;
 use crate::note_history::{self, EventType};
+use crate::permissions::{self, Capability};
 use crate::structs::{
     FieldSuggestionInfo, FieldsInfo, Note, NoteData, NoteMoveReq, ReviewOverview, TagsInfo,
 };
@@ -513,7 +514,7 @@ pub async fn deny_note_removal_request(
     }
     let deck_id: i64 = q_guid[0].get(0);
 
-    let access = suggestion_manager::is_authorized(db_state, &user, deck_id).await?;
+    let access = permissions::has_capability(db_state, &user, deck_id, Capability::Approve).await?;
     if !access {
         return Err("Unauthorized.".into());
     }
@@ -549,7 +550,7 @@ pub async fn mark_note_deleted(
     let deck_id: i64 = q_guid[0].get(1);
 
     if !bulk {
-        let access = suggestion_manager::is_authorized(db_state, &user, deck_id).await?;
+        let access = permissions::has_capability(db_state, &user, deck_id, Capability::Approve).await?;
         if !access {
             return Err(Unauthorized);
         }
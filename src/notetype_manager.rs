@@ -2,6 +2,7 @@ use crate::user::User;
 
 use crate::database;
 use crate::error::Error::NoNoteTypesAffected;
+use crate::permissions;
 use crate::structs::{NoteModelFieldInfo, NotetypeOverview, UpdateNotetype};
 use crate::Return;
 
@@ -83,17 +84,12 @@ pub async fn update_notetype(
     user: &User,
     notetype: &UpdateNotetype,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = database::client(db_state).await?;
-    let rows = client
-        .query(
-            "SELECT 1 FROM notetype WHERE (owner = $1 AND id = $3) OR $2 LIMIT 1",
-            &[&user.id(), &user.is_admin, &notetype.notetype_id],
-        )
-        .await?;
-    if rows.is_empty() {
+    if !permissions::can_edit_notetype(db_state, user, notetype.notetype_id).await? {
         return Err("Unauthorized".into());
     }
 
+    let mut client = database::client(db_state).await?;
+
     let tx = client.transaction().await?;
 
     // Batch update field protection flags only where changed, returning changed rows.
@@ -51,7 +51,21 @@ pub async fn create_commit_notification(
     if user_id == actor_user_id {
         return Ok(());
     }
+    drop(client);
 
+    create_notification(db_state, user_id, Some(commit_id), deck_id, status, reason).await
+}
+
+/// Notifications that are not about a commit (e.g. the outcome of a content report) pass `None`
+pub async fn create_notification(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+    commit_id: Option<i32>,
+    deck_id: i64,
+    status: &str,
+    reason: Option<&str>,
+) -> Return<()> {
+    let client = database::client(db_state).await?;
     client
         .execute(
             "INSERT INTO notifications (user_id, commit_id, deck_id, status, reason)
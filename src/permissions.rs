@@ -0,0 +1,431 @@
+//! Central permission service.
+//!
+//! Deck owners and site admins implicitly hold every deck capability. Everybody else gets
+//! capabilities through a role in the `maintainers` table. A role granted on a deck applies
+//! to the whole subtree below it, so we walk up the parents when checking. Site-wide
+//! capabilities like moderation are granted per user in `site_grants` instead.
+
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::database;
+use crate::error::Error::{BadRequest, DeckNotFound, Unauthorized};
+use crate::user::User;
+use crate::{DeckHash, DeckId, Return};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// See pending suggestions
+    Review,
+    /// Accept or deny suggestions
+    Approve,
+    /// Description, visibility, changelogs, optional tags, subscriptions
+    EditDeckSettings,
+    EditNotetypes,
+    ManageMaintainers,
+    ViewStats,
+    /// Content reports and hiding reported decks, site-wide only
+    Moderate,
+}
+
+impl Capability {
+    /// Name in `site_grants` for capabilities that are granted for the whole site
+    #[must_use]
+    pub const fn site_grant(self) -> Option<&'static str> {
+        match self {
+            Self::Moderate => Some("moderate"),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    Reviewer,
+    Maintainer,
+    Editor,
+    Manager,
+}
+
+impl Role {
+    pub const ALL: [Self; 4] = [Self::Reviewer, Self::Maintainer, Self::Editor, Self::Manager];
+
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Reviewer => "reviewer",
+            Self::Maintainer => "maintainer",
+            Self::Editor => "editor",
+            Self::Manager => "manager",
+        }
+    }
+
+    #[must_use]
+    pub fn parse(role: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|r| r.as_str() == role)
+    }
+
+    #[must_use]
+    pub const fn capabilities(self) -> &'static [Capability] {
+        use Capability::{
+            Approve, EditDeckSettings, EditNotetypes, ManageMaintainers, Review, ViewStats,
+        };
+        match self {
+            Self::Reviewer => &[Review, ViewStats],
+            Self::Maintainer => &[Review, Approve, ViewStats],
+            Self::Editor => &[Review, Approve, ViewStats, EditDeckSettings, EditNotetypes],
+            Self::Manager => &[
+                Review,
+                Approve,
+                ViewStats,
+                EditDeckSettings,
+                EditNotetypes,
+                ManageMaintainers,
+            ],
+        }
+    }
+
+    #[must_use]
+    pub fn grants(self, capability: Capability) -> bool {
+        self.capabilities().contains(&capability)
+    }
+}
+
+/// Roles that grant `capability`, as stored in `maintainers.role`
+fn roles_granting(capability: Capability) -> Vec<String> {
+    Role::ALL
+        .into_iter()
+        .filter(|r| r.grants(capability))
+        .map(|r| r.as_str().to_owned())
+        .collect()
+}
+
+pub async fn has_capability(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    deck: DeckId,
+    capability: Capability,
+) -> Return<bool> {
+    if user.is_admin {
+        return Ok(true);
+    }
+    // Owning a deck or holding a role on it doesn't make anybody a site moderator
+    if capability.site_grant().is_some() {
+        return has_site_capability(db_state, user, capability).await;
+    }
+
+    let client = database::client(db_state).await?;
+    let query = r"
+        WITH RECURSIVE parent_decks AS (
+            SELECT id, parent, owner
+            FROM decks
+            WHERE id = $1
+            UNION ALL
+            SELECT decks.id, decks.parent, decks.owner
+            FROM decks
+            JOIN parent_decks ON decks.id = parent_decks.parent
+        )
+        SELECT
+            EXISTS (SELECT 1 FROM parent_decks WHERE owner = $2),
+            EXISTS (
+                SELECT 1 FROM maintainers m
+                JOIN parent_decks p ON m.deck = p.id
+                WHERE m.user_id = $2 AND m.role = ANY($3)
+            )
+    ";
+    let row = client
+        .query_one(query, &[&deck, &user.id(), &roles_granting(capability)])
+        .await?;
+    let is_owner: bool = row.get(0);
+    let has_role: bool = row.get(1);
+
+    Ok(is_owner || has_role)
+}
+
+pub async fn require(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    deck: DeckId,
+    capability: Capability,
+) -> Return<()> {
+    if has_capability(db_state, user, deck, capability).await? {
+        Ok(())
+    } else {
+        Err(Unauthorized)
+    }
+}
+
+pub async fn has_site_capability(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    capability: Capability,
+) -> Return<bool> {
+    if user.is_admin {
+        return Ok(true);
+    }
+    has_site_grant(db_state, user.id(), capability).await
+}
+
+pub async fn require_site(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    capability: Capability,
+) -> Return<()> {
+    if has_site_capability(db_state, user, capability).await? {
+        Ok(())
+    } else {
+        Err(Unauthorized)
+    }
+}
+
+/// Only the explicit grant, admins hold every site capability without one
+pub async fn has_site_grant(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+    capability: Capability,
+) -> Return<bool> {
+    let Some(grant) = capability.site_grant() else {
+        return Ok(false);
+    };
+    Ok(database::client(db_state)
+        .await?
+        .query_opt(
+            "SELECT 1 FROM site_grants WHERE user_id = $1 AND capability = $2",
+            &[&user_id, &grant],
+        )
+        .await?
+        .is_some())
+}
+
+pub async fn set_site_grant(
+    db_state: &Arc<database::AppState>,
+    user_id: i32,
+    capability: Capability,
+    granted: bool,
+    granted_by: i32,
+) -> Return<()> {
+    let Some(grant) = capability.site_grant() else {
+        return Err(BadRequest(
+            "This capability is granted per deck".to_string(),
+        ));
+    };
+    let client = database::client(db_state).await?;
+    if granted {
+        client
+            .execute(
+                "INSERT INTO site_grants (user_id, capability, granted_by) VALUES ($1, $2, $3)
+                 ON CONFLICT (user_id, capability) DO NOTHING",
+                &[&user_id, &grant, &granted_by],
+            )
+            .await?;
+    } else {
+        client
+            .execute(
+                "DELETE FROM site_grants WHERE user_id = $1 AND capability = $2",
+                &[&user_id, &grant],
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Resolves the deck hash and checks the capability in one go
+pub async fn deck_id_with(
+    db_state: &Arc<database::AppState>,
+    deck_hash: &DeckHash,
+    user: &User,
+    capability: Capability,
+) -> Return<DeckId> {
+    let client = database::client(db_state).await?;
+    let deck_id: DeckId = client
+        .query_opt(
+            "SELECT id FROM decks WHERE human_hash = $1 AND deleted_at IS NULL",
+            &[deck_hash],
+        )
+        .await?
+        .ok_or(DeckNotFound)?
+        .get(0);
+    drop(client);
+
+    require(db_state, user, deck_id, capability).await?;
+    Ok(deck_id)
+}
+
+/// What a user may do with a deck they do not maintain
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeckAccess {
+    None,
+    /// Browse notes
+    View,
+    /// Browse notes and see the subscription key
+    Subscribe,
+}
+
+impl DeckAccess {
+    #[must_use]
+    pub fn parse(access: &str) -> Option<Self> {
+        match access {
+            "view" => Some(Self::View),
+            "subscribe" => Some(Self::Subscribe),
+            _ => None,
+        }
+    }
+}
+
+/// Public and unlisted decks are open to everybody. Private decks (the flag of the top-level
+/// deck counts) only to people who can review them and to users holding an invite grant.
+pub async fn deck_access(
+    db_state: &Arc<database::AppState>,
+    user: Option<&User>,
+    deck: DeckId,
+) -> Return<DeckAccess> {
+    let client = database::client(db_state).await?;
+    let query = r"
+        WITH RECURSIVE parent_decks AS (
+            SELECT id, parent, private FROM decks WHERE id = $1
+            UNION ALL
+            SELECT decks.id, decks.parent, decks.private
+            FROM decks
+            JOIN parent_decks ON decks.id = parent_decks.parent
+        )
+        SELECT id, private FROM parent_decks WHERE parent IS NULL
+    ";
+    let root = client.query_opt(query, &[&deck]).await?.ok_or(DeckNotFound)?;
+    let root_id: DeckId = root.get(0);
+    let private: bool = root.get(1);
+    if !private {
+        return Ok(DeckAccess::Subscribe);
+    }
+
+    let Some(user) = user else {
+        return Ok(DeckAccess::None);
+    };
+    drop(client);
+    if has_capability(db_state, user, deck, Capability::Review).await? {
+        return Ok(DeckAccess::Subscribe);
+    }
+
+    let grant = database::client(db_state)
+        .await?
+        .query_opt(
+            "SELECT access FROM deck_access_grants WHERE deck_id = $1 AND user_id = $2",
+            &[&root_id, &user.id()],
+        )
+        .await?;
+    Ok(grant
+        .and_then(|row| DeckAccess::parse(row.get(0)))
+        .unwrap_or(DeckAccess::None))
+}
+
+/// Top-level deck (id and name) of the deck a bulk tool was started from. Bulk tools suggest
+/// changes, so they need the same access as subscribing.
+pub async fn suggestion_root(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    deck_hash: &str,
+) -> Return<(DeckId, String)> {
+    let row = database::client(db_state)
+        .await?
+        .query_opt(
+            "WITH RECURSIVE up AS (
+                SELECT id, parent, name FROM decks WHERE human_hash = $1 AND deleted_at IS NULL
+                UNION ALL
+                SELECT d.id, d.parent, d.name FROM decks d JOIN up ON d.id = up.parent
+            )
+            SELECT id, name FROM up WHERE parent IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(DeckNotFound)?;
+    let root: DeckId = row.get(0);
+    if deck_access(db_state, Some(user), root).await? != DeckAccess::Subscribe {
+        return Err(Unauthorized);
+    }
+    Ok((root, row.get(1)))
+}
+
+/// Destructive actions (deleting a deck) stay reserved for the actual owner
+pub async fn owned_deck_id(
+    db_state: &Arc<database::AppState>,
+    deck_hash: &DeckHash,
+    user: &User,
+) -> Return<DeckId> {
+    let owned_info = database::client(db_state)
+        .await?
+        .query(
+            "SELECT id FROM decks WHERE human_hash = $1 AND owner = $2",
+            &[deck_hash, &user.id()],
+        )
+        .await?;
+
+    match owned_info.first() {
+        Some(row) => Ok(row.get(0)),
+        None => Err(Unauthorized),
+    }
+}
+
+/// Owner of the deck itself (or a site admin), maintainer roles don't count
+pub async fn owns_deck(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    deck: DeckId,
+) -> Return<bool> {
+    if user.is_admin {
+        return Ok(true);
+    }
+    Ok(database::client(db_state)
+        .await?
+        .query_opt(
+            "SELECT 1 FROM decks WHERE id = $1 AND owner = $2",
+            &[&deck, &user.id()],
+        )
+        .await?
+        .is_some())
+}
+
+/// Notetypes belong to a user, not a deck, and a change reaches every deck using them. Besides
+/// the notetype owner, only someone holding `EditNotetypes` on all of those decks may edit it.
+pub async fn can_edit_notetype(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    notetype_id: i64,
+) -> Return<bool> {
+    if user.is_admin {
+        return Ok(true);
+    }
+
+    let client = database::client(db_state).await?;
+    let query = r"
+        WITH RECURSIVE granted AS (
+            SELECT id FROM decks WHERE owner = $1
+            UNION
+            SELECT deck FROM maintainers WHERE user_id = $1 AND role = ANY($3)
+            UNION
+            SELECT d.id FROM decks d JOIN granted g ON d.parent = g.id
+        )
+        SELECT
+            EXISTS (SELECT 1 FROM notetype WHERE id = $2 AND owner = $1)
+            OR (
+                EXISTS (SELECT 1 FROM notes WHERE notetype = $2 AND NOT deleted)
+                AND NOT EXISTS (
+                    SELECT 1 FROM notes n
+                    WHERE n.notetype = $2 AND NOT n.deleted
+                      AND n.deck NOT IN (SELECT id FROM granted)
+                )
+            )
+    ";
+    let row = client
+        .query_one(
+            query,
+            &[
+                &user.id(),
+                &notetype_id,
+                &roles_granting(Capability::EditNotetypes),
+            ],
+        )
+        .await?;
+
+    Ok(row.get(0))
+}
@@ -0,0 +1,454 @@
+//! Renaming, moving and merging subdecks from the website.
+//!
+//! Every change recomputes `full_path` for the affected subtree, bumps `last_update` of the
+//! notes below it (and their ancestor decks) so subscribers sync them again, and is written to
+//! `deck_restructure_log` so the add-on can move the cards of subscribers accordingly.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::database;
+use crate::error::Error::{BadRequest, DeckNotFound};
+use crate::permissions::{self, Capability};
+use crate::suggestion_manager::update_notes_timestamps;
+use crate::user::User;
+use crate::{DeckId, Return};
+
+const PATH_SEPARATOR: &str = "::";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestructureAction {
+    Rename,
+    Move,
+    Merge,
+}
+
+impl RestructureAction {
+    #[must_use]
+    pub fn parse(action: &str) -> Option<Self> {
+        match action {
+            "rename" => Some(Self::Rename),
+            "move" => Some(Self::Move),
+            "merge" => Some(Self::Merge),
+            _ => None,
+        }
+    }
+
+    const fn as_str(self) -> &'static str {
+        match self {
+            Self::Rename => "rename",
+            Self::Move => "move",
+            Self::Merge => "merge",
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct SubdeckInfo {
+    pub hash: String,
+    pub name: String,
+    pub full_path: String,
+    pub is_root: bool,
+}
+
+#[derive(Serialize)]
+pub struct RestructureLogEntry {
+    pub id: i64,
+    pub action: String,
+    pub deck_hash: String,
+    pub old_path: String,
+    pub new_path: String,
+    pub merged_into: Option<String>,
+    pub timestamp: String,
+}
+
+struct DeckNode {
+    id: DeckId,
+    parent: Option<DeckId>,
+    root: DeckId,
+    full_path: String,
+}
+
+async fn deck_node(tx: &tokio_postgres::Transaction<'_>, deck_hash: &str) -> Return<DeckNode> {
+    let row = tx
+        .query_opt(
+            r"
+            WITH RECURSIVE ancestors AS (
+                SELECT id, parent FROM decks WHERE human_hash = $1 AND deleted_at IS NULL
+                UNION ALL
+                SELECT d.id, d.parent FROM decks d JOIN ancestors a ON d.id = a.parent
+            )
+            SELECT d.id, d.parent, (SELECT id FROM ancestors WHERE parent IS NULL), d.full_path
+            FROM decks d WHERE d.human_hash = $1 AND d.deleted_at IS NULL",
+            &[&deck_hash],
+        )
+        .await?
+        .ok_or(DeckNotFound)?;
+    Ok(DeckNode {
+        id: row.get(0),
+        parent: row.get(1),
+        root: row.get(2),
+        full_path: row.get(3),
+    })
+}
+
+async fn is_in_subtree(
+    tx: &tokio_postgres::Transaction<'_>,
+    subtree_root: DeckId,
+    deck_id: DeckId,
+) -> Return<bool> {
+    let row = tx
+        .query_one(
+            r"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id
+            )
+            SELECT EXISTS (SELECT 1 FROM subtree WHERE id = $2)",
+            &[&subtree_root, &deck_id],
+        )
+        .await?;
+    Ok(row.get(0))
+}
+
+async fn ensure_free_name(
+    tx: &tokio_postgres::Transaction<'_>,
+    parent: DeckId,
+    name: &str,
+    except: DeckId,
+) -> Return<()> {
+    let taken: bool = tx
+        .query_one(
+            "SELECT EXISTS (SELECT 1 FROM decks
+                            WHERE parent = $1 AND name = $2 AND id <> $3 AND deleted_at IS NULL)",
+            &[&parent, &name, &except],
+        )
+        .await?
+        .get(0);
+    if taken {
+        return Err(BadRequest(format!(
+            "There already is a subdeck called \"{name}\" there. Merge the two decks instead."
+        )));
+    }
+    Ok(())
+}
+
+/// Recomputes `full_path` for `deck_id` and everything below it. Returns the new path of `deck_id`.
+async fn recompute_paths(tx: &tokio_postgres::Transaction<'_>, deck_id: DeckId) -> Return<String> {
+    tx.execute(
+        r"
+        WITH RECURSIVE paths AS (
+            SELECT d.id, CASE WHEN p.id IS NULL THEN d.name ELSE p.full_path || '::' || d.name END AS path
+            FROM decks d LEFT JOIN decks p ON p.id = d.parent
+            WHERE d.id = $1
+            UNION ALL
+            SELECT c.id, paths.path || '::' || c.name
+            FROM decks c JOIN paths ON c.parent = paths.id
+        )
+        UPDATE decks SET full_path = paths.path
+        FROM paths WHERE decks.id = paths.id",
+        &[&deck_id],
+    )
+    .await?;
+    Ok(tx
+        .query_one("SELECT full_path FROM decks WHERE id = $1", &[&deck_id])
+        .await?
+        .get(0))
+}
+
+/// Bumps the notes below `deck_id` and, through them, all ancestor decks
+async fn touch_subtree(tx: &tokio_postgres::Transaction<'_>, deck_id: DeckId) -> Return<()> {
+    let note_ids: Vec<i64> = tx
+        .query(
+            r"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id
+            )
+            SELECT n.id FROM notes n JOIN subtree s ON n.deck = s.id WHERE n.deleted = false",
+            &[&deck_id],
+        )
+        .await?
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    update_notes_timestamps(tx, &note_ids).await?;
+
+    // Empty decks still have to show up as changed
+    tx.execute(
+        r"
+        WITH RECURSIVE ancestors AS (
+            SELECT id, parent FROM decks WHERE id = $1
+            UNION ALL
+            SELECT d.id, d.parent FROM decks d JOIN ancestors a ON d.id = a.parent
+        )
+        UPDATE decks SET last_update = NOW() WHERE id IN (SELECT id FROM ancestors)",
+        &[&deck_id],
+    )
+    .await?;
+    Ok(())
+}
+
+async fn log_change(
+    tx: &tokio_postgres::Transaction<'_>,
+    node: &DeckNode,
+    deck_hash: &str,
+    action: RestructureAction,
+    new_path: &str,
+    merged_into: Option<DeckId>,
+    actor: &User,
+) -> Return<()> {
+    let deck_id = (action != RestructureAction::Merge).then_some(node.id);
+    tx.execute(
+        "INSERT INTO deck_restructure_log
+            (root_deck, deck_id, deck_hash, action, old_path, new_path, merged_into, actor_id)
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+        &[
+            &node.root,
+            &deck_id,
+            &deck_hash,
+            &action.as_str(),
+            &node.full_path,
+            &new_path,
+            &merged_into,
+            &actor.id(),
+        ],
+    )
+    .await?;
+    Ok(())
+}
+
+/// Applies one restructuring step to the subdeck `deck_hash`. `new_name` is used by renames,
+/// `target_hash` is the new parent for moves and the deck to merge into for merges.
+/// Returns the hash of the top-level deck.
+pub async fn restructure(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    action: RestructureAction,
+    deck_hash: &str,
+    new_name: Option<&str>,
+    target_hash: Option<&str>,
+) -> Return<String> {
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+
+    let node = deck_node(&tx, deck_hash).await?;
+    let Some(parent) = node.parent else {
+        return Err(BadRequest(
+            "Top-level decks cannot be restructured here. Use the deck settings to rename them.".to_string(),
+        ));
+    };
+    permissions::require(db_state, user, node.root, Capability::EditDeckSettings).await?;
+
+    let target = match (action, target_hash) {
+        (RestructureAction::Rename, _) => None,
+        (_, Some(hash)) => {
+            let target = deck_node(&tx, hash).await?;
+            if target.root != node.root {
+                return Err(BadRequest(
+                    "Subdecks can only be moved within the same deck".to_string(),
+                ));
+            }
+            if is_in_subtree(&tx, node.id, target.id).await? {
+                return Err(BadRequest(
+                    "A deck cannot be moved into itself or one of its subdecks".to_string(),
+                ));
+            }
+            Some(target)
+        }
+        (_, None) => return Err(BadRequest("Please pick a target deck".to_string())),
+    };
+
+    let new_path = match (action, target) {
+        (RestructureAction::Rename, _) => {
+            let name = new_name.map(str::trim).unwrap_or_default();
+            if name.is_empty() || name.contains(PATH_SEPARATOR) {
+                return Err(BadRequest(format!(
+                    "Deck names must not be empty or contain \"{PATH_SEPARATOR}\""
+                )));
+            }
+            ensure_free_name(&tx, parent, name, node.id).await?;
+            tx.execute("UPDATE decks SET name = $2 WHERE id = $1", &[&node.id, &name])
+                .await?;
+            let new_path = recompute_paths(&tx, node.id).await?;
+            touch_subtree(&tx, node.id).await?;
+            log_change(&tx, &node, deck_hash, action, &new_path, None, user).await?;
+            new_path
+        }
+        (RestructureAction::Move, Some(target)) => {
+            let name: String = tx
+                .query_one("SELECT name FROM decks WHERE id = $1", &[&node.id])
+                .await?
+                .get(0);
+            ensure_free_name(&tx, target.id, &name, node.id).await?;
+            tx.execute(
+                "UPDATE decks SET parent = $2 WHERE id = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            let new_path = recompute_paths(&tx, node.id).await?;
+            touch_subtree(&tx, node.id).await?;
+            // The old parent lost notes, subscribers need to see that as well
+            touch_subtree(&tx, parent).await?;
+            log_change(&tx, &node, deck_hash, action, &new_path, None, user).await?;
+            new_path
+        }
+        (RestructureAction::Merge, Some(target)) => {
+            // Children keep their names, so they must not clash with the children of the target
+            let clash: Option<String> = tx
+                .query_opt(
+                    "SELECT c.name FROM decks c
+                     JOIN decks t ON t.parent = $2 AND t.name = c.name AND t.deleted_at IS NULL
+                     WHERE c.parent = $1 AND c.deleted_at IS NULL
+                     LIMIT 1",
+                    &[&node.id, &target.id],
+                )
+                .await?
+                .map(|row| row.get(0));
+            if let Some(name) = clash {
+                return Err(BadRequest(format!(
+                    "Both decks have a subdeck called \"{name}\". Merge or rename those first."
+                )));
+            }
+
+            tx.execute(
+                "UPDATE notes SET deck = $2 WHERE deck = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE note_move_suggestions SET target_deck = $2 WHERE target_deck = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE decks SET parent = $2 WHERE parent = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            // History, reports and subscribers follow the notes. Anything else that hangs off
+            // the merged deck (maintainer rows, stats, optional tags) goes through the same
+            // cleanup as a purged deck.
+            tx.execute(
+                "UPDATE commits SET deck = $2 WHERE deck = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE changelogs SET deck = $2 WHERE deck = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE content_reports SET deck_id = $2 WHERE deck_id = $1",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            tx.execute(
+                "UPDATE subscriptions SET deck_id = $2
+                 WHERE deck_id = $1
+                   AND user_hash NOT IN (SELECT user_hash FROM subscriptions WHERE deck_id = $2)",
+                &[&node.id, &target.id],
+            )
+            .await?;
+            tx.query("SELECT delete_deck($1)", &[&deck_hash]).await?;
+
+            recompute_paths(&tx, target.id).await?;
+            touch_subtree(&tx, target.id).await?;
+            touch_subtree(&tx, parent).await?;
+            log_change(
+                &tx,
+                &node,
+                deck_hash,
+                action,
+                &target.full_path,
+                Some(target.id),
+                user,
+            )
+            .await?;
+            target.full_path
+        }
+        (_, None) => unreachable!("target is checked above"),
+    };
+
+    let root_hash: String = tx
+        .query_one("SELECT human_hash FROM decks WHERE id = $1", &[&node.root])
+        .await?
+        .get(0);
+    tx.commit().await?;
+
+    tracing::info!(
+        deck_hash,
+        action = action.as_str(),
+        old_path = %node.full_path,
+        new_path = %new_path,
+        "Restructured subdeck"
+    );
+    Ok(root_hash)
+}
+
+/// All live decks of the tree, for the restructure form
+pub async fn get_subdecks(
+    db_state: &Arc<database::AppState>,
+    root: DeckId,
+) -> Return<Vec<SubdeckInfo>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            r"
+            WITH RECURSIVE subtree AS (
+                SELECT id FROM decks WHERE id = $1
+                UNION ALL
+                SELECT d.id FROM decks d JOIN subtree s ON d.parent = s.id WHERE d.deleted_at IS NULL
+            )
+            SELECT d.human_hash, d.name, d.full_path, d.parent IS NULL
+            FROM decks d JOIN subtree s ON s.id = d.id
+            ORDER BY d.full_path",
+            &[&root],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| SubdeckInfo {
+            hash: row.get(0),
+            name: row.get(1),
+            full_path: row.get(2),
+            is_root: row.get(3),
+        })
+        .collect())
+}
+
+/// Changes to the tree below `root` after log entry `since_id`, oldest first
+pub async fn get_log(
+    db_state: &Arc<database::AppState>,
+    root: DeckId,
+    since_id: i64,
+) -> Return<Vec<RestructureLogEntry>> {
+    let client = database::client(db_state).await?;
+    let rows = client
+        .query(
+            "SELECT l.id, l.action, l.deck_hash, l.old_path, l.new_path, m.human_hash,
+                    TO_CHAR(l.created_at, 'YYYY-MM-DD\"T\"HH24:MI:SSOF')
+             FROM deck_restructure_log l
+             LEFT JOIN decks m ON m.id = l.merged_into
+             WHERE l.root_deck = $1 AND l.id > $2
+             ORDER BY l.id",
+            &[&root, &since_id],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| RestructureLogEntry {
+            id: row.get(0),
+            action: row.get(1),
+            deck_hash: row.get(2),
+            old_path: row.get(3),
+            new_path: row.get(4),
+            merged_into: row.get(5),
+            timestamp: row.get(6),
+        })
+        .collect())
+}
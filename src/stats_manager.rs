@@ -13,6 +13,36 @@ pub async fn update_stats(
     // Update the decks retention rates
     update_all_decks(db_state).await?;
 
+    // Update the note counts /decks sorts by
+    update_note_counts(db_state).await?;
+
+    Ok(())
+}
+
+/// Stores the number of reviewed notes of each top-level deck and its subdecks
+pub async fn update_note_counts(
+    db_state: &Arc<database::AppState>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let client = database::client(db_state).await?;
+    let query = "
+        WITH RECURSIVE tree AS (
+            SELECT id AS root, id FROM decks WHERE parent IS NULL AND deleted_at IS NULL
+            UNION ALL
+            SELECT tree.root, d.id
+            FROM tree JOIN decks d ON d.parent = tree.id
+        ),
+        counts AS (
+            SELECT tree.root, COUNT(n.id) AS note_count
+            FROM tree
+            LEFT JOIN notes n ON n.deck = tree.id AND NOT n.deleted AND n.reviewed = true
+            GROUP BY tree.root
+        )
+        UPDATE decks
+        SET note_count = counts.note_count
+        FROM counts
+        WHERE decks.id = counts.root AND decks.note_count IS DISTINCT FROM counts.note_count
+    ";
+    client.execute(query, &[]).await?;
     Ok(())
 }
 
@@ -237,23 +237,50 @@ pub struct EditDecksData {
     pub description: String,
     pub hash: String,
     pub is_private: bool,
+    #[serde(default)]
+    pub is_unlisted: bool,
     pub prevent_subdecks: bool,
     pub restrict_notetypes: bool,
     pub changelog: String,
+    #[serde(default)]
+    pub changelog_commits: Vec<i32>,
+    #[serde(default)]
+    pub language: String,
+    #[serde(default)]
+    pub categories: Vec<String>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct ChangelogInfo {
     pub id: i64,
+    /// Sanitized HTML
     pub message: String,
     pub timestamp: String,
+    pub edited: bool,
+    pub commits: Vec<i32>,
 }
 
 #[derive(Deserialize, Serialize)]
 pub struct UpdateMaintainer {
     pub deck: String,
     pub username: String,
-    pub action: i32, // 1 = add, 0 = remove
+    pub action: i32, // 1 = add, 0 = remove, 2 = change role
+    #[serde(default)]
+    pub role: Option<String>, // defaults to "maintainer"
+}
+
+#[derive(Serialize)]
+pub struct MaintainerInfo {
+    pub username: String,
+    pub role: String,
+}
+
+#[derive(Serialize)]
+pub struct MaintainedDeckInfo {
+    pub name: String,
+    pub hash: String,
+    pub role: String,
+    pub can_edit: bool,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -451,7 +478,7 @@ pub struct BulkNoteActionResponse {
 #[derive(Serialize)]
 pub struct NotificationItem {
     pub id: i32,
-    pub commit_id: i32,
+    pub commit_id: Option<i32>,
     pub deck_id: i64,
     pub deck_name: String,
     pub status: String,
@@ -511,3 +538,191 @@ pub struct SubscriptionPolicyPostRequest {
     pub base_deck_hash: String,
     pub policies: Vec<SubscriptionPolicyItem>,
 }
+
+// Admin: lift a login lockout
+#[derive(Deserialize)]
+pub struct ClearLoginBlockRequest {
+    pub kind: String, // "username" or "ip"
+    pub value: String,
+}
+
+#[derive(Deserialize)]
+pub struct AdminSearchQuery {
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AdminTransferDeckRequest {
+    pub deck_hash: String,
+    pub new_owner: String,
+}
+
+#[derive(Deserialize)]
+pub struct ReportContentRequest {
+    pub deck_hash: Option<String>,
+    pub note_id: Option<i64>,
+    pub category: String,
+    #[serde(default)]
+    pub comment: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResolveReportRequest {
+    pub action: String,
+}
+
+#[derive(Deserialize)]
+pub struct AdminDeckRequest {
+    pub deck_hash: String,
+}
+
+#[derive(Deserialize)]
+pub struct AdminFeatureDeckRequest {
+    pub deck_hash: String,
+    pub featured: bool,
+}
+
+#[derive(Deserialize)]
+pub struct AdminModeratorRequest {
+    pub moderator: bool,
+}
+
+#[derive(Deserialize)]
+pub struct DiscoverQuery {
+    pub category: Option<String>,
+    pub language: Option<String>,
+    pub sort: Option<String>,
+    pub page: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct DeckTransferRequest {
+    pub deck_hash: String,
+    pub recipient: String,
+}
+
+#[derive(Deserialize)]
+pub struct RestructureDeckRequest {
+    pub action: String,
+    pub deck_hash: String,
+    #[serde(default)]
+    pub new_name: Option<String>,
+    #[serde(default)]
+    pub target_hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RestructureLogQuery {
+    #[serde(default)]
+    pub since: i64,
+}
+
+#[derive(Deserialize)]
+pub struct CreateInviteRequest {
+    pub access: String,
+    #[serde(default)]
+    pub label: String,
+    /// Empty for links that never expire
+    #[serde(default)]
+    pub expires_in_days: String,
+    /// Empty for unlimited uses
+    #[serde(default)]
+    pub max_uses: String,
+}
+
+#[derive(Deserialize)]
+pub struct ChangelogPageQuery {
+    pub page: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub struct EditChangelogForm {
+    pub message: String,
+    /// Comma separated commit ids
+    #[serde(default)]
+    pub commits: String,
+}
+
+#[derive(Deserialize)]
+pub struct FeedQuery {
+    pub token: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct ImportNotesForm {
+    /// "map" shows the column mapping, "import" creates the commit
+    #[serde(default)]
+    pub step: String,
+    pub content: String,
+    /// "comma", "tab", "semicolon" or empty to detect it
+    #[serde(default)]
+    pub delimiter: String,
+    #[serde(default)]
+    pub has_header: Option<String>,
+    pub notetype: String,
+    pub deck: String,
+    #[serde(default)]
+    pub info: String,
+    /// The `column_<n>` selects of the mapping step. Every field is a string, numbers don't
+    /// survive `flatten` in form bodies
+    #[serde(flatten)]
+    pub columns: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct FindReplaceForm {
+    /// "preview" shows the diffs, "replace" creates the commit
+    #[serde(default)]
+    pub step: String,
+    pub deck: String,
+    pub find: String,
+    #[serde(default)]
+    pub replace: String,
+    #[serde(default)]
+    pub regex: Option<String>,
+    #[serde(default)]
+    pub case_sensitive: Option<String>,
+    /// Empty for every notetype
+    #[serde(default)]
+    pub notetype: String,
+    /// Empty for every field
+    #[serde(default)]
+    pub field: String,
+    #[serde(default)]
+    pub tag: String,
+    #[serde(default)]
+    pub info: String,
+}
+
+#[derive(Deserialize)]
+pub struct TagToolsForm {
+    /// "preview" shows the affected notes, "run" creates the commit
+    #[serde(default)]
+    pub step: String,
+    /// "rename", "merge", "delete" or "add"
+    pub operation: String,
+    pub deck: String,
+    #[serde(default)]
+    pub from: String,
+    #[serde(default)]
+    pub to: String,
+    /// Empty for every notetype
+    #[serde(default)]
+    pub notetype: String,
+    #[serde(default)]
+    pub tag: String,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub apply: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NoteExportQuery {
+    /// "csv" (default) or "jsonl"
+    pub format: Option<String>,
+    #[serde(default)]
+    pub pending: bool,
+    #[serde(default)]
+    pub inherited: bool,
+}
@@ -7,6 +7,7 @@ use crate::error::Error::{
 use crate::error::NoteNotFoundContext;
 use crate::media_reference_manager;
 use crate::note_history::{self, EventType};
+use crate::permissions::{self, Capability};
 use crate::user::User;
 use crate::{database, note_manager, Return};
 use sentry::Level;
@@ -74,61 +75,6 @@ async fn derive_commit_id(
     Ok(None)
 }
 
-pub async fn is_authorized(
-    db_state: &Arc<database::AppState>,
-    user: &User,
-    deck: i64,
-) -> Return<bool> {
-    let client = database::client(db_state).await?;
-    let rows = client
-        .query(
-            "SELECT 1 FROM decks WHERE (owner = $1 AND id = $3) OR $2 LIMIT 1",
-            &[&user.id(), &user.is_admin, &deck],
-        )
-        .await?;
-    let access = !rows.is_empty();
-
-    // Check if it's a maintainer
-    if !access {
-        // Get all parent decks including the current one
-        let query = r"
-            WITH RECURSIVE parent_decks AS (
-                SELECT id, parent
-                FROM decks
-                WHERE id = $1
-                UNION ALL
-                SELECT decks.id, decks.parent
-                FROM decks
-                JOIN parent_decks ON decks.id = parent_decks.parent
-            )
-            SELECT id
-            FROM parent_decks
-        ";
-        let parent_decks = client.query(query, &[&deck]).await?;
-        if parent_decks.is_empty() {
-            return Ok(false);
-        }
-        // Check if the user is a maintainer for any of the parent decks
-        for row in parent_decks {
-            let parent_deck_id: i64 = row.get(0);
-            let rows = client
-                .query(
-                    "SELECT 1 FROM maintainers WHERE user_id = $1 AND deck = $2 LIMIT 1",
-                    &[&user.id(), &parent_deck_id],
-                )
-                .await?;
-            if !rows.is_empty() {
-                // User is a maintainer for this deck or one of its parents
-                return Ok(true);
-            }
-        }
-        // User is not a maintainer for any of the decks in the hierarchy
-        return Ok(false);
-    }
-
-    Ok(access)
-}
-
 // Only used for unreviewed cards to prevent them from being added to the deck. Existing cards should use mark_note_deleted instead
 pub async fn delete_card(
     db_state: &Arc<database::AppState>,
@@ -149,7 +95,7 @@ pub async fn delete_card(
     let guid: String = q_guid[0].get(0);
     let deck_id: i64 = q_guid[0].get(1);
 
-    let access = is_authorized(db_state, &user, deck_id).await?;
+    let access = permissions::has_capability(db_state, &user, deck_id, Capability::Approve).await?;
     if !access {
         return Err(Unauthorized);
     }
@@ -184,7 +130,7 @@ pub async fn approve_card(
     let was_reviewed: bool = q_guid[0].get(1);
 
     if !bulk {
-        let access = is_authorized(db_state, user, deck_id).await?;
+        let access = permissions::has_capability(db_state, user, deck_id, Capability::Approve).await?;
         if !access {
             return Err(Unauthorized);
         }
@@ -992,19 +938,12 @@ pub struct FieldSuggestionResult {
     pub reviewed_content: String,
 }
 
-/// Create a tag suggestion (addition or removal) for a note within a commit.
-/// Returns the new tag ID on success.
-pub async fn create_tag_suggestion(
+/// Checks that the note exists and turns `content` into a valid Anki tag
+async fn tag_suggestion_content(
     tx: &tokio_postgres::Transaction<'_>,
     note_id: i64,
-    commit_id: i32,
     content: &str,
-    action: bool,
-    actor_user_id: i32,
-    client_ip: &str,
-) -> Return<i64> {
-    use crate::note_history::{self, EventType};
-
+) -> Return<String> {
     // Validate note exists
     let note_row = tx
         .query_opt(
@@ -1021,6 +960,21 @@ pub async fn create_tag_suggestion(
     if content.is_empty() {
         return Err(NoteNotFound(NoteNotFoundContext::TagUpdate));
     }
+    Ok(content)
+}
+
+/// Create a tag suggestion (addition or removal) for a note within a commit.
+/// Returns the new tag ID on success.
+pub async fn create_tag_suggestion(
+    tx: &tokio_postgres::Transaction<'_>,
+    note_id: i64,
+    commit_id: i32,
+    content: &str,
+    action: bool,
+    actor_user_id: i32,
+    client_ip: &str,
+) -> Return<i64> {
+    let content = tag_suggestion_content(tx, note_id, content).await?;
 
     // Check for duplicate: same note, same content, same action, unreviewed
     let existing = tx
@@ -1033,6 +987,65 @@ pub async fn create_tag_suggestion(
         return Ok(row.get(0)); // Return existing tag ID
     }
 
+    insert_tag_suggestion(
+        tx,
+        note_id,
+        commit_id,
+        &content,
+        action,
+        actor_user_id,
+        client_ip,
+    )
+    .await
+}
+
+/// Like `create_tag_suggestion`, but only a suggestion of the same commit counts as a duplicate.
+/// Bulk commits use this, they must neither count nor approve suggestions of other commits.
+pub async fn create_commit_tag_suggestion(
+    tx: &tokio_postgres::Transaction<'_>,
+    note_id: i64,
+    commit_id: i32,
+    content: &str,
+    action: bool,
+    actor_user_id: i32,
+    client_ip: &str,
+) -> Return<i64> {
+    let content = tag_suggestion_content(tx, note_id, content).await?;
+
+    let existing = tx
+        .query_opt(
+            "SELECT id FROM tags
+             WHERE note = $1 AND content = $2 AND action = $3 AND reviewed = false AND commit = $4",
+            &[&note_id, &content, &action, &commit_id],
+        )
+        .await?;
+    if let Some(row) = existing {
+        return Ok(row.get(0));
+    }
+
+    insert_tag_suggestion(
+        tx,
+        note_id,
+        commit_id,
+        &content,
+        action,
+        actor_user_id,
+        client_ip,
+    )
+    .await
+}
+
+async fn insert_tag_suggestion(
+    tx: &tokio_postgres::Transaction<'_>,
+    note_id: i64,
+    commit_id: i32,
+    content: &str,
+    action: bool,
+    actor_user_id: i32,
+    client_ip: &str,
+) -> Return<i64> {
+    use crate::note_history::{self, EventType};
+
     // Insert new tag suggestion
     let tag_id: i64 = tx
         .query_one(
@@ -1570,7 +1583,7 @@ pub async fn merge_by_note_ids(
     let deck_id: i64 = q_guid[0].get(0);
 
     // Verify user authorization
-    let access = is_authorized(db_state, user, deck_id).await?;
+    let access = permissions::has_capability(db_state, user, deck_id, Capability::Approve).await?;
     if !access {
         return Err(Unauthorized);
     }
@@ -1864,7 +1877,7 @@ pub async fn merge_by_commit(
     }
     let deck_id: i64 = q_guid[0].get(0);
 
-    let access = is_authorized(db_state, &user, deck_id).await?;
+    let access = permissions::has_capability(db_state, &user, deck_id, Capability::Approve).await?;
     if !access {
         return Err(Unauthorized);
     }
@@ -0,0 +1,370 @@
+//! Deck-wide tag maintenance: renaming a tag prefix, merging tags, deleting a tag and adding a
+//! tag to a filtered set of notes.
+//!
+//! Every run becomes one tag commit built from regular tag suggestions, so the changes show up in
+//! the review queue and the note history. The deck owner can apply the commit right away, which
+//! goes through the same approval path as the review page.
+
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::commit_manager::{
+    BULK_PREVIEW_NOTES, MAX_BULK_NOTES, RATIONALE_NEW_TAGS, RATIONALE_UPDATED_TAGS,
+};
+use crate::database;
+use crate::deck_manager::DECK_TREE;
+use crate::error::Error::BadRequest;
+use crate::permissions;
+use crate::suggestion_manager;
+use crate::user::User;
+use crate::{cleanser, DeckId, Return};
+
+pub enum TagOperation {
+    /// Renames `from` and its child tags, `from::child` becomes `to::child`
+    Rename {
+        from: String,
+        to: String,
+    },
+    Merge {
+        sources: Vec<String>,
+        into: String,
+    },
+    Delete {
+        tag: String,
+    },
+    Add {
+        tag: String,
+    },
+}
+
+/// Notes the operation looks at, empty filters match every note of the deck tree
+pub struct NoteFilter {
+    pub deck: DeckId,
+    pub notetype: Option<i64>,
+    /// Notes with this tag or one of its children
+    pub tag: Option<String>,
+    /// Text any field contains, ignoring case
+    pub text: Option<String>,
+}
+
+struct TagChange {
+    note_id: i64,
+    removed: Vec<String>,
+    added: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct PreviewNote {
+    pub note_id: i64,
+    pub removed: Vec<String>,
+    pub added: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct Preview {
+    pub note_count: usize,
+    pub removed: usize,
+    pub added: usize,
+    /// The first `BULK_PREVIEW_NOTES` notes
+    pub notes: Vec<PreviewNote>,
+}
+
+#[derive(Serialize)]
+pub struct TagSummary {
+    pub commit_id: i32,
+    pub notes: usize,
+    pub removed: usize,
+    pub added: usize,
+    /// The commit was approved right away
+    pub applied: bool,
+}
+
+/// Tags as the add-on stores them, Anki tags can't contain whitespace
+pub fn normalize_tag(value: &str) -> String {
+    cleanser::clean(value.trim())
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+fn has_tag_or_child(tags: &[String], tag: &str) -> bool {
+    tags.iter().any(|t| {
+        t == tag
+            || t.strip_prefix(tag)
+                .is_some_and(|rest| rest.starts_with("::"))
+    })
+}
+
+impl TagOperation {
+    /// `from` holds the old tag (the space separated tags for "merge"), `to` the new one
+    pub fn parse(kind: &str, from: &str, to: &str) -> Return<Self> {
+        Ok(match kind {
+            "rename" => Self::Rename {
+                from: normalize_tag(from),
+                to: normalize_tag(to),
+            },
+            "merge" => Self::Merge {
+                sources: from
+                    .split_whitespace()
+                    .map(normalize_tag)
+                    .filter(|tag| !tag.is_empty())
+                    .collect(),
+                into: normalize_tag(to),
+            },
+            "delete" => Self::Delete {
+                tag: normalize_tag(from),
+            },
+            "add" => Self::Add {
+                tag: normalize_tag(to),
+            },
+            _ => return Err(BadRequest("Choose what to do with the tags".to_string())),
+        })
+    }
+
+    fn validate(&self) -> Return<()> {
+        let valid = match self {
+            Self::Rename { from, to } => !from.is_empty() && !to.is_empty() && from != to,
+            Self::Merge { sources, into } => {
+                !into.is_empty() && sources.iter().any(|source| source != into)
+            }
+            Self::Delete { tag } | Self::Add { tag } => !tag.is_empty(),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(BadRequest(
+                "Enter the tags to change, the new tag has to differ from the old ones".to_string(),
+            ))
+        }
+    }
+
+    fn rationale(&self) -> i32 {
+        match self {
+            Self::Add { .. } => RATIONALE_NEW_TAGS,
+            _ => RATIONALE_UPDATED_TAGS,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Self::Rename { from, to } => format!("Renamed tag {from} to {to}"),
+            Self::Merge { sources, into } => {
+                format!("Merged tags {} into {into}", sources.join(", "))
+            }
+            Self::Delete { tag } => format!("Removed tag {tag}"),
+            Self::Add { tag } => format!("Added tag {tag}"),
+        }
+    }
+
+    /// Tags to remove from and add to a note with the given reviewed tags
+    fn apply(&self, tags: &[String]) -> (Vec<String>, Vec<String>) {
+        let mut removed = vec![];
+        let mut added: Vec<String> = vec![];
+        match self {
+            Self::Rename { from, to } => {
+                for tag in tags {
+                    let Some(rest) = tag.strip_prefix(from.as_str()) else {
+                        continue;
+                    };
+                    if !rest.is_empty() && !rest.starts_with("::") {
+                        continue;
+                    }
+                    let renamed = format!("{to}{rest}");
+                    removed.push(tag.clone());
+                    if !tags.contains(&renamed) && !added.contains(&renamed) {
+                        added.push(renamed);
+                    }
+                }
+            }
+            Self::Merge { sources, into } => {
+                removed.extend(
+                    tags.iter()
+                        .filter(|tag| *tag != into && sources.contains(*tag))
+                        .cloned(),
+                );
+                if !removed.is_empty() && !tags.contains(into) {
+                    added.push(into.clone());
+                }
+            }
+            Self::Delete { tag } => {
+                if tags.contains(tag) {
+                    removed.push(tag.clone());
+                }
+            }
+            Self::Add { tag } => {
+                if !tags.contains(tag) {
+                    added.push(tag.clone());
+                }
+            }
+        }
+        (removed, added)
+    }
+}
+
+async fn find_changes(
+    db_state: &Arc<database::AppState>,
+    operation: &TagOperation,
+    filter: &NoteFilter,
+) -> Return<Vec<TagChange>> {
+    operation.validate()?;
+    let filter_tag = filter.tag.as_deref().map(normalize_tag);
+
+    let rows = database::client(db_state)
+        .await?
+        .query(
+            &format!(
+                "{DECK_TREE}
+                SELECT n.id,
+                       COALESCE(array_agg(t.content) FILTER (WHERE t.content IS NOT NULL), '{{}}')
+                FROM notes n
+                JOIN tree ON tree.id = n.deck
+                LEFT JOIN tags t ON t.note = n.id AND t.reviewed = true AND t.action = true
+                WHERE NOT n.deleted AND n.reviewed = true
+                  AND ($2::bigint IS NULL OR n.notetype = $2)
+                  AND ($3::text IS NULL OR EXISTS (
+                        SELECT 1 FROM fields f WHERE f.note = n.id AND f.reviewed = true
+                          AND strpos(lower(f.content), lower($3)) > 0))
+                GROUP BY n.id
+                ORDER BY n.id"
+            ),
+            &[&filter.deck, &filter.notetype, &filter.text],
+        )
+        .await?;
+
+    let mut changes = vec![];
+    for row in &rows {
+        let tags: Vec<String> = row.get(1);
+        if let Some(tag) = &filter_tag {
+            if !has_tag_or_child(&tags, tag) {
+                continue;
+            }
+        }
+        let (removed, added) = operation.apply(&tags);
+        if removed.is_empty() && added.is_empty() {
+            continue;
+        }
+        changes.push(TagChange {
+            note_id: row.get(0),
+            removed,
+            added,
+        });
+    }
+    Ok(changes)
+}
+
+/// Dry run of the operation
+pub async fn preview(
+    db_state: &Arc<database::AppState>,
+    operation: &TagOperation,
+    filter: &NoteFilter,
+) -> Return<Preview> {
+    let changes = find_changes(db_state, operation, filter).await?;
+    Ok(Preview {
+        note_count: changes.len(),
+        removed: changes.iter().map(|note| note.removed.len()).sum(),
+        added: changes.iter().map(|note| note.added.len()).sum(),
+        notes: changes
+            .into_iter()
+            .take(BULK_PREVIEW_NOTES)
+            .map(|note| PreviewNote {
+                note_id: note.note_id,
+                removed: note.removed,
+                added: note.added,
+            })
+            .collect(),
+    })
+}
+
+/// Creates the tag commit, `apply` approves it in the same transaction
+pub async fn run(
+    db_state: &Arc<database::AppState>,
+    user: &User,
+    client_ip: &str,
+    root: DeckId,
+    operation: &TagOperation,
+    filter: &NoteFilter,
+    apply: bool,
+) -> Return<TagSummary> {
+    if apply && !permissions::owns_deck(db_state, user, root).await? {
+        return Err(BadRequest(
+            "Only the deck owner can apply changes directly".to_string(),
+        ));
+    }
+    let changes = find_changes(db_state, operation, filter).await?;
+    if changes.is_empty() {
+        return Err(BadRequest("No note would change".to_string()));
+    }
+    if changes.len() > MAX_BULK_NOTES {
+        return Err(BadRequest(format!(
+            "The operation changes {} notes, narrow it down to at most {MAX_BULK_NOTES}",
+            changes.len()
+        )));
+    }
+
+    let mut client = database::client(db_state).await?;
+    let tx = client.transaction().await?;
+    let commit_id: i32 = tx
+        .query_one(
+            "INSERT INTO commits (rationale, info, timestamp, deck, user_id)
+             VALUES ($1, $2, NOW(), $3, $4) RETURNING commit_id",
+            &[
+                &operation.rationale(),
+                &operation.describe(),
+                &filter.deck,
+                &user.id(),
+            ],
+        )
+        .await?
+        .get(0);
+
+    let mut summary = TagSummary {
+        commit_id,
+        notes: changes.len(),
+        removed: 0,
+        added: 0,
+        applied: apply,
+    };
+    let mut tag_ids = vec![];
+    for note in &changes {
+        let updates = note
+            .removed
+            .iter()
+            .map(|tag| (tag, false))
+            .chain(note.added.iter().map(|tag| (tag, true)));
+        for (tag, action) in updates {
+            tag_ids.push(
+                suggestion_manager::create_commit_tag_suggestion(
+                    &tx,
+                    note.note_id,
+                    commit_id,
+                    tag,
+                    action,
+                    user.id(),
+                    client_ip,
+                )
+                .await?,
+            );
+            if action {
+                summary.added += 1;
+            } else {
+                summary.removed += 1;
+            }
+        }
+    }
+
+    if apply {
+        for tag_id in tag_ids {
+            suggestion_manager::approve_tag_change_with_commit(
+                &tx,
+                tag_id,
+                true,
+                Some(commit_id),
+                user.id(),
+            )
+            .await?;
+        }
+    }
+    tx.commit().await?;
+    Ok(summary)
+}
@@ -0,0 +1,42 @@
+/**
+ * confirm_forms.js - Confirmation dialogs for destructive forms
+ * The question comes from a data-confirm attribute on the form or its submit button, so deck
+ * and user names never end up inside inline event handlers
+ */
+document.addEventListener('DOMContentLoaded', function() {
+    document.querySelectorAll('form').forEach(function(form) {
+        form.addEventListener('submit', function(event) {
+            if (form.dataset.confirmed === 'true') {
+                delete form.dataset.confirmed;
+                return;
+            }
+            var submitter = event.submitter;
+            var question = (submitter && submitter.dataset.confirm) || form.dataset.confirm;
+            if (!question) return;
+
+            event.preventDefault();
+            swal(
+                {
+                    title: "Are you sure?",
+                    text: question,
+                    type: "warning",
+                    showCancelButton: true,
+                    confirmButtonColor: "#DD6B55",
+                    confirmButtonText: "Yes",
+                    cancelButtonText: "Cancel",
+                },
+                function (confirmed) {
+                    if (!confirmed) return;
+                    form.dataset.confirmed = 'true';
+                    if (!form.requestSubmit) {
+                        form.submit();
+                    } else if (submitter) {
+                        form.requestSubmit(submitter);
+                    } else {
+                        form.requestSubmit();
+                    }
+                }
+            );
+        });
+    });
+});
@@ -0,0 +1,27 @@
+// Attach the CSRF token to every same-origin, state-changing fetch request.
+(function () {
+    const meta = document.querySelector('meta[name="csrf-token"]');
+    const token = meta ? meta.getAttribute('content') : '';
+    if (!token || !window.fetch) {
+        return;
+    }
+
+    const safeMethods = ['GET', 'HEAD', 'OPTIONS'];
+    const originalFetch = window.fetch.bind(window);
+
+    window.fetch = function (input, init) {
+        init = init || {};
+        const method = (init.method || (input instanceof Request ? input.method : 'GET')).toUpperCase();
+        const url = new URL(input instanceof Request ? input.url : input, window.location.href);
+
+        if (safeMethods.includes(method) || url.origin !== window.location.origin) {
+            return originalFetch(input, init);
+        }
+
+        const headers = new Headers(init.headers || (input instanceof Request ? input.headers : undefined));
+        if (!headers.has('X-CSRF-Token')) {
+            headers.set('X-CSRF-Token', token);
+        }
+        return originalFetch(input, Object.assign({}, init, { headers: headers }));
+    };
+})();
@@ -33,23 +33,6 @@ jQuery(document).ready(function() {
             var descHtml = JSON.parse(descDataElement.textContent);
             $('#desc-editor').trumbowyg('html', descHtml);
         }
-
-        $("#changelog-editor").trumbowyg({
-            btns: [
-                ['fullscreen']
-            ],
-            tagsToRemove: ['script', 'link'], // Basic security
-            removeformatPasted: true, // Clean pasted content
-            resetCss: true, // Helps in isolating editor styling
-            changeActiveDropdownIcon: true,
-            autogrow: true,
-        });
-
-        // Accessibility: add ARIA attributes to changelog editor
-        $("#changelog-editor").closest('.trumbowyg-box').find('.trumbowyg-editor')
-            .attr('role', 'textbox')
-            .attr('aria-multiline', 'true')
-            .attr('aria-label', 'Changelog entry');
     }
 });
 
@@ -64,29 +47,37 @@ document.addEventListener('DOMContentLoaded', function() {
         event.preventDefault();
 
         var description = $('#desc-editor').trumbowyg('html').replace(/<p><br><\/p>/g, '').trim();
-        var isPrivate = document.querySelector('input[name="private"]').checked;
+        var visibility = document.querySelector('select[name="visibility"]').value;
         var preventSubdecks = document.querySelector('input[name="prevent_subdecks"]').checked;
         var restrictNotetypes = document.querySelector('input[name="restrict_notetypes"]').checked;
-        var changelog = $('#changelog-editor').trumbowyg('html').trim();
-        changelog = changelog.replace(/<\/p>/g, '\n'); // Replace </p> with newline
-        changelog = changelog.replace(/<[^>]*>/g, ''); // Remove all other HTML tags
-        if (changelog === '\n') { // If the changelog is empty, set it to an empty string
-            changelog = '';
-        }
+        var languageSelect = document.querySelector('select[name="language"]');
+        var categories = Array.from(document.querySelectorAll('input[name="categories"]:checked'))
+            .map(function(input) { return input.value; });
+        var changelogEditor = document.getElementById('changelog-editor');
+        var commitsInput = document.getElementById('changelog-commits');
+        var changelog = changelogEditor.value.trim();
+        var changelogCommits = commitsInput.value.split(/[\s,]+/)
+            .map(function(id) { return parseInt(id.replace(/^#/, ''), 10); })
+            .filter(function(id) { return !isNaN(id); });
 
         var data = {
             description: description,
             hash: deckHash,
-            is_private: isPrivate,
+            is_private: visibility === 'private',
+            is_unlisted: visibility === 'unlisted',
             prevent_subdecks: preventSubdecks,
             restrict_notetypes: restrictNotetypes,
-            changelog: changelog
+            language: languageSelect ? languageSelect.value : '',
+            categories: categories,
+            changelog: changelog,
+            changelog_commits: changelogCommits
         };
 
         window.ApiService.apiCall('/EditDeck', 'POST', data);
 
         // Reset to empty string to prevent accidental double submits
-        $('#changelog-editor').trumbowyg('empty');
+        changelogEditor.value = '';
+        commitsInput.value = '';
         swal("Success!", "All changes have been saved!", "success");
     });
 });
@@ -103,7 +94,7 @@ document.addEventListener('DOMContentLoaded', function() {
         swal(
             {
                 title: "Are you sure you want to delete this deck?",
-                text: "This action cannot be undone.",
+                text: "You can restore it from Manage Decks within 30 days.",
                 type: "warning",
                 showCancelButton: true,
                 confirmButtonColor: "#DD6B55",
@@ -125,6 +116,37 @@ document.addEventListener('DOMContentLoaded', function() {
     };
 });
 
+// Changelog draft from approved commits
+document.addEventListener('DOMContentLoaded', function() {
+    var draftBtn = document.getElementById('changelog-draft-btn');
+    if (!draftBtn) return;
+
+    draftBtn.addEventListener('click', function() {
+        var editor = document.getElementById('changelog-editor');
+        var commitsInput = document.getElementById('changelog-commits');
+        if (editor.value.trim() !== '' && !confirm('Replace the changelog message with the draft?')) {
+            return;
+        }
+        draftBtn.disabled = true;
+        window.ApiService.apiCall('/api/changelog-draft/' + draftBtn.dataset.deckHash)
+            .then(function(draft) {
+                if (!draft || !draft.message) {
+                    swal("Nothing to summarize", "No commits were approved since the last changelog entry.", "info");
+                    return;
+                }
+                editor.value = draft.message;
+                commitsInput.value = draft.commits.join(', ');
+                editor.focus();
+            })
+            .catch(function() {
+                swal("Error", "Could not create a draft. Please try again.", "error");
+            })
+            .finally(function() {
+                draftBtn.disabled = false;
+            });
+    });
+});
+
 // Changelog deletion handling
 document.addEventListener('DOMContentLoaded', function() {
     document.querySelectorAll('.changelog-delete-btn').forEach(function(btn) {
@@ -0,0 +1,103 @@
+/**
+ * import_deck.js - Import deck page functionality
+ * Uploads the package with fetch so csrf.js can add its header, multipart bodies can't carry the form token
+ */
+document.addEventListener('DOMContentLoaded', function() {
+    var form = document.getElementById('import-deck-form');
+    var submit = document.getElementById('import-deck-submit');
+    var result = document.getElementById('import-deck-result');
+    if (!form || !result) return;
+
+    function plural(count, word) {
+        return count + ' ' + word + (count === 1 ? '' : 's');
+    }
+
+    // The report lists only the first names, the count covers all of them
+    function showNames(names, count) {
+        if (names.length === 0) return;
+        var list = document.createElement('pre');
+        list.className = 'small';
+        list.textContent = names.join('\n') + (count > names.length ? '\n…' : '');
+        result.appendChild(list);
+    }
+
+    function showReport(report) {
+        result.textContent = '';
+
+        var summary = document.createElement('p');
+        summary.textContent = 'Created ' + plural(report.decks, 'deck') + ' with ' +
+            plural(report.notes, 'note') + ' and ' + plural(report.notetypes, 'notetype') + '.';
+        result.appendChild(summary);
+
+        var skipped = [];
+        if (report.empty > 0) {
+            skipped.push(plural(report.empty, 'note') + ' without a first field were skipped.');
+        }
+        if (report.unknown_notetype > 0) {
+            skipped.push(plural(report.unknown_notetype, 'note') + ' with a notetype missing from the package were skipped.');
+        }
+        if (report.media_uploaded > 0) {
+            skipped.push('Uploaded ' + plural(report.media_uploaded, 'media file') + '.');
+        }
+        if (report.missing_media_count > 0) {
+            skipped.push('The notes reference ' + plural(report.missing_media_count, 'media file') +
+                ' that the package does not contain or that could not be stored. Upload them with the add-on:');
+        }
+        skipped.forEach(function(text) {
+            var line = document.createElement('p');
+            line.className = 'text-muted';
+            line.textContent = text;
+            result.appendChild(line);
+        });
+        showNames(report.missing_media, report.missing_media_count);
+
+        if (report.duplicate_count > 0) {
+            var duplicates = document.createElement('p');
+            duplicates.className = 'text-muted';
+            duplicates.textContent = 'Notes with ' + plural(report.duplicate_count, 'GUID') +
+                ' were skipped because the GUID is already on AnkiCollab or repeats an earlier note of the package:';
+            result.appendChild(duplicates);
+        }
+        showNames(report.duplicate_guids, report.duplicate_count);
+
+        var link = document.createElement('a');
+        link.href = '/EditDeck/' + encodeURIComponent(report.deck_hash);
+        link.className = 'btn mb-1 btn-rounded btn-outline-primary';
+        link.textContent = 'Open deck settings';
+        result.appendChild(link);
+    }
+
+    form.addEventListener('submit', function(event) {
+        event.preventDefault();
+        submit.disabled = true;
+        result.textContent = 'Importing, this can take a while for large packages…';
+
+        fetch(form.action, {
+            method: 'POST',
+            body: new FormData(form),
+        })
+        .then(function(response) {
+            if (!response.ok) {
+                throw new Error('HTTP ' + response.status);
+            }
+            return response.json();
+        })
+        .then(function(report) {
+            if (report.error) {
+                result.textContent = '';
+                swal('Import failed', report.error, 'error');
+                return;
+            }
+            form.reset();
+            showReport(report);
+        })
+        .catch(function(error) {
+            console.error(error);
+            result.textContent = '';
+            swal('Error', 'The package could not be imported. Please try again.', 'error');
+        })
+        .finally(function() {
+            submit.disabled = false;
+        });
+    });
+});
@@ -0,0 +1,27 @@
+/**
+ * import_notes.js - Import notes page functionality
+ * Reads the chosen CSV/TSV file into the content textarea, the server never receives the file itself
+ */
+document.addEventListener('DOMContentLoaded', function() {
+    var fileInput = document.getElementById('import-file');
+    var content = document.getElementById('import-content');
+    var delimiter = document.getElementById('import-delimiter');
+    if (!fileInput || !content) return;
+
+    fileInput.addEventListener('change', function() {
+        var file = fileInput.files[0];
+        if (!file) return;
+
+        var reader = new FileReader();
+        reader.onload = function() {
+            content.value = reader.result;
+            if (delimiter && /\.tsv$/i.test(file.name)) {
+                delimiter.value = 'tab';
+            }
+        };
+        reader.onerror = function() {
+            swal('Error', 'The file could not be read.', 'error');
+        };
+        reader.readAsText(file);
+    });
+});
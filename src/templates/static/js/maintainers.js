@@ -52,13 +52,28 @@ $(document).ready(function () {
     
     var ACTION_ADD = 1;
     var ACTION_REMOVE = 0;
+    var ACTION_CHANGE_ROLE = 2;
 
-    function sendData(action, username) {
+    function roleSelect(username, role) {
+        var select = $('#new-maintainer-role').clone()
+            .removeAttr('id')
+            .removeClass('mb-2')
+            .addClass('form-control-sm maintainer-role')
+            .attr('data-username', username)
+            .attr('aria-label', 'Role of ' + username);
+        select.val(role);
+        return select;
+    }
+
+    function sendData(action, username, role) {
         var data = {
             deck: deckHash,
             username: username,
             action: action,
         };
+        if (role) {
+            data.role = role;
+        }
 
         fetch("/Maintainers", {
             method: "POST",
@@ -70,16 +85,17 @@ $(document).ready(function () {
         .then(function(response) { return response.text(); })
         .then(function(text) {
             if (text === 'added') {
+                var label = $('<label>')
+                    .append($('<span>').text(username))
+                    .append($('<a href="#" class="ti-close" role="button"></a>').attr('aria-label', 'Remove maintainer ' + username));
                 $('.tdl-content2 ul').append(
-                    '<li>' +
-                    '<label>' +
-                    '<span>' + username + '</span>' +
-                    '<a href="#" class="ti-close"></a>' +
-                    '</label>' +
-                    '</li>'
+                    $('<li>').append(label).append(roleSelect(username, role))
                 );
                 toast_success('Maintainer added');
             }
+            else if(text === 'updated') {
+                toast_success('Role updated');
+            }
             else if(text === 'removed') {
                 toast_success('Maintainer removed');
             }
@@ -98,9 +114,13 @@ $(document).ready(function () {
     }
 
     function addMaintainer(username) {
-        sendData(ACTION_ADD, username);
+        sendData(ACTION_ADD, username, $('#new-maintainer-role').val());
     }
 
+    $(document).on("change", ".maintainer-role", function () {
+        sendData(ACTION_CHANGE_ROLE, $(this).attr('data-username'), $(this).val());
+    });
+
     // addMaintainer
     $('.tdl-new2').on('keypress', function (e) {
         var code = (e.keyCode ? e.keyCode : e.which);
@@ -1,15 +1,24 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+    },
     Argon2,
 };
 
+use axum::extract::Request;
 use axum::http::request::Parts;
+use axum::http::{header, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
 
 use axum::extract::{FromRequestParts, OptionalFromRequestParts};
 use axum_extra::extract::cookie::CookieJar;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 
 use cookie::{Cookie as CookieBuilder, SameSite};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::{decode, decode_header, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
 use sha2::{Sha256, Digest};
 use std::net::IpAddr;
@@ -18,9 +27,17 @@ use time::{Duration, OffsetDateTime};
 use tokio_postgres::Client;
 
 use crate::error::AuthError;
+use crate::keyring::Keyring;
+use crate::login_guard;
 
 const AUTH_COOKIE_NAME: &str = "__Host-ankicollabsession";
+const REFRESH_COOKIE_NAME: &str = "__Host-ankicollabrefresh";
 const COOKIE_MAX_AGE: i64 = 60 * 60 * 24 * 7; // 7 days in seconds
+/// Lifetime of the access JWT. The refresh token renews it transparently.
+const ACCESS_TOKEN_MINUTES: i64 = 15;
+/// A rotated refresh token presented again within this window is assumed to come from
+/// parallel requests of the same browser, not from a thief.
+const REFRESH_REUSE_GRACE_SECS: i32 = 30;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct User {
@@ -65,16 +82,16 @@ impl Clone for Credentials {
 
 pub struct Auth {
     db: Arc<Client>,
-    jwt_secret: String,
+    keys: Keyring,
     cookie_secure: bool, // Should be true in production
 }
 
 impl Auth {
     #[must_use]
-    pub const fn new(db: Arc<Client>, jwt_secret: String, cookie_secure: bool) -> Self {
+    pub const fn new(db: Arc<Client>, keys: Keyring, cookie_secure: bool) -> Self {
         Self {
             db,
-            jwt_secret,
+            keys,
             cookie_secure,
         }
     }
@@ -89,7 +106,7 @@ impl Auth {
             .query_one(
                 "SELECT id, username, is_admin
                  FROM users
-                 WHERE id = $1 AND deleted_at IS NULL",
+                 WHERE id = $1 AND deleted_at IS NULL AND suspended_at IS NULL",
                 &[&user_id],
             )
             .await?;
@@ -101,38 +118,17 @@ impl Auth {
     }
 
     pub async fn signup(&self, creds: Credentials, ip: IpAddr) -> Result<User, AuthError> {
-        // Normalize username to lowercase for case-insensitive comparison
-        let normalized_username = creds.username.trim().to_lowercase();
+        let normalized_username = normalize_username(&creds.username)?;
 
-        if normalized_username.is_empty() {
-            return Err(AuthError::InvalidCredentials);
-        }
-
-        if !normalized_username
-            .chars()
-            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
-            || normalized_username.len() > 30
-        {
-            return Err(AuthError::InvalidCredentials);
-        }
-
-        // Check if username already exists (case insensitive)
-        let exists = self
-            .db
-            .query_one(
-                "SELECT EXISTS(SELECT 1 FROM users WHERE LOWER(username) = $1)",
-                &[&normalized_username],
-            )
-            .await?
-            .get::<_, bool>(0);
-
-        if exists {
+        if self.is_username_taken(&normalized_username, None).await? {
             return Err(AuthError::UsernameAlreadyExists);
         }
 
         // Validate password strength
         self.validate_password(&creds.password)?;
 
+        login_guard::check_signup_allowed(&self.db, ip).await?;
+
         // Hash password
         let salt = SaltString::generate(&mut OsRng);
         let argon2 = Argon2::default();
@@ -158,6 +154,27 @@ impl Auth {
         })
     }
 
+    /// Taken by an account (deleted ones included) or still reserved after a rename.
+    /// `exempt_user` may reclaim names reserved for them.
+    async fn is_username_taken(
+        &self,
+        username: &str,
+        exempt_user: Option<i32>,
+    ) -> Result<bool, AuthError> {
+        let taken = self
+            .db
+            .query_one(
+                "SELECT EXISTS(SELECT 1 FROM users WHERE LOWER(username) = $1)
+                     OR EXISTS(SELECT 1 FROM reserved_usernames
+                               WHERE username = $1 AND reserved_until > NOW()
+                                 AND user_id IS DISTINCT FROM $2)",
+                &[&username, &exempt_user],
+            )
+            .await?
+            .get::<_, bool>(0);
+        Ok(taken)
+    }
+
     const fn validate_password(&self, password: &str) -> Result<(), AuthError> {
         // Check password length
         if password.len() < 8 {
@@ -175,19 +192,27 @@ impl Auth {
         Ok(())
     }
 
-    pub async fn login(&self, creds: Credentials, ip: IpAddr) -> Result<String, AuthError> {
+    /// Returns the `Set-Cookie` values for the access and the refresh token
+    pub async fn login(&self, creds: Credentials, ip: IpAddr) -> Result<Vec<String>, AuthError> {
         let normalized_username = creds.username.to_lowercase();
+
+        // Refuse early while the username or IP is locked out, before touching argon2
+        login_guard::check_login_allowed(&self.db, &normalized_username, ip).await?;
+
         // Find user (exclude soft-deleted accounts)
-        let row = self
+        let Some(row) = self
             .db
             .query_opt(
-                "SELECT id, password 
+                "SELECT id, password, suspended_at IS NOT NULL
                  FROM users 
                  WHERE username = $1 AND deleted_at IS NULL",
                 &[&normalized_username],
             )
             .await?
-            .ok_or(AuthError::InvalidCredentials)?;
+        else {
+            login_guard::record_login_attempt(&self.db, &normalized_username, ip, false).await;
+            return Err(AuthError::InvalidCredentials);
+        };
 
         let user_id: i32 = row.get(0);
         let password_hash: String = row.get(1);
@@ -199,22 +224,21 @@ impl Auth {
             .verify_password(creds.password.as_bytes(), &parsed_hash)
             .is_err()
         {
+            login_guard::record_login_attempt(&self.db, &normalized_username, ip, false).await;
             return Err(AuthError::InvalidCredentials);
         }
 
-        // Generate JWT
-        let now = OffsetDateTime::now_utc();
-        let claims = Claims {
-            sub: user_id,
-            iat: now.unix_timestamp(),
-            exp: (now + Duration::days(7)).unix_timestamp(),
-        };
+        login_guard::record_login_attempt(&self.db, &normalized_username, ip, true).await;
 
-        let token = encode(
-            &Header::default(),
-            &claims,
-            &EncodingKey::from_secret(self.jwt_secret.as_bytes()),
-        )?;
+        // Only tell the owner of the correct password about the suspension
+        let suspended: bool = row.get(2);
+        if suspended {
+            return Err(AuthError::AccountSuspended);
+        }
+
+        let persistent = creds.cookie.unwrap_or_default() == "on";
+        let access_token = self.access_token(user_id)?;
+        let refresh_token = self.start_session(user_id, persistent).await?;
 
         // Insert login log (best-effort)
         let _ = self
@@ -225,31 +249,57 @@ impl Auth {
             )
             .await;
 
-        if creds.cookie.unwrap_or_default() == "on" {
-            let cookie = CookieBuilder::build((AUTH_COOKIE_NAME, token))
-                .path("/")
-                .secure(self.cookie_secure)
-                .http_only(true)
-                .same_site(SameSite::Lax)
-                .max_age(time::Duration::new(COOKIE_MAX_AGE, 0))
-                .to_string();
-
-            Ok(cookie)
-        } else {
-            let cookie = CookieBuilder::build((AUTH_COOKIE_NAME, token))
-                .path("/")
-                .secure(self.cookie_secure)
-                .http_only(true)
-                .same_site(SameSite::Lax)
-                .to_string();
-
-            Ok(cookie)
+        Ok(vec![
+            self.access_cookie(access_token),
+            self.refresh_cookie(refresh_token, persistent),
+        ])
+    }
+
+    fn access_token(&self, user_id: i32) -> Result<String, AuthError> {
+        let now = OffsetDateTime::now_utc();
+        let claims = Claims {
+            sub: user_id,
+            iat: now.unix_timestamp(),
+            exp: (now + Duration::minutes(ACCESS_TOKEN_MINUTES)).unix_timestamp(),
+        };
+
+        let key = self.keys.active();
+        let header = Header {
+            kid: Some(key.kid.clone()),
+            ..Header::default()
+        };
+
+        Ok(encode(
+            &header,
+            &claims,
+            &EncodingKey::from_secret(key.secret()),
+        )?)
+    }
+
+    fn access_cookie(&self, token: String) -> String {
+        // Session cookie, the JWT inside expires long before the browser session does
+        CookieBuilder::build((AUTH_COOKIE_NAME, token))
+            .path("/")
+            .secure(self.cookie_secure)
+            .http_only(true)
+            .same_site(SameSite::Lax)
+            .to_string()
+    }
+
+    fn refresh_cookie(&self, token: String, persistent: bool) -> String {
+        let mut cookie = CookieBuilder::build((REFRESH_COOKIE_NAME, token))
+            .path("/")
+            .secure(self.cookie_secure)
+            .http_only(true)
+            .same_site(SameSite::Lax);
+        if persistent {
+            cookie = cookie.max_age(time::Duration::new(COOKIE_MAX_AGE, 0));
         }
+        cookie.to_string()
     }
 
-    pub async fn logout(&self) -> String {
-        // Create expired cookie to clear the session
-        CookieBuilder::build((AUTH_COOKIE_NAME, ""))
+    fn expired_cookie(&self, name: &'static str) -> String {
+        CookieBuilder::build((name, ""))
             .expires(time::OffsetDateTime::now_utc() - time::Duration::days(1))
             .path("/")
             .secure(self.cookie_secure)
@@ -258,10 +308,187 @@ impl Auth {
             .to_string()
     }
 
+    /// Creates the first refresh token of a new token family and returns it.
+    /// The whole family expires `COOKIE_MAX_AGE` after login, rotation does not extend it.
+    async fn start_session(&self, user_id: i32, persistent: bool) -> Result<String, AuthError> {
+        let refresh_token = random_token();
+
+        // Housekeeping, nobody can use these anymore
+        let _ = self
+            .db
+            .execute(
+                "DELETE FROM refresh_tokens WHERE user_id = $1 AND expires_at < NOW()",
+                &[&user_id],
+            )
+            .await;
+
+        self.db
+            .execute(
+                "INSERT INTO refresh_tokens (user_id, family_id, token_hash, persistent, expires_at)
+                 VALUES ($1, $2, $3, $4, NOW() + $5::BIGINT * INTERVAL '1 second')",
+                &[
+                    &user_id,
+                    &random_token(),
+                    &token_hash(&refresh_token),
+                    &persistent,
+                    &COOKIE_MAX_AGE,
+                ],
+            )
+            .await?;
+
+        Ok(refresh_token)
+    }
+
+    /// Trades a refresh token for a new access token and a rotated refresh token.
+    ///
+    /// Every refresh token is single use. Presenting an already used token again means it
+    /// was copied, so the whole family is revoked and both parties have to log in again.
+    pub async fn refresh_session(&self, refresh_token: &str) -> Result<RefreshedSession, AuthError> {
+        let presented_hash = token_hash(refresh_token);
+        let rotated_token = random_token();
+
+        let rotated = self
+            .db
+            .query_opt(
+                "WITH used AS (
+                     UPDATE refresh_tokens SET used_at = NOW()
+                     WHERE token_hash = $1 AND used_at IS NULL AND revoked_at IS NULL
+                       AND expires_at > NOW()
+                     RETURNING user_id, family_id, persistent, expires_at
+                 )
+                 INSERT INTO refresh_tokens (user_id, family_id, token_hash, persistent, expires_at)
+                 SELECT user_id, family_id, $2, persistent, expires_at FROM used
+                 RETURNING user_id, persistent",
+                &[&presented_hash, &token_hash(&rotated_token)],
+            )
+            .await?;
+
+        if let Some(row) = rotated {
+            let user_id: i32 = row.get(0);
+            let persistent: bool = row.get(1);
+            let access_token = self.access_token(user_id)?;
+            return Ok(RefreshedSession {
+                cookies: vec![
+                    self.access_cookie(access_token.clone()),
+                    self.refresh_cookie(rotated_token, persistent),
+                ],
+                access_token,
+            });
+        }
+
+        let row = self
+            .db
+            .query_opt(
+                "SELECT user_id, family_id,
+                        revoked_at IS NOT NULL OR expires_at <= NOW(),
+                        used_at > NOW() - $2::INT * INTERVAL '1 second'
+                 FROM refresh_tokens WHERE token_hash = $1",
+                &[&presented_hash, &REFRESH_REUSE_GRACE_SECS],
+            )
+            .await?
+            .ok_or(AuthError::InvalidToken)?;
+        let user_id: i32 = row.get(0);
+        let family_id: String = row.get(1);
+        let dead: bool = row.get(2);
+        let within_grace: Option<bool> = row.get(3);
+
+        if dead {
+            return Err(AuthError::InvalidToken);
+        }
+
+        if within_grace == Some(true) {
+            // A parallel request already rotated this token and its response carries the
+            // new refresh cookie. Only hand out an access token here.
+            let access_token = self.access_token(user_id)?;
+            return Ok(RefreshedSession {
+                cookies: vec![self.access_cookie(access_token.clone())],
+                access_token,
+            });
+        }
+
+        tracing::warn!(user_id, "Refresh token reuse detected, revoking token family");
+        self.db
+            .execute(
+                "UPDATE refresh_tokens SET revoked_at = NOW()
+                 WHERE family_id = $1 AND revoked_at IS NULL",
+                &[&family_id],
+            )
+            .await?;
+        Err(AuthError::InvalidToken)
+    }
+
+    /// Revokes the session of the presented refresh token and returns cookies clearing both tokens
+    pub async fn logout(&self, jar: &CookieJar) -> Vec<String> {
+        if let Some(refresh_cookie) = jar.get(REFRESH_COOKIE_NAME) {
+            if let Err(e) = self
+                .db
+                .execute(
+                    "UPDATE refresh_tokens SET revoked_at = NOW()
+                     WHERE revoked_at IS NULL AND family_id =
+                         (SELECT family_id FROM refresh_tokens WHERE token_hash = $1)",
+                    &[&token_hash(refresh_cookie.value())],
+                )
+                .await
+            {
+                tracing::warn!(error = %e, "Failed to revoke refresh token on logout");
+            }
+        }
+
+        vec![
+            self.expired_cookie(AUTH_COOKIE_NAME),
+            self.expired_cookie(REFRESH_COOKIE_NAME),
+        ]
+    }
+
+    /// Starts a fresh session for the browser that sent `jar` and returns its cookies, keeping
+    /// the "remember me" choice of its current refresh token. Used after `revoke_all_sessions`
+    /// so the user who changed their password stays logged in.
+    pub async fn reissue_session(
+        &self,
+        user_id: i32,
+        jar: &CookieJar,
+    ) -> Result<Vec<String>, AuthError> {
+        let persistent = match jar.get(REFRESH_COOKIE_NAME) {
+            Some(cookie) => self
+                .db
+                .query_opt(
+                    "SELECT persistent FROM refresh_tokens WHERE token_hash = $1 AND user_id = $2",
+                    &[&token_hash(cookie.value()), &user_id],
+                )
+                .await?
+                .is_some_and(|row| row.get(0)),
+            None => false,
+        };
+
+        let access_token = self.access_token(user_id)?;
+        let refresh_token = self.start_session(user_id, persistent).await?;
+        Ok(vec![
+            self.access_cookie(access_token),
+            self.refresh_cookie(refresh_token, persistent),
+        ])
+    }
+
+    /// Ends every browser session of the user, e.g. after a password change
+    pub async fn revoke_all_sessions(&self, user_id: i32) -> Result<(), AuthError> {
+        self.db
+            .execute(
+                "UPDATE refresh_tokens SET revoked_at = NOW()
+                 WHERE user_id = $1 AND revoked_at IS NULL",
+                &[&user_id],
+            )
+            .await?;
+        Ok(())
+    }
+
     pub fn verify_token(&self, token: &str) -> Result<i32, AuthError> {
+        let header = decode_header(token)?;
+        let key = self
+            .keys
+            .verification_key(header.kid.as_deref())
+            .ok_or(AuthError::InvalidToken)?;
         let token_data = decode::<Claims>(
             token,
-            &DecodingKey::from_secret(self.jwt_secret.as_bytes()),
+            &DecodingKey::from_secret(key.secret()),
             &Validation::default(),
         )?;
 
@@ -329,6 +556,91 @@ where
     }
 }
 
+pub struct RefreshedSession {
+    /// Fresh access JWT, so the current request can already use it
+    pub access_token: String,
+    /// `Set-Cookie` values for the response
+    pub cookies: Vec<String>,
+}
+
+pub(crate) fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Refresh tokens, invite links, feed and export tokens are only stored hashed, a database leak
+/// must not hand out access
+pub(crate) fn token_hash(token: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(token.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Renews an expired access token from the refresh cookie before the `User` extractor runs.
+///
+/// The `Cookie` header of the request is rewritten with the new access token so handlers
+/// see a logged in user right away, and the new cookies are added to the response.
+pub async fn session_refresh_middleware(mut request: Request, next: Next) -> Response {
+    // Static files never look at the user, rotating the refresh token there only races the page
+    if request.uri().path().starts_with("/static/") {
+        return next.run(request).await;
+    }
+    let Some(auth) = request.extensions().get::<Arc<Auth>>().cloned() else {
+        return next.run(request).await;
+    };
+
+    let jar = CookieJar::from_headers(request.headers());
+    let access_valid = jar
+        .get(AUTH_COOKIE_NAME)
+        .is_some_and(|c| auth.verify_token(c.value()).is_ok());
+    let refresh_token = jar.get(REFRESH_COOKIE_NAME).map(|c| c.value().to_string());
+
+    let (Some(refresh_token), false) = (refresh_token, access_valid) else {
+        return next.run(request).await;
+    };
+
+    let set_cookies = match auth.refresh_session(&refresh_token).await {
+        Ok(session) => {
+            let jar = jar.add(CookieBuilder::new(AUTH_COOKIE_NAME, session.access_token));
+            let cookie_header = jar
+                .iter()
+                .map(|c| format!("{}={}", c.name(), c.value()))
+                .collect::<Vec<_>>()
+                .join("; ");
+            if let Ok(value) = HeaderValue::from_str(&cookie_header) {
+                request.headers_mut().insert(header::COOKIE, value);
+            }
+            session.cookies
+        }
+        // The refresh token is dead, drop it so we don't try again on every request
+        Err(AuthError::InvalidToken) => vec![auth.expired_cookie(REFRESH_COOKIE_NAME)],
+        Err(e) => {
+            tracing::warn!(error = %e, "Failed to refresh session");
+            vec![]
+        }
+    };
+
+    let mut response = next.run(request).await;
+
+    // Login and logout set their own session cookies, those win
+    let handler_set_session = response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .any(|v| v.starts_with(AUTH_COOKIE_NAME) || v.starts_with(REFRESH_COOKIE_NAME));
+    if !handler_set_session {
+        for cookie in set_cookies {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                response.headers_mut().append(header::SET_COOKIE, value);
+            }
+        }
+    }
+
+    response
+}
+
 pub fn require_auth(user: Option<User>) -> Result<User, AuthError> {
     user.ok_or(AuthError::Redirect("/login".to_string()))
 }
@@ -340,6 +652,33 @@ pub struct ChangePasswordRequest {
     pub confirm_password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ChangeUsernameRequest {
+    pub new_username: String,
+    pub current_password: String,
+}
+
+/// Minimum time between two renames of the same account
+const USERNAME_CHANGE_COOLDOWN_DAYS: i32 = 30;
+/// How long an old username stays blocked for everybody else
+const USERNAME_RESERVATION_DAYS: i32 = 180;
+
+/// Lowercases and validates a username for signup and renames
+fn normalize_username(username: &str) -> Result<String, AuthError> {
+    // Normalize username to lowercase for case-insensitive comparison
+    let normalized = username.trim().to_lowercase();
+
+    if normalized.is_empty()
+        || !normalized
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+        || normalized.len() > 30
+    {
+        return Err(AuthError::InvalidCredentials);
+    }
+    Ok(normalized)
+}
+
 impl Auth {
     pub async fn change_password(
         &self,
@@ -396,9 +735,92 @@ impl Auth {
             )
             .await?;
 
+        // And every browser session
+        self.revoke_all_sessions(user_id).await?;
+
         Ok(())
     }
 
+    /// Renames the account. Commits, note history and notifications reference the user id
+    /// and follow automatically, but the add-on keys `note_stats` and `subscriptions` by
+    /// `user_hash(username)`, so those rows are rehashed. The old name stays reserved for
+    /// `USERNAME_RESERVATION_DAYS` to prevent impersonation.
+    pub async fn change_username(
+        &self,
+        user_id: i32,
+        current_password: &str,
+        new_username: &str,
+    ) -> Result<String, AuthError> {
+        let new_username = normalize_username(new_username)?;
+
+        let row = self
+            .db
+            .query_opt(
+                "SELECT username, password,
+                        username_changed_at > NOW() - make_interval(days => $2)
+                 FROM users WHERE id = $1 AND deleted_at IS NULL",
+                &[&user_id, &USERNAME_CHANGE_COOLDOWN_DAYS],
+            )
+            .await?
+            .ok_or(AuthError::UserNotFound)?;
+        let old_username: String = row.get(0);
+        let password_hash: String = row.get(1);
+        let in_cooldown: Option<bool> = row.get(2);
+
+        let parsed_hash = PasswordHash::new(&password_hash)
+            .map_err(|e| AuthError::PasswordHash(e.to_string()))?;
+        if Argon2::default()
+            .verify_password(current_password.as_bytes(), &parsed_hash)
+            .is_err()
+        {
+            return Err(AuthError::InvalidCredentials);
+        }
+
+        if new_username == old_username {
+            return Ok(old_username);
+        }
+        if in_cooldown == Some(true) {
+            return Err(AuthError::UsernameChangeTooSoon);
+        }
+        if self.is_username_taken(&new_username, Some(user_id)).await? {
+            return Err(AuthError::UsernameAlreadyExists);
+        }
+
+        // Auth shares a single client, so instead of a transaction everything happens in
+        // one statement, which Postgres applies atomically.
+        self.db
+            .execute(
+                "WITH renamed AS (
+                     UPDATE users SET username = $2, username_changed_at = NOW()
+                     WHERE id = $1 RETURNING id
+                 ), reserved AS (
+                     INSERT INTO reserved_usernames (username, user_id, reserved_until)
+                     SELECT $3, $1, NOW() + make_interval(days => $6) FROM renamed
+                     ON CONFLICT (username) DO UPDATE
+                     SET user_id = EXCLUDED.user_id, reserved_until = EXCLUDED.reserved_until
+                 ), released AS (
+                     DELETE FROM reserved_usernames
+                     WHERE username = $2 AND EXISTS (SELECT 1 FROM renamed)
+                 ), stats AS (
+                     UPDATE note_stats SET user_hash = $5
+                     WHERE user_hash = $4 AND EXISTS (SELECT 1 FROM renamed)
+                 )
+                 UPDATE subscriptions SET user_hash = $5
+                 WHERE user_hash = $4 AND EXISTS (SELECT 1 FROM renamed)",
+                &[
+                    &user_id,
+                    &new_username,
+                    &old_username,
+                    &user_hash(&old_username),
+                    &user_hash(&new_username),
+                    &USERNAME_RESERVATION_DAYS,
+                ],
+            )
+            .await?;
+
+        Ok(new_username)
+    }
+
     /// Fast soft-delete: invalidates the password so the user can no longer log in,
     /// sets `deleted_at = NOW()`, and clears auth tokens.  Returns the username
     /// so the caller can hand it to the background purge task.
@@ -424,7 +846,7 @@ impl Auth {
             )
             .await?;
 
-        // Invalidate third-party auth tokens immediately
+        // Invalidate third-party auth tokens and browser sessions immediately
         let _ = self
             .db
             .execute(
@@ -432,9 +854,92 @@ impl Auth {
                 &[&user_id],
             )
             .await;
+        let _ = self.revoke_all_sessions(user_id).await;
 
         Ok(username)
     }
+
+    /// Locks the account out (moderation) and ends all its sessions and add-on tokens
+    pub async fn suspend_account(&self, user_id: i32) -> Result<(), AuthError> {
+        let updated = self
+            .db
+            .execute(
+                "UPDATE users SET suspended_at = NOW() WHERE id = $1 AND suspended_at IS NULL",
+                &[&user_id],
+            )
+            .await?;
+        if updated == 0 {
+            return Ok(());
+        }
+
+        self.db
+            .execute("DELETE FROM auth_tokens WHERE user_id = $1", &[&user_id])
+            .await?;
+        self.revoke_all_sessions(user_id).await
+    }
+
+    pub async fn unsuspend_account(&self, user_id: i32) -> Result<(), AuthError> {
+        self.db
+            .execute(
+                "UPDATE users SET suspended_at = NULL WHERE id = $1",
+                &[&user_id],
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Reverts a soft-delete as long as the background purge has not removed the row yet.
+    /// The old password is gone, so we set a random temporary one and return it.
+    pub async fn restore_account(&self, user_id: i32) -> Result<String, AuthError> {
+        let mut bytes = [0u8; 12];
+        OsRng.fill_bytes(&mut bytes);
+        let temporary_password = URL_SAFE_NO_PAD.encode(bytes);
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(temporary_password.as_bytes(), &salt)
+            .map_err(|e| AuthError::PasswordHash(e.to_string()))?
+            .to_string();
+
+        let restored = self
+            .db
+            .execute(
+                "UPDATE users SET password = $1, deleted_at = NULL WHERE id = $2 AND deleted_at IS NOT NULL",
+                &[&password_hash, &user_id],
+            )
+            .await?;
+        if restored == 0 {
+            return Err(AuthError::UserNotFound);
+        }
+
+        Ok(temporary_password)
+    }
+}
+
+/// SHA256 of the username, the key the add-on uses for `note_stats` and `subscriptions`
+#[must_use]
+pub fn user_hash(username: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(username.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Days a deleted account can still be restored before the periodic purge removes its data
+pub const ACCOUNT_RESTORE_WINDOW_DAYS: i32 = 30;
+
+/// Deleted accounts whose restore window has passed, as `(user_id, username)`
+pub async fn expired_deleted_accounts<C: std::ops::Deref<Target = tokio_postgres::Client>>(
+    db: &C,
+) -> Result<Vec<(i32, String)>, tokio_postgres::Error> {
+    let rows = db
+        .query(
+            "SELECT id, username FROM users
+             WHERE deleted_at IS NOT NULL
+               AND deleted_at < NOW() - make_interval(days => $1)",
+            &[&ACCOUNT_RESTORE_WINDOW_DAYS],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
 }
 
 /// Heavy account data cleanup that can safely run in a background task.
@@ -443,40 +948,35 @@ pub async fn purge_deleted_account_data<C: std::ops::Deref<Target = tokio_postgr
     db: &C,
     user_id: i32,
     username: &str,
-) {
-    // Calculate SHA256 hash of username (matching the user_hash format in note_stats)
-    let mut hasher = Sha256::new();
-    hasher.update(username.as_bytes());
-    let user_hash = format!("{:x}", hasher.finalize());
+) -> Result<(), tokio_postgres::Error> {
+    let user_hash = user_hash(username);
 
     // Delete user's statistics by user_hash
-    if let Err(e) = db
-        .execute(
-            "DELETE FROM note_stats WHERE user_hash = $1",
-            &[&user_hash],
-        )
-        .await
-    {
-        tracing::error!(user_id, error = %e, "Failed to purge note_stats for deleted account");
-    }
+    db.execute(
+        "DELETE FROM note_stats WHERE user_hash = $1",
+        &[&user_hash],
+    )
+    .await
+    .inspect_err(
+        |e| tracing::error!(user_id, error = %e, "Failed to purge note_stats for deleted account"),
+    )?;
 
     // Delete user's subscriptions by user_hash
-    if let Err(e) = db
-        .execute(
-            "DELETE FROM subscriptions WHERE user_hash = $1",
-            &[&user_hash],
-        )
-        .await
-    {
-        tracing::error!(user_id, error = %e, "Failed to purge subscriptions for deleted account");
-    }
+    db.execute(
+        "DELETE FROM subscriptions WHERE user_hash = $1",
+        &[&user_hash],
+    )
+    .await
+    .inspect_err(
+        |e| tracing::error!(user_id, error = %e, "Failed to purge subscriptions for deleted account"),
+    )?;
 
     // Finally remove the user row (cascading deletes handle the rest)
-    if let Err(e) = db
-        .execute("DELETE FROM users WHERE id = $1", &[&user_id])
+    db.execute("DELETE FROM users WHERE id = $1", &[&user_id])
         .await
-    {
-        tracing::error!(user_id, error = %e, "Failed to delete user row for deleted account");
-    }
+        .inspect_err(
+            |e| tracing::error!(user_id, error = %e, "Failed to delete user row for deleted account"),
+        )?;
 
+    Ok(())
 }